@@ -0,0 +1,68 @@
+//! Opening, searching and scrolling a file above the large-file threshold
+//!
+//! Run with `cargo bench --bench large_file`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use editor::large_file;
+use regex::Regex;
+use ropey::Rope;
+
+/// A log a bit bigger than the threshold, with a match on its last line only
+fn log_file() -> Vec<u8> {
+    let line = "2024-01-01T00:00:00Z INFO request handled in 12ms path=/api/items\n";
+    let lines = large_file::LARGE_FILE_SIZE as usize / line.len() + 1;
+    let mut log = line.repeat(lines);
+    log.push_str("2024-01-01T00:00:01Z ERROR needle\n");
+    log.into_bytes()
+}
+
+fn bench_large_file(c: &mut Criterion) {
+    let bytes = log_file();
+    let mut group = c.benchmark_group("large_file");
+    group.sample_size(10);
+
+    group.bench_function("load", |b| {
+        b.iter(|| large_file::rope_from_bytes(black_box(&bytes)))
+    });
+
+    let (rope, _) = large_file::rope_from_bytes(&bytes);
+    let regex = Regex::new("ERROR").unwrap();
+    group.bench_function("find_last_line", |b| {
+        b.iter(|| large_file::find_next(black_box(&rope), &regex, 0))
+    });
+    group.bench_function("find_whole_string", |b| {
+        b.iter(|| regex.find(&black_box(&rope).to_string()).map(|m| m.start()))
+    });
+
+    // What rendering a screen after `:goto` costs
+    let last_line = rope.len_lines() - 30;
+    group.bench_function("jump_to_last_screen", |b| {
+        b.iter(|| {
+            let start = rope.line_to_char(black_box(last_line));
+            let screen: usize = rope
+                .lines_at(last_line)
+                .take(30)
+                .map(|l| l.len_chars())
+                .sum();
+            start + screen
+        })
+    });
+    group.bench_function("skip_to_last_screen", |b| {
+        b.iter(|| {
+            rope.lines()
+                .skip(black_box(last_line))
+                .take(30)
+                .map(|l| l.len_chars())
+                .sum::<usize>()
+        })
+    });
+    group.finish();
+
+    // Rope::from_str of the whole text, the way files were opened before
+    c.bench_function("large_file/load_whole_string", |b| {
+        b.iter(|| Rope::from_str(&String::from_utf8_lossy(black_box(&bytes))))
+    });
+}
+
+criterion_group!(benches, bench_large_file);
+criterion_main!(benches);
@@ -6,6 +6,8 @@ pub enum ActionType {
     Insert,
     Delete,
     MoveCursor,
+    /// `old_text` at `cursor_position_byte` replaced by `text`
+    Replace,
 }
 
 #[derive(Clone, Debug)]
@@ -15,6 +17,8 @@ pub struct Action {
     pub widget_type: WidgetType,
     pub cursor_position_byte: CursorPositionByte,
     pub text: String,
+    /// the text replaced by a `Replace`
+    pub old_text: String,
     pub done: bool,
     pub started: bool,
 }
@@ -39,6 +43,7 @@ impl Default for Action {
             widget_type: WidgetType::None,
             cursor_position_byte: 0,
             text: String::new(),
+            old_text: String::new(),
             done: false,
             started: false,
         }
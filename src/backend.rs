@@ -0,0 +1,187 @@
+use std::{
+    io::{stdout, Write},
+    time::Duration,
+};
+
+use crossterm::{
+    cursor,
+    event::{self, Event},
+    queue,
+    style::{self, ContentStyle, StyledContent},
+    terminal,
+};
+
+/// Where the editor draws: the terminal, or a screen kept in memory by tests
+pub trait Backend {
+    fn clear(&mut self);
+    fn move_to(&mut self, x: u16, y: u16);
+    /// Prints `text` at the cursor, which moves past it
+    fn print(&mut self, text: &str, style: ContentStyle);
+    fn show_cursor(&mut self, visible: bool);
+    fn flush(&mut self);
+}
+
+/// Draws on the terminal with crossterm, nothing is shown before `flush`
+pub struct CrosstermBackend;
+
+impl Backend for CrosstermBackend {
+    fn clear(&mut self) {
+        queue!(stdout(), terminal::Clear(terminal::ClearType::All)).unwrap();
+    }
+
+    fn move_to(&mut self, x: u16, y: u16) {
+        queue!(stdout(), cursor::MoveTo(x, y)).unwrap();
+    }
+
+    fn print(&mut self, text: &str, style: ContentStyle) {
+        queue!(
+            stdout(),
+            style::PrintStyledContent(StyledContent::new(style, text))
+        )
+        .unwrap();
+    }
+
+    fn show_cursor(&mut self, visible: bool) {
+        if visible {
+            queue!(stdout(), cursor::Show).unwrap();
+        } else {
+            queue!(stdout(), cursor::Hide).unwrap();
+        }
+    }
+
+    fn flush(&mut self) {
+        stdout().flush().unwrap();
+    }
+}
+
+/// Where the events come from: the terminal, or a script
+pub trait EventSource {
+    /// The next event, None when none came within `timeout`
+    fn next_event(&mut self, timeout: Duration) -> Option<Event>;
+}
+
+/// Reads the keys, mouse and resizes of the terminal
+pub struct CrosstermEvents;
+
+impl EventSource for CrosstermEvents {
+    fn next_event(&mut self, timeout: Duration) -> Option<Event> {
+        if event::poll(timeout).unwrap() {
+            Some(event::read().unwrap())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+pub use self::test_backend::TestBackend;
+
+#[cfg(test)]
+mod test_backend {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crossterm::style::ContentStyle;
+
+    use super::Backend;
+
+    #[derive(Clone, Copy)]
+    struct Cell {
+        ch: char,
+        style: ContentStyle,
+    }
+
+    impl Default for Cell {
+        fn default() -> Self {
+            Self {
+                ch: ' ',
+                style: ContentStyle::default(),
+            }
+        }
+    }
+
+    struct Screen {
+        width: usize,
+        height: usize,
+        cells: Vec<Cell>,
+        cursor: (u16, u16),
+        cursor_visible: bool,
+    }
+
+    /// A screen in memory, clones draw on and read the same screen
+    ///
+    /// A char takes one cell, what goes past the right edge is dropped.
+    #[derive(Clone)]
+    pub struct TestBackend {
+        screen: Rc<RefCell<Screen>>,
+    }
+
+    impl TestBackend {
+        pub fn new(width: usize, height: usize) -> Self {
+            Self {
+                screen: Rc::new(RefCell::new(Screen {
+                    width,
+                    height,
+                    cells: vec![Cell::default(); width * height],
+                    cursor: (0, 0),
+                    cursor_visible: true,
+                })),
+            }
+        }
+
+        /// The rows of the screen, without trailing spaces
+        pub fn lines(&self) -> Vec<String> {
+            let screen = self.screen.borrow();
+            screen
+                .cells
+                .chunks(screen.width)
+                .map(|row| {
+                    let line: String = row.iter().map(|cell| cell.ch).collect();
+                    line.trim_end().to_string()
+                })
+                .collect()
+        }
+
+        pub fn cursor(&self) -> (u16, u16) {
+            self.screen.borrow().cursor
+        }
+
+        pub fn is_cursor_visible(&self) -> bool {
+            self.screen.borrow().cursor_visible
+        }
+
+        pub fn style_at(&self, x: usize, y: usize) -> ContentStyle {
+            let screen = self.screen.borrow();
+            screen.cells[y * screen.width + x].style
+        }
+    }
+
+    impl Backend for TestBackend {
+        fn clear(&mut self) {
+            let mut screen = self.screen.borrow_mut();
+            screen.cells.fill(Cell::default());
+        }
+
+        fn move_to(&mut self, x: u16, y: u16) {
+            self.screen.borrow_mut().cursor = (x, y);
+        }
+
+        fn print(&mut self, text: &str, style: ContentStyle) {
+            let mut screen = self.screen.borrow_mut();
+            let (mut x, y) = (screen.cursor.0 as usize, screen.cursor.1 as usize);
+            for ch in text.chars() {
+                if x < screen.width && y < screen.height {
+                    let width = screen.width;
+                    screen.cells[y * width + x] = Cell { ch, style };
+                }
+                x += 1;
+            }
+            screen.cursor.0 = x as u16;
+        }
+
+        fn show_cursor(&mut self, visible: bool) {
+            self.screen.borrow_mut().cursor_visible = visible;
+        }
+
+        fn flush(&mut self) {}
+    }
+}
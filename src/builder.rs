@@ -0,0 +1,444 @@
+use std::{
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use crossterm::{event::Event, style::Color, terminal};
+use ropey::Rope;
+
+use crate::{
+    backend::{Backend, CrosstermEvents, EventSource},
+    cli::FileArg,
+    command::registry::Command,
+    editor::TextEditor,
+    encoding::FileFormat,
+    file_io::{self, FileError},
+    highlight::Highlighter,
+    history, large_file,
+    terminal::{cleanup_terminal, setup_terminal},
+    widget::{
+        command_line::CommandLine,
+        line_number::LineNumber,
+        panel::Panel,
+        status_bar::StatusBar,
+        widget::{BorderStyle, ProcessEvent},
+    },
+};
+
+/// Size of the screen when it is not the terminal and none was given
+static DEFAULT_SIZE: (usize, usize) = (80, 24);
+
+/// Columns taken by the line numbers
+static LINE_NUMBER_WIDTH: usize = 8;
+
+/// How long to wait for an event before ticking again
+static TICK: Duration = Duration::from_millis(100);
+
+/// What to edit before the buffer is built
+enum Content {
+    /// a file read when building, a missing file is a new buffer
+    File(PathBuf),
+    /// text already read, with the file it is saved to, if any
+    Text {
+        text: Rope,
+        file_format: FileFormat,
+        save_path: PathBuf,
+        new_file: bool,
+    },
+}
+
+/// Sets up an editor the way the `editor` binary does, for programs embedding it
+///
+/// Without a backend the editor takes over the terminal while it runs:
+///
+/// ```no_run
+/// let editor = editor::EditorBuilder::new()
+///     .file(".git/COMMIT_EDITMSG")
+///     .theme("InspiredGitHub")
+///     .bind("ctrl+s", ":wq")
+///     .run()
+///     .unwrap();
+/// assert!(!editor.written);
+/// ```
+pub struct EditorBuilder {
+    content: Content,
+    name: Option<String>,
+    readonly: bool,
+    history_dir: Option<PathBuf>,
+    buffers: Vec<FileArg>,
+    theme: Option<String>,
+    bindings: Vec<(String, String)>,
+    commands: Vec<Command>,
+    command_lines: Vec<String>,
+    line_numbers: bool,
+    status_bar: bool,
+    widgets: Vec<Box<dyn ProcessEvent>>,
+    size: Option<(usize, usize)>,
+    highlighter: Option<Arc<Highlighter>>,
+    backend: Option<Box<dyn Backend>>,
+    events: Option<Box<dyn EventSource>>,
+}
+
+impl Default for EditorBuilder {
+    fn default() -> Self {
+        Self {
+            content: Content::Text {
+                text: Rope::new(),
+                file_format: FileFormat::default(),
+                save_path: PathBuf::new(),
+                new_file: false,
+            },
+            name: None,
+            readonly: false,
+            history_dir: history::history_dir(),
+            buffers: Vec::new(),
+            theme: None,
+            bindings: Vec::new(),
+            commands: Vec::new(),
+            command_lines: Vec::new(),
+            line_numbers: true,
+            status_bar: true,
+            widgets: Vec::new(),
+            size: None,
+            highlighter: None,
+            backend: None,
+            events: None,
+        }
+    }
+}
+
+impl EditorBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Edits `path`, read when the editor is built, it is created on save when
+    /// missing
+    pub fn file(mut self, path: impl AsRef<Path>) -> Self {
+        self.content = Content::File(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Edits `text`, saved to `save_path` in `file_format`, an empty path being no
+    /// file like text read from stdin
+    pub fn text(mut self, text: Rope, file_format: FileFormat, save_path: PathBuf) -> Self {
+        self.content = Content::Text {
+            text,
+            file_format,
+            save_path,
+            new_file: false,
+        };
+        self
+    }
+
+    /// The text is not saved yet, like a file that does not exist
+    pub fn new_file(mut self, new_file: bool) -> Self {
+        if let Content::Text { new_file: new, .. } = &mut self.content {
+            *new = new_file;
+        }
+        self
+    }
+
+    /// What the status bar calls the buffer, its path by default
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn readonly(mut self, readonly: bool) -> Self {
+        self.readonly = readonly;
+        self
+    }
+
+    /// The files `:buffer` switches to, the cursor goes to the position of the
+    /// one being edited
+    pub fn buffers(mut self, buffers: Vec<FileArg>) -> Self {
+        self.buffers = buffers;
+        self
+    }
+
+    /// Highlights with `theme`, see `:theme`
+    pub fn theme(mut self, theme: impl Into<String>) -> Self {
+        self.theme = Some(theme.into());
+        self
+    }
+
+    /// Binds `chord`, like `ctrl+s`, to a command line, over the default keys
+    pub fn bind(mut self, chord: &str, command_line: &str) -> Self {
+        self.bindings
+            .push((chord.to_string(), command_line.to_string()));
+        self
+    }
+
+    /// Adds a `:` command, replacing a builtin one of the same name
+    pub fn command(mut self, command: Command) -> Self {
+        self.commands.push(command);
+        self
+    }
+
+    /// Runs `command_line` once the editor started, like the lines of the config
+    pub fn run_command(mut self, command_line: &str) -> Self {
+        self.command_lines.push(command_line.to_string());
+        self
+    }
+
+    /// Keeps the histories of the command line in `dir` instead of the state
+    /// directory, none keeping them for the session only
+    pub fn history_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.history_dir = dir;
+        self
+    }
+
+    pub fn line_numbers(mut self, line_numbers: bool) -> Self {
+        self.line_numbers = line_numbers;
+        self
+    }
+
+    pub fn status_bar(mut self, status_bar: bool) -> Self {
+        self.status_bar = status_bar;
+        self
+    }
+
+    /// Adds a widget of the embedding program over the standard ones
+    pub fn widget(mut self, widget: Box<dyn ProcessEvent>) -> Self {
+        self.widgets.push(widget);
+        self
+    }
+
+    /// The size of the screen, the size of the terminal by default
+    pub fn size(mut self, width: usize, height: usize) -> Self {
+        self.size = Some((width, height));
+        self
+    }
+
+    /// Highlights with `highlighter`, e.g. with syntaxes of its own, instead of
+    /// the syntaxes and themes bundled with syntect
+    pub fn highlighter(mut self, highlighter: Arc<Highlighter>) -> Self {
+        self.highlighter = Some(highlighter);
+        self
+    }
+
+    /// Draws on `backend` instead of the terminal, which is then left alone
+    pub fn backend(mut self, backend: impl Backend + 'static) -> Self {
+        self.backend = Some(Box::new(backend));
+        self
+    }
+
+    /// Reads the events from `events` instead of the terminal
+    pub fn events(mut self, events: impl EventSource + 'static) -> Self {
+        self.events = Some(Box::new(events));
+        self
+    }
+
+    /// Builds the editor and draws its first frame on the backend
+    ///
+    /// The terminal is not set up, [`EditorBuilder::run`] does it. A screen
+    /// without room for a row and a column of text is an error.
+    pub fn build(self) -> Result<TextEditor, FileError> {
+        let (text, file_format, save_path, new_file) = match self.content {
+            Content::File(path) => match file_io::read_rope(&path) {
+                Ok((text, file_format)) => (text, file_format, path, false),
+                Err(FileError::NotFound(_)) => (Rope::new(), FileFormat::default(), path, true),
+                Err(e) => return Err(e),
+            },
+            Content::Text {
+                text,
+                file_format,
+                save_path,
+                new_file,
+            } => (text, file_format, save_path, new_file),
+        };
+        let (width, height) = match (self.size, &self.backend) {
+            (Some(size), _) => size,
+            (None, Some(_)) => DEFAULT_SIZE,
+            (None, None) => terminal::size()
+                .map(|(width, height)| (width as usize, height as usize))
+                .unwrap_or(DEFAULT_SIZE),
+        };
+        let line_number_width = if self.line_numbers {
+            LINE_NUMBER_WIDTH
+        } else {
+            0
+        };
+        // The command line takes the last row, the status bar the one above
+        let bars_height = 1 + usize::from(self.status_bar);
+        if width <= line_number_width || height <= bars_height {
+            let e = io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "a screen of {}x{} is too small, {}x{} at least",
+                    width,
+                    height,
+                    line_number_width + 1,
+                    bars_height + 1
+                ),
+            );
+            return Err(FileError::Other(save_path, e));
+        }
+        let mut editor = TextEditor::new(&save_path, width, height);
+        if let Some(backend) = self.backend {
+            editor.backend = backend;
+        }
+        if let Some(highlighter) = self.highlighter {
+            editor.highlighter = highlighter;
+        }
+        for command in self.commands {
+            editor.commands.register(command);
+        }
+        for (chord, command_line) in &self.bindings {
+            editor.keymap.bind(chord, command_line);
+        }
+        editor.written = new_file;
+        editor.file_format = file_format;
+        editor.large_file = large_file::is_large(&save_path);
+        editor.readonly = self.readonly;
+        editor.history_dir = self.history_dir;
+
+        let text_height = height - bars_height;
+        log::debug!(
+            "width: {}, height: {}, line_number_width: {}",
+            width,
+            height,
+            line_number_width
+        );
+        let mut main = Panel::new(
+            String::new(),
+            line_number_width,
+            0,
+            width - line_number_width,
+            text_height,
+            Color::White,
+            Color::Reset,
+            true,
+            true,
+            BorderStyle::None,
+        );
+        main.set_buffer(text);
+        // base16-ocean.dark,base16-eighties.dark,base16-mocha.dark,base16-ocean.light
+        // InspiredGitHub from here
+        // Solarized (dark) and Solarized (light)
+        main.set_theme(Some("base16-eighties.dark".to_string()));
+        main.set_z_idx(1);
+
+        if self.status_bar {
+            let name = self
+                .name
+                .unwrap_or_else(|| save_path.to_string_lossy().to_string());
+            let status_bar: Box<StatusBar> = StatusBar::new(
+                name,
+                0,
+                height - 2,
+                width,
+                1,
+                Color::Black,
+                Color::White,
+                false,
+                false,
+                BorderStyle::None,
+            );
+            editor.add_widget(status_bar);
+        }
+
+        if self.line_numbers {
+            let line_number = LineNumber::new(
+                String::new(),
+                0,
+                0,
+                line_number_width,
+                text_height,
+                Color::DarkGrey,
+                Color::Black,
+                false,
+                false,
+                BorderStyle::None,
+            );
+            editor.add_widget(line_number);
+        }
+
+        let mut command_line = CommandLine::new(
+            0,
+            String::new(),
+            0,
+            height - 1,
+            width,
+            1,
+            Color::White,
+            Color::Black,
+            false,
+            false,
+            BorderStyle::None,
+        );
+        command_line.load_histories(editor.history_dir.as_deref());
+        let pos = main.update_cursor_position_and_view();
+        editor.add_widget(command_line);
+        editor.focused_widget_id = editor.add_widget(main);
+        editor.detect_filetype(&save_path);
+        editor.diff_with_head(&save_path);
+        for widget in self.widgets {
+            editor.add_widget(widget);
+        }
+
+        editor.buffers = self
+            .buffers
+            .into_iter()
+            .filter(|file| !file.is_stdin())
+            .collect();
+        let position = editor
+            .buffers
+            .first()
+            .filter(|file| file.path == save_path)
+            .and_then(|file| Some((file.line?, file.column)));
+        let pos = match position {
+            Some((line, column)) => editor.goto_position(line, column).unwrap_or(pos),
+            None => pos,
+        };
+
+        editor.check_swap();
+        // The theme asked for wins over the config
+        for command_line in &self.command_lines {
+            editor.queue_command(command_line);
+        }
+        if let Some(theme) = &self.theme {
+            editor.queue_command(&format!(":theme {}", theme));
+        }
+        editor.event(&Event::FocusGained);
+        editor.render(pos, true);
+        Ok(editor)
+    }
+
+    /// Builds the editor and runs it until it quits, on the terminal unless a
+    /// backend was given
+    ///
+    /// Returns the editor as it quit, e.g. to know whether the buffer was saved.
+    pub fn run(mut self) -> Result<TextEditor, FileError> {
+        let on_terminal = self.backend.is_none();
+        let mut events = self
+            .events
+            .take()
+            .unwrap_or_else(|| Box::new(CrosstermEvents));
+        if on_terminal {
+            setup_terminal(true);
+        }
+        let mut editor = match self.build() {
+            Ok(editor) => editor,
+            Err(e) => {
+                if on_terminal {
+                    cleanup_terminal();
+                }
+                return Err(e);
+            }
+        };
+        while editor.running {
+            if let Some(event) = events.next_event(TICK) {
+                editor.event(&event);
+            }
+            editor.tick();
+        }
+        if on_terminal {
+            cleanup_terminal();
+        }
+        Ok(editor)
+    }
+}
@@ -0,0 +1,335 @@
+use std::{
+    env, fmt,
+    path::{Path, PathBuf},
+};
+
+pub static USAGE: &str = "\
+Usage: terminus [options] [+line] [file[:line[:column]]]...
+       terminus -d <old> <new>
+       command | terminus [options] -
+
+Options:
+  +<line>            put the cursor on <line> of the next file
+  -R, --readonly     refuse edits, :w <path> still saves a copy
+  -d, --diff         show the differences between two files
+  --theme <name>     highlight with <name>, see :theme
+  --config <file>    run the command lines of <file> at startup
+  --log-file <file>  write the log to <file>, also set by TERMINUS_LOG
+  --log-level <spec> keep records like `info,widget::panel=trace`, also set
+                     by TERMINUS_LOG_LEVEL, see :messages
+  -V, --version      print the version
+  -h, --help         print this help
+
+Text read from stdin with `-`, given before the files, is read-only.
+";
+
+/// A file to open, with where to put the cursor, lines and columns start at 1
+#[derive(PartialEq, Debug, Clone)]
+pub struct FileArg {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl FileArg {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            line: None,
+            column: None,
+        }
+    }
+
+    /// `file.rs:42:7` opens `file.rs` at line 42, column 7, unless a file has that
+    /// very name
+    fn parse(arg: &str) -> Self {
+        if arg == "-" || Path::new(arg).exists() {
+            return Self::new(PathBuf::from(arg));
+        }
+        let mut path = arg;
+        let mut numbers = Vec::new();
+        while numbers.len() < 2 {
+            match path.rsplit_once(':') {
+                Some((rest, number)) if !rest.is_empty() => match number.parse::<usize>() {
+                    Ok(number) => {
+                        numbers.push(number.max(1));
+                        path = rest;
+                    }
+                    Err(_) => break,
+                },
+                _ => break,
+            }
+        }
+        numbers.reverse();
+        Self {
+            path: PathBuf::from(path),
+            line: numbers.first().copied(),
+            column: numbers.get(1).copied(),
+        }
+    }
+
+    pub fn is_stdin(&self) -> bool {
+        self.path.as_os_str() == "-"
+    }
+}
+
+/// What the editor was asked to do
+#[derive(PartialEq, Debug, Default)]
+pub struct Options {
+    pub files: Vec<FileArg>,
+    pub readonly: bool,
+    /// shows the differences between the two files
+    pub diff: bool,
+    pub theme: Option<String>,
+    pub config: Option<PathBuf>,
+    pub log_file: Option<PathBuf>,
+    /// the levels of the log, see [`crate::logger::Filter`]
+    pub log_level: Option<String>,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Cli {
+    Edit(Options),
+    Help,
+    Version,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum CliError {
+    UnknownOption(String),
+    MissingValue(String),
+    InvalidLine(String),
+    /// `+line` after the last file
+    NoFileForLine(usize),
+    /// the diff mode compares exactly two files, not this many
+    DiffFiles(usize),
+    /// stdin is read once, for one buffer
+    StdinTwice,
+    /// stdin is the buffer shown first, the files come after it
+    StdinNotFirst,
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::UnknownOption(option) => write!(f, "Unknown option: {}", option),
+            CliError::MissingValue(option) => write!(f, "{} expects a value", option),
+            CliError::InvalidLine(arg) => write!(f, "Invalid line: {}", arg),
+            CliError::NoFileForLine(line) => write!(f, "+{} is not followed by a file", line),
+            CliError::DiffFiles(count) => write!(f, "--diff compares 2 files, not {}", count),
+            CliError::StdinTwice => write!(f, "- can only be given once"),
+            CliError::StdinNotFirst => write!(f, "- must come before the files"),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+/// Parses the arguments, without the name of the program
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, CliError> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    let mut line = None;
+    let mut only_files = false;
+    while let Some(arg) = args.next() {
+        if only_files || arg == "-" || !(arg.starts_with('-') || arg.starts_with('+')) {
+            let mut file = FileArg::parse(&arg);
+            if let Some(line) = line.take() {
+                file.line = Some(line);
+                file.column = None;
+            }
+            options.files.push(file);
+            continue;
+        }
+        match arg.as_str() {
+            "--" => only_files = true,
+            "-h" | "--help" => return Ok(Cli::Help),
+            "-V" | "--version" => return Ok(Cli::Version),
+            "-R" | "--readonly" => options.readonly = true,
+            "-d" | "--diff" => options.diff = true,
+            "--theme" => {
+                options.theme = Some(args.next().ok_or(CliError::MissingValue(arg))?);
+            }
+            "--config" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.config = Some(PathBuf::from(path));
+            }
+            "--log-file" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.log_file = Some(PathBuf::from(path));
+            }
+            "--log-level" => {
+                options.log_level = Some(args.next().ok_or(CliError::MissingValue(arg))?);
+            }
+            _ => match arg.strip_prefix('+') {
+                Some(number) => {
+                    let number = number
+                        .parse::<usize>()
+                        .map_err(|_| CliError::InvalidLine(arg.clone()))?;
+                    line = Some(number.max(1));
+                }
+                None => return Err(CliError::UnknownOption(arg)),
+            },
+        }
+    }
+    if let Some(line) = line {
+        return Err(CliError::NoFileForLine(line));
+    }
+    if options.files.iter().filter(|file| file.is_stdin()).count() > 1 {
+        return Err(CliError::StdinTwice);
+    }
+    if options.files.iter().skip(1).any(|file| file.is_stdin()) {
+        return Err(CliError::StdinNotFirst);
+    }
+    if options.diff && options.files.len() != 2 {
+        return Err(CliError::DiffFiles(options.files.len()));
+    }
+    Ok(Cli::Edit(options))
+}
+
+/// The file of command lines run at startup: `--config`, or `terminus/config`
+/// in the config directory
+pub fn config_path(options: &Options) -> Option<PathBuf> {
+    if let Some(config) = &options.config {
+        return Some(config.clone());
+    }
+    if let Ok(config) = env::var("XDG_CONFIG_HOME") {
+        if !config.is_empty() {
+            return Some(PathBuf::from(config).join("terminus/config"));
+        }
+    }
+    env::var("HOME")
+        .ok()
+        .map(|home| PathBuf::from(home).join(".config/terminus/config"))
+}
+
+/// Where the log goes: `--log-file`, or `$TERMINUS_LOG`, and its levels
+pub fn log_settings(options: &Options) -> (Option<PathBuf>, String) {
+    let file = options.log_file.clone().or_else(|| {
+        env::var("TERMINUS_LOG")
+            .ok()
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    });
+    let level = options
+        .log_level
+        .clone()
+        .or_else(|| env::var("TERMINUS_LOG_LEVEL").ok())
+        .unwrap_or_default();
+    (file, level)
+}
+
+/// The command lines of a config file, without blank lines and `#` comments
+pub fn config_commands(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| format!(":{}", line.trim_start_matches(':')))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Cli, CliError> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn file(path: &str, line: Option<usize>, column: Option<usize>) -> FileArg {
+        FileArg {
+            path: PathBuf::from(path),
+            line,
+            column,
+        }
+    }
+
+    #[test]
+    fn parses_files_and_positions() {
+        let Ok(Cli::Edit(options)) =
+            parse_args(&["src/main.rs:42:7", "+3", "b.txt", "c.txt:9", "-R"])
+        else {
+            panic!("not parsed");
+        };
+        assert_eq!(
+            options.files,
+            vec![
+                file("src/main.rs", Some(42), Some(7)),
+                file("b.txt", Some(3), None),
+                file("c.txt", Some(9), None),
+            ]
+        );
+        assert!(options.readonly);
+        // Not a position, part of the name
+        let Ok(Cli::Edit(options)) = parse_args(&["a:b", ":1", "--", "-R"]) else {
+            panic!("not parsed");
+        };
+        assert_eq!(
+            options.files,
+            vec![
+                file("a:b", None, None),
+                file(":1", None, None),
+                file("-R", None, None)
+            ]
+        );
+        assert!(!options.readonly);
+    }
+
+    #[test]
+    fn parses_flags() {
+        assert_eq!(parse_args(&["a", "--help"]), Ok(Cli::Help));
+        assert_eq!(parse_args(&["-V"]), Ok(Cli::Version));
+        let Ok(Cli::Edit(options)) = parse_args(&[
+            "--theme",
+            "InspiredGitHub",
+            "--config",
+            "init",
+            "--log-file",
+            "log",
+            "--log-level",
+            "debug",
+            "-d",
+            "old",
+            "new",
+        ]) else {
+            panic!("not parsed");
+        };
+        assert_eq!(options.theme.as_deref(), Some("InspiredGitHub"));
+        assert_eq!(options.config, Some(PathBuf::from("init")));
+        assert_eq!(options.log_file, Some(PathBuf::from("log")));
+        assert_eq!(options.log_level.as_deref(), Some("debug"));
+        assert!(options.diff);
+        let Ok(Cli::Edit(options)) = parse_args(&["-"]) else {
+            panic!("not parsed");
+        };
+        assert!(options.files[0].is_stdin());
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert_eq!(
+            parse_args(&["--nope"]),
+            Err(CliError::UnknownOption("--nope".to_string()))
+        );
+        assert_eq!(
+            parse_args(&["--theme"]),
+            Err(CliError::MissingValue("--theme".to_string()))
+        );
+        assert_eq!(
+            parse_args(&["+x", "a"]),
+            Err(CliError::InvalidLine("+x".to_string()))
+        );
+        assert_eq!(parse_args(&["a", "+4"]), Err(CliError::NoFileForLine(4)));
+        assert_eq!(parse_args(&["-d", "a"]), Err(CliError::DiffFiles(1)));
+        assert_eq!(parse_args(&["-", "-"]), Err(CliError::StdinTwice));
+        assert_eq!(parse_args(&["a", "-"]), Err(CliError::StdinNotFirst));
+    }
+
+    #[test]
+    fn reads_config_commands() {
+        assert_eq!(
+            config_commands("# theme\ntheme InspiredGitHub\n\n  :set ff=dos\n"),
+            vec![":theme InspiredGitHub", ":set ff=dos"]
+        );
+    }
+}
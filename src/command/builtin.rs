@@ -0,0 +1,1001 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crossterm::style::Color;
+use regex::Regex;
+use ropey::Rope;
+
+use crate::{
+    cli::FileArg,
+    completion, diff,
+    editor::TextEditor,
+    encoding::{self, Encoding, FileFormat, LineEnding},
+    file_io::{self, FileError},
+    large_file, logger, swap,
+    widget::{
+        palette::Palette,
+        prompt::{self, Answer, Choice, Input},
+        widget::{ColorText, ColorTextTag, CursorPosition, ProcessEvent, WidgetType},
+    },
+};
+
+use super::{
+    keymap::Keymap,
+    parser::{Address, Arg, ArgKind, ArgSpec, Range},
+    registry::{Command, CommandContext, CommandRegistry, Trigger},
+};
+
+/// Matches of the last `:find`
+#[derive(Default)]
+pub struct Search {
+    /// the command line that produced the matches
+    pub query: String,
+    /// char position and length of every match
+    pub positions: Vec<(usize, usize)>,
+    pub position_idx: usize,
+}
+
+pub fn register_builtin_commands(registry: &mut CommandRegistry) {
+    registry.register(
+        Command::new(
+            "quit",
+            "Quit the editor, asks to save the changes, ! discards them",
+            vec![],
+            quit,
+        )
+        .with_alias("q"),
+    );
+    registry.register(
+        Command::new(
+            "wq",
+            "Save the panel and quit",
+            vec![ArgSpec::optional("path", ArgKind::Path)],
+            write_quit,
+        )
+        .with_alias("x")
+        .with_completer(completion::complete_paths),
+    );
+    // Only the buffer in the panel can have changes, the others are read again
+    // when switched to
+    registry.register(Command::new(
+        "wqa",
+        "Save every changed buffer and quit",
+        vec![],
+        write_quit,
+    ));
+    registry.register(
+        Command::new(
+            "find",
+            "Highlight the matches of a text or /regex/ and jump to the next one",
+            vec![ArgSpec::optional("pattern", ArgKind::Regex)],
+            find,
+        )
+        .incremental(),
+    );
+    registry.register(
+        Command::new(
+            "open",
+            "Open a file in the panel, asks to save the changes, ! discards them",
+            vec![ArgSpec::required("path", ArgKind::Path)],
+            open,
+        )
+        .with_alias("e")
+        .with_completer(completion::complete_paths),
+    );
+    registry.register(
+        Command::new(
+            "save",
+            "Save the panel, to the current file by default, ! overwrites changes made by others",
+            vec![ArgSpec::optional("path", ArgKind::Path)],
+            save,
+        )
+        .with_alias("w")
+        .with_completer(completion::complete_paths),
+    );
+    registry.register(
+        Command::new(
+            "set",
+            "Show or change an option: fileformat=unix|dos, fileencoding=<encoding>, [no]readonly",
+            vec![ArgSpec::required("option", ArgKind::String)],
+            set,
+        )
+        .with_completer(completion::complete_options),
+    );
+    registry.register(Command::new(
+        "reopen-with-encoding",
+        "Read the file again in another encoding, ! discards the changes",
+        vec![ArgSpec::required(
+            "encoding",
+            ArgKind::Enum(Encoding::NAMES),
+        )],
+        reopen_with_encoding,
+    ));
+    registry.register(Command::new(
+        "reload",
+        "Read the file again, ! discards the changes",
+        vec![],
+        reload,
+    ));
+    registry.register(Command::new(
+        "diff",
+        "Show how the buffer differs from its file",
+        vec![],
+        diff,
+    ));
+    registry.register(Command::new(
+        "recover",
+        "Load the swap file of the buffer, ! deletes it instead",
+        vec![],
+        recover,
+    ));
+    registry.register(
+        Command::new(
+            "theme",
+            "Change the highlighting theme",
+            vec![ArgSpec::required("name", ArgKind::String)],
+            theme,
+        )
+        .with_completer(completion::complete_themes),
+    );
+    registry.register(
+        Command::new(
+            "syntax-add",
+            "Load the .sublime-syntax files of a directory",
+            vec![ArgSpec::required("dir", ArgKind::Path)],
+            syntax_add,
+        )
+        .with_completer(completion::complete_paths),
+    );
+    registry.register(
+        Command::new(
+            "setfiletype",
+            "Highlight the buffer as a filetype like rust or sh, shows the current one",
+            vec![ArgSpec::optional("filetype", ArgKind::String)],
+            setfiletype,
+        )
+        .with_alias("setf")
+        .with_completer(completion::complete_filetypes),
+    );
+    registry.register(
+        Command::new(
+            "buffer",
+            "Switch to an open buffer, asks to save the changes, ! discards them",
+            vec![ArgSpec::required("name", ArgKind::String)],
+            buffer,
+        )
+        .with_alias("b")
+        .with_completer(completion::complete_buffers),
+    );
+    registry.register(
+        Command::new(
+            "goto",
+            "Jump to a line, also written :<line>",
+            vec![ArgSpec::optional("line", ArgKind::Int)],
+            goto,
+        )
+        .with_range(),
+    );
+    registry.register(
+        Command::new(
+            "help",
+            "Show the help of a command, or list every command",
+            vec![ArgSpec::optional("command", ArgKind::String)],
+            help,
+        )
+        .with_completer(completion::complete_commands),
+    );
+    registry.register(Command::new(
+        "messages",
+        "Show the recent lines of the log",
+        vec![],
+        messages,
+    ));
+    registry.register(Command::new("undo", "Undo the last change", vec![], undo));
+    registry.register(Command::new(
+        "redo",
+        "Redo the last undone change",
+        vec![],
+        redo,
+    ));
+    registry.register(Command::new(
+        "command-line",
+        "Focus the command line, optionally filled with a text",
+        vec![ArgSpec::optional("text", ArgKind::Rest)],
+        command_line,
+    ));
+    registry.register(Command::new(
+        "interrupt",
+        "Kill the running shell commands, quit if there are none",
+        vec![],
+        interrupt,
+    ));
+    registry.register(Command::new(
+        "palette",
+        "List every command with its keybindings and run one",
+        vec![],
+        palette,
+    ));
+}
+
+pub fn bind_default_keys(keymap: &mut Keymap) {
+    keymap.bind("ctrl+z", ":undo");
+    keymap.bind("ctrl+y", ":redo");
+    keymap.bind("ctrl+q", ":quit");
+    keymap.bind("ctrl+c", ":interrupt");
+    keymap.bind("ctrl+e", ":command-line");
+    keymap.bind("ctrl+s", ":command-line :save __FILE__");
+    keymap.bind("ctrl+f", ":command-line :find ");
+    // Most terminals send ctrl+shift+p as ctrl+p
+    keymap.bind("ctrl+shift+p", ":palette");
+    keymap.bind("ctrl+p", ":palette");
+}
+
+/// Gives the focus back to the panel
+pub(crate) fn focus_panel(editor: &mut TextEditor) -> Option<CursorPosition> {
+    if let Some(panel) = editor.get_widget(WidgetType::Panel) {
+        editor.focused_widget_id = panel.get_id();
+    }
+    let panel = editor.get_widget_mut(WidgetType::Panel)?;
+    panel.set_focused(true);
+    Some(panel.update_cursor_position_and_view())
+}
+
+fn quit(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    if ctx.editor.written && !ctx.invocation.bang {
+        return Some(ctx.editor.ask_to_save(":quit"));
+    }
+    ctx.editor.running = false;
+    None
+}
+
+fn write_quit(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    // A failed save says why and keeps the editor open
+    match save_buffer(ctx) {
+        Saved::Written => {
+            ctx.editor.running = false;
+            None
+        }
+        Saved::Asking(pos) => Some(pos),
+        Saved::Failed => None,
+    }
+}
+
+fn interrupt(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let killed = ctx.editor.jobs.kill_all();
+    if killed == 0 {
+        return quit(ctx);
+    }
+    ctx.editor
+        .info(format!("Killing {} running command(s)", killed));
+    None
+}
+
+fn next_position(editor: &mut TextEditor) -> (usize, usize) {
+    let search = &mut editor.search;
+    if search.positions.is_empty() {
+        return (0, 0);
+    }
+    search.position_idx += 1;
+    if search.position_idx >= search.positions.len() {
+        search.position_idx = 0;
+    }
+    search.positions[search.position_idx]
+}
+
+fn find(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let editor = &mut *ctx.editor;
+    let regex = match ctx.invocation.args.first() {
+        Some(Arg::Regex(regex)) => regex.clone(),
+        _ => {
+            editor.search = Search::default();
+            if let Some(panel) = editor.get_widget_mut(WidgetType::Panel) {
+                panel.remove_color(&|c: &ColorText| {
+                    c.tag == ColorTextTag::Selection || c.tag == ColorTextTag::Find
+                });
+            }
+            return None;
+        }
+    };
+    if editor.large_file {
+        return find_in_large_file(editor, &regex, ctx.trigger);
+    }
+    let query = regex.as_str().to_string();
+    // Typing keeps the current match, Enter moves to the next one
+    if !editor.search.positions.is_empty() && editor.search.query == query {
+        if ctx.trigger == Trigger::Typing {
+            return None;
+        }
+        let (new_position, new_len) = next_position(editor);
+        if let Some(panel) = editor.get_widget_mut(WidgetType::Panel) {
+            let y = panel.get_buffer().char_to_line(new_position);
+            let x = new_position - panel.get_buffer().line_to_char(y);
+            panel.remove_color(&|c: &ColorText| c.tag == ColorTextTag::Selection);
+
+            panel.push_color(
+                y,
+                ColorText {
+                    x,
+                    fg: Color::Blue,
+                    bg: Color::Reset,
+                    len: new_len,
+                    z_index: 10,
+                    tag: ColorTextTag::Selection,
+                },
+            );
+            panel.set_text_position(new_position);
+            panel.update_cursor_position_and_view();
+        }
+        return None;
+    }
+    let mut search = Search {
+        query,
+        ..Default::default()
+    };
+    if let Some(panel) = editor.get_widget_mut(WidgetType::Panel) {
+        let mut found_pos = panel.get_text_position();
+        let mut found = false;
+        let mut found_len = 0;
+
+        panel.remove_color(&|c: &ColorText| c.tag == ColorTextTag::Find);
+        panel.remove_color(&|c: &ColorText| c.tag == ColorTextTag::Selection);
+
+        let text = panel.get_buffer().to_string();
+        for m in regex.find_iter(&text) {
+            if m.is_empty() {
+                continue;
+            }
+            let pos = panel.get_buffer().byte_to_char(m.start());
+            let len = m.as_str().chars().count();
+            let y = panel.get_buffer().char_to_line(pos);
+            let x = pos - panel.get_buffer().line_to_char(y);
+            panel.push_color(
+                y,
+                ColorText {
+                    x,
+                    fg: Color::Red,
+                    bg: Color::Reset,
+                    len,
+                    z_index: 5,
+                    tag: ColorTextTag::Find,
+                },
+            );
+            if !found && pos >= found_pos {
+                found = true;
+                found_pos = pos;
+                found_len = len;
+                search.position_idx = search.positions.len();
+            }
+            search.positions.push((pos, len));
+        }
+        if !found && !search.positions.is_empty() {
+            found_pos = search.positions[0].0;
+            found_len = search.positions[0].1;
+            search.position_idx = 0;
+            found = true;
+        }
+
+        if found {
+            let y = panel.get_buffer().char_to_line(found_pos);
+            let x = found_pos - panel.get_buffer().line_to_char(y);
+            panel.push_color(
+                y,
+                ColorText {
+                    x,
+                    fg: Color::Blue,
+                    bg: Color::Reset,
+                    len: found_len,
+                    z_index: 10,
+                    tag: ColorTextTag::Selection,
+                },
+            );
+            panel.set_text_position(found_pos);
+            panel.update_cursor_position_and_view();
+        }
+    }
+    editor.search = search;
+    None
+}
+
+/// Jumps to the next match after the cursor without copying the text or coloring
+/// every match, which would cost gigabytes in a large file
+fn find_in_large_file(
+    editor: &mut TextEditor,
+    regex: &Regex,
+    trigger: Trigger,
+) -> Option<CursorPosition> {
+    // Searching the whole file on every key would freeze the typing
+    if trigger == Trigger::Typing {
+        return None;
+    }
+    let query = regex.as_str().to_string();
+    // Enter again moves past the current match
+    let again = editor.search.query == query && !editor.search.positions.is_empty();
+    let panel = editor.get_widget_mut(WidgetType::Panel)?;
+    let from = panel.get_text_position() + again as usize;
+    match large_file::find_next(panel.get_buffer(), regex, from) {
+        Some((position, len)) => {
+            panel.set_text_position(position);
+            panel.update_cursor_position_and_view();
+            editor.search = Search {
+                query,
+                positions: vec![(position, len)],
+                position_idx: 0,
+            };
+        }
+        None => {
+            editor.search = Search::default();
+            editor.warn(format!("No match for {}", query));
+        }
+    }
+    None
+}
+
+fn open(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let path = match ctx.arg(0) {
+        Some(Arg::Path(path)) => path.clone(),
+        _ => return None,
+    };
+    let editor = &mut *ctx.editor;
+    if editor.written && !ctx.invocation.bang {
+        return Some(editor.ask_to_save(&format!(":open {}", path.display())));
+    }
+    // Open the file
+    match file_io::read_rope(&path) {
+        Ok((buffer, format)) => {
+            editor.remember_position();
+            load_buffer(editor, path, buffer, format)
+        }
+        Err(e) => {
+            editor.error(e.to_string());
+            None
+        }
+    }
+}
+
+/// Shows the text of the file at `path` in the panel
+fn load_buffer(
+    editor: &mut TextEditor,
+    path: PathBuf,
+    buffer: Rope,
+    format: FileFormat,
+) -> Option<CursorPosition> {
+    editor.file_format = format;
+    // The filetype set for the previous file is not the one of this file
+    if path != editor.save_path {
+        editor.filetype = None;
+    }
+    if !editor.buffers.iter().any(|buffer| buffer.path == path) {
+        editor.buffers.push(FileArg::new(path.clone()));
+    }
+    editor.large_file = large_file::is_large(&path);
+    if let Some(panel) = editor.get_widget(WidgetType::Panel) {
+        editor.focused_widget_id = panel.get_id();
+    }
+    // The changes of the previous file are discarded with it
+    editor.swap.remove(&editor.save_path);
+    // Saving writes to the opened file, not to the previous one
+    editor.save_path = path.clone();
+    editor.file_watch.reset(&path);
+    if let Some(status_bar) = editor.get_widget_mut(WidgetType::StatusBar) {
+        status_bar.set_buffer(Rope::from_str(&path.to_string_lossy()));
+    }
+    let res = match editor.get_widget_mut(WidgetType::Panel) {
+        Some(panel) => {
+            panel.set_buffer(buffer);
+            panel.set_text_position(0);
+            panel.set_focused(true);
+            let res = panel.update_cursor_position_and_view();
+            panel.remove_color(&|c: &ColorText| {
+                c.tag == ColorTextTag::Selection || c.tag == ColorTextTag::Find
+            });
+            res
+        }
+        None => return None,
+    };
+    editor.detect_filetype(&path);
+    editor.diff_with_head(&path);
+    editor.written = false;
+    editor.search = Search::default();
+    editor.check_swap();
+    Some(res)
+}
+
+/// What saving the buffer came to
+enum Saved {
+    Written,
+    /// the user is asked for a path or whether to overwrite a file
+    Asking(CursorPosition),
+    /// the error was shown
+    Failed,
+}
+
+fn save(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    match save_buffer(ctx) {
+        Saved::Written => focus_panel(ctx.editor),
+        Saved::Asking(pos) => Some(pos),
+        Saved::Failed => None,
+    }
+}
+
+/// Writes the buffer to the path given to the command, its own file by default
+fn save_buffer(ctx: &mut CommandContext) -> Saved {
+    let path = match ctx.arg(0) {
+        Some(Arg::Path(path)) => path.clone(),
+        _ => ctx.editor.save_path.clone(),
+    };
+    let force = ctx.invocation.bang;
+    let editor = &mut *ctx.editor;
+    let own_file = path == editor.save_path;
+    if path.as_os_str().is_empty() {
+        let input = Input::new(":save {}")
+            .with_completer(completion::complete_paths)
+            .with_validator(validate_save_path)
+            .with_history(editor.history_dir.as_deref(), "save_history");
+        return Saved::Asking(editor.prompt("Save a copy as:", Answer::Input(input)));
+    }
+    if own_file && editor.readonly {
+        editor.error("The buffer is read-only, :w <path> saves a copy");
+        return Saved::Failed;
+    }
+    if !own_file && !force && path.exists() {
+        let question = format!("Overwrite {}? [y]es/[n]o", path.display());
+        let overwrite = format!(":save! {}", prompt::quote(&path.to_string_lossy()));
+        let choices = vec![Choice::new('y', &[&overwrite]), Choice::new('n', &[])];
+        return Saved::Asking(editor.prompt(&question, Answer::Choices(choices)));
+    }
+    if own_file && !force && editor.file_watch.changed_on_disk() {
+        editor.error(format!(
+            "{} was changed by another program, :save! overwrites it",
+            path.display()
+        ));
+        return Saved::Failed;
+    }
+    let panel = match editor.get_widget(WidgetType::Panel) {
+        Some(panel) => panel,
+        None => return Saved::Failed,
+    };
+    let content = match encoding::encode(&panel.get_buffer().to_string(), editor.file_format) {
+        Ok(content) => content,
+        Err(e) => {
+            editor.error(format!("Cannot save {}: {}", path.display(), e));
+            return Saved::Failed;
+        }
+    };
+    if let Err(e) = file_io::write_atomic(&path, &content) {
+        // The buffer stays modified, nothing is lost
+        editor.error(e.to_string());
+        return Saved::Failed;
+    }
+    // Text without a file becomes the text of the file it went to, a copy leaves
+    // the buffer as modified as it was
+    if editor.save_path.as_os_str().is_empty() {
+        editor.save_path = path.clone();
+        editor.buffers.push(FileArg::new(path.clone()));
+        if let Some(status_bar) = editor.get_widget_mut(WidgetType::StatusBar) {
+            status_bar.set_buffer(Rope::from_str(&path.to_string_lossy()));
+        }
+    }
+    if path == editor.save_path {
+        editor.written = false;
+        editor.file_watch.reset(&path);
+        // The file may have gained a shebang or a modeline, or been committed
+        editor.detect_filetype(&path);
+        editor.diff_with_head(&path);
+    }
+    editor.info(format!("Saved {}", path.display()));
+    Saved::Written
+}
+
+fn validate_save_path(_editor: &TextEditor, text: &str) -> Result<(), String> {
+    if text.trim().is_empty() {
+        return Err("Type the path of the copy".to_string());
+    }
+    if Path::new(text).is_dir() {
+        return Err(format!("{} is a directory", text));
+    }
+    Ok(())
+}
+
+fn set(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let option = ctx.arg(0)?.as_str().to_string();
+    let editor = &mut *ctx.editor;
+    let (name, value) = match option.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (option.as_str(), None),
+    };
+    let format = &mut editor.file_format;
+    let res = match (name, value) {
+        ("readonly" | "ro", None) => {
+            editor.readonly = true;
+            Ok("readonly".to_string())
+        }
+        ("noreadonly" | "noro", None) => {
+            editor.readonly = false;
+            Ok("noreadonly".to_string())
+        }
+        ("fileformat" | "ff", None) => Ok(format!("fileformat={}", format.line_ending)),
+        ("fileencoding" | "fenc", None) => Ok(format!("fileencoding={}", format.encoding)),
+        ("fileformat" | "ff", Some(value)) => match LineEnding::from_name(value) {
+            Some(line_ending) => {
+                format.line_ending = line_ending;
+                editor.written = true;
+                Ok(format!("fileformat={}", line_ending))
+            }
+            None => Err(format!("Invalid fileformat {}, expected unix|dos", value)),
+        },
+        ("fileencoding" | "fenc", Some(value)) => match Encoding::from_name(value) {
+            // The text is converted when saved
+            Some(encoding) => {
+                // UTF-16 is ambiguous without a byte order mark
+                if encoding != format.encoding {
+                    format.bom = true;
+                }
+                format.encoding = encoding;
+                editor.written = true;
+                Ok(format!("fileencoding={}", encoding))
+            }
+            None => Err(format!(
+                "Invalid fileencoding {}, expected {}",
+                value,
+                Encoding::NAMES.join("|")
+            )),
+        },
+        _ => Err(format!("Unknown option: {}", name)),
+    };
+    match res {
+        Ok(message) => {
+            editor.info(message);
+            focus_panel(editor)
+        }
+        Err(e) => {
+            editor.error(e);
+            None
+        }
+    }
+}
+
+fn reopen_with_encoding(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let encoding = Encoding::from_name(ctx.arg(0)?.as_str())?;
+    let force = ctx.invocation.bang;
+    let editor = &mut *ctx.editor;
+    if editor.written && !force {
+        editor.error("The buffer has unsaved changes, add ! to discard them");
+        return None;
+    }
+    let path = editor.save_path.clone();
+    let bytes = match file_io::read_bytes(&path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            editor.error(e.to_string());
+            return None;
+        }
+    };
+    match encoding::decode_as(&bytes, encoding) {
+        Ok((buffer, format)) => load_buffer(editor, path, Rope::from_str(&buffer), format),
+        Err(e) => {
+            editor.error(e);
+            None
+        }
+    }
+}
+
+fn reload(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let force = ctx.invocation.bang;
+    let editor = &mut *ctx.editor;
+    if let Some((typ, id)) = editor.output_popup.take() {
+        editor.remove_widget_id(id, typ);
+    }
+    if editor.written && !force {
+        editor.error("The buffer has unsaved changes, add ! to discard them");
+        return None;
+    }
+    match editor.reload() {
+        Ok(_) => {
+            editor.info(format!("Reloaded {}", editor.save_path.display()));
+            focus_panel(editor)
+        }
+        Err(e) => {
+            editor.error(e.to_string());
+            None
+        }
+    }
+}
+
+fn diff(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let editor = &mut *ctx.editor;
+    if editor.large_file {
+        editor.error("The file is too large to be diffed");
+        return None;
+    }
+    let path = editor.save_path.clone();
+    let on_disk = match file_io::read_file(&path) {
+        Ok((text, _)) => text,
+        Err(e) => {
+            editor.error(e.to_string());
+            return None;
+        }
+    };
+    let buffer = editor
+        .get_widget(WidgetType::Panel)?
+        .get_buffer()
+        .to_string();
+    let name = path.display().to_string();
+    let text = diff::unified(&name, &format!("{} (buffer)", name), &on_disk, &buffer);
+    if text.is_empty() {
+        editor.info(format!("The buffer is the same as {}", name));
+        return focus_panel(editor);
+    }
+    Some(editor.show_output_popup(text, Some("diff")))
+}
+
+fn recover(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let editor = &mut *ctx.editor;
+    let path = editor.save_path.clone();
+    // The popup asking for it is answered
+    if let Some((typ, id)) = editor.output_popup.take() {
+        editor.remove_widget_id(id, typ);
+    }
+    if swap::recoverable(&path).is_none() && !swap::swap_path(&path).exists() {
+        editor.error(format!("No swap file for {}", path.display()));
+        return None;
+    }
+    if ctx.invocation.bang {
+        let swap = swap::swap_path(&path);
+        match fs::remove_file(&swap) {
+            Ok(()) => editor.info(format!("Deleted {}", swap.display())),
+            Err(e) => editor.error(format!("Cannot delete {}: {}", swap.display(), e)),
+        }
+        return focus_panel(editor);
+    }
+    let text = match swap::recover(&path) {
+        Ok(text) => Rope::from_str(&text),
+        Err(e) => {
+            editor.error(e.to_string());
+            return None;
+        }
+    };
+    // Saving the recovered text replaces the swap file
+    editor.swap.adopt(&path, &text);
+    if let Some(panel) = editor.get_widget_mut(WidgetType::Panel) {
+        panel.set_buffer(text);
+        panel.set_text_position(0);
+        panel.update_cursor_position_and_view();
+    }
+    editor.written = true;
+    editor.info(format!("Recovered {}", path.display()));
+    focus_panel(editor)
+}
+
+fn theme(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let theme = ctx.arg(0)?.as_str().to_string();
+    if !ctx.editor.highlighter.has_theme(&theme) {
+        ctx.editor.error(format!("Unknown theme: {}", theme));
+        return None;
+    }
+    if let Some(panel) = ctx.editor.get_widget_mut(WidgetType::Panel) {
+        panel.set_theme(Some(theme));
+    }
+    None
+}
+
+fn syntax_add(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let dir = match ctx.arg(0)? {
+        Arg::Path(path) => path.clone(),
+        _ => return None,
+    };
+    match ctx.editor.add_syntaxes(&dir) {
+        Ok(()) => ctx
+            .editor
+            .info(format!("Loaded the syntaxes of {}", dir.display())),
+        Err(e) => ctx.editor.error(format!(
+            "Cannot load the syntaxes of {}: {}",
+            dir.display(),
+            e
+        )),
+    }
+    None
+}
+
+fn setfiletype(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let filetype = ctx.arg(0).map(|arg| arg.as_str().to_string());
+    let editor = &mut *ctx.editor;
+    let filetype = match filetype {
+        Some(filetype) => filetype,
+        None => {
+            let panel = editor.get_widget(WidgetType::Panel)?;
+            let name = panel
+                .get_syntax()
+                .map_or(editor.highlighter.plain_text().name.clone(), |syntax| {
+                    syntax.name.clone()
+                });
+            editor.info(format!("filetype={}", name));
+            return focus_panel(editor);
+        }
+    };
+    if editor.highlighter.syntax_for_filetype(&filetype).is_none() {
+        editor.error(format!("Unknown filetype: {}", filetype));
+        return None;
+    }
+    editor.filetype = Some(filetype);
+    let path = editor.save_path.clone();
+    editor.detect_filetype(&path);
+    focus_panel(editor)
+}
+
+fn buffer(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let name = ctx.arg(0)?.as_str().to_string();
+    let force = ctx.invocation.bang;
+    let editor = &mut *ctx.editor;
+    let file = match editor
+        .buffers
+        .iter()
+        .find(|buffer| buffer.path.to_string_lossy() == name)
+    {
+        Some(file) => file.clone(),
+        None => {
+            editor.error(format!("No buffer named {}", name));
+            return None;
+        }
+    };
+    if file.path == editor.save_path {
+        return focus_panel(editor);
+    }
+    if editor.written && !force {
+        return Some(editor.ask_to_save(&format!(":buffer {}", name)));
+    }
+    editor.remember_position();
+    let (buffer, format) = match file_io::read_rope(&file.path) {
+        Ok(read) => read,
+        // Named on the command line but not created yet
+        Err(FileError::NotFound(_)) => (Rope::new(), FileFormat::default()),
+        Err(e) => {
+            editor.error(e.to_string());
+            return None;
+        }
+    };
+    load_buffer(editor, file.path, buffer, format)?;
+    match file.line {
+        Some(line) => editor.goto_position(line, file.column),
+        None => focus_panel(editor),
+    }
+}
+
+fn goto(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let range = match (ctx.arg(0), ctx.invocation.range) {
+        (Some(Arg::Int(line)), _) => {
+            let line = Address::Line((*line).max(1) as usize);
+            Range::Lines(line, line)
+        }
+        (_, Some(range)) => range,
+        _ => Range::Lines(Address::Current, Address::Current),
+    };
+    let editor = &mut *ctx.editor;
+    let panel = editor.get_widget_mut(WidgetType::Panel)?;
+    let current_line = panel.get_buffer().char_to_line(panel.get_text_position());
+    let selection = panel.get_selection_lines();
+    let line = match range.resolve(panel.get_buffer(), current_line, selection) {
+        Ok((line, _)) => line,
+        Err(e) => {
+            editor.error(e.to_string());
+            return None;
+        }
+    };
+    let line_start = panel.get_buffer().line_to_char(line);
+    panel.set_text_position(line_start);
+    focus_panel(editor)
+}
+
+fn help(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let editor = &mut *ctx.editor;
+    let mut text = String::new();
+    match ctx.invocation.args.first() {
+        Some(name) => {
+            let name = name.as_str().trim_start_matches(':');
+            let command = match editor.commands.get(name) {
+                Some(command) => command,
+                None => {
+                    editor.error(format!("Unknown command: {}", name));
+                    return None;
+                }
+            };
+            text.push_str(&command.usage());
+            text.push('\n');
+            text.push_str(&format!("    {}\n", command.description));
+            for arg in &command.args {
+                text.push_str(&format!("    {}: {}\n", arg, arg.kind));
+            }
+            if !command.aliases.is_empty() {
+                text.push_str(&format!("    aliases: {}\n", command.aliases.join(", ")));
+            }
+            let keys = editor.keymap.bindings_for(&command.name);
+            if !keys.is_empty() {
+                text.push_str(&format!("    keys: {}\n", keys.join(", ")));
+            }
+            if command.accepts_range {
+                text.push_str("    accepts a range, e.g. :10,20 :% :.,$\n");
+            }
+        }
+        None => {
+            let names = editor.commands.names();
+            let usages: Vec<String> = names
+                .iter()
+                .map(|name| editor.commands.get(name).unwrap().usage())
+                .collect();
+            let width = usages.iter().map(|usage| usage.len()).max().unwrap_or(0);
+            for (name, usage) in names.iter().zip(usages.iter()) {
+                text.push_str(&format!(
+                    "{:width$}  {}\n",
+                    usage,
+                    editor.commands.get(name).unwrap().description,
+                    width = width
+                ));
+            }
+        }
+    }
+    Some(editor.show_output_popup(text, None))
+}
+
+fn messages(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let mut text = logger::recent().join("\n");
+    if text.is_empty() {
+        text.push_str("No messages");
+    }
+    text.push('\n');
+    Some(ctx.editor.show_output_popup(text, None))
+}
+
+fn undo(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    if ctx.editor.focused_widget_mut()?.get_type() == WidgetType::Panel
+        && !ctx.editor.check_writable()
+    {
+        return None;
+    }
+    ctx.editor.focused_widget_mut()?.undo()
+}
+
+fn redo(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    if ctx.editor.focused_widget_mut()?.get_type() == WidgetType::Panel
+        && !ctx.editor.check_writable()
+    {
+        return None;
+    }
+    ctx.editor.focused_widget_mut()?.redo()
+}
+
+fn command_line(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let text = match ctx.arg(0) {
+        Some(text) => text
+            .as_str()
+            .replace("__FILE__", &ctx.editor.save_path.to_string_lossy()),
+        None => String::new(),
+    };
+    let editor = &mut *ctx.editor;
+    let command_line = editor.get_widget_mut(WidgetType::CommandLine)?;
+    command_line.set_focused(true);
+    if !text.is_empty() {
+        command_line.set_scroll_columns(0);
+        command_line.set_buffer(Rope::from_str(&text));
+        command_line.set_text_position(text.chars().count());
+    }
+    let cursor_position = command_line.update_cursor_position_and_view();
+    editor.focused_widget_id = command_line.get_id();
+    Some(cursor_position)
+}
+
+fn palette(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let editor = &mut *ctx.editor;
+    let return_focus = editor.focused_widget_id;
+    let return_cursor = match editor.focused_widget_mut() {
+        Some(widget) => {
+            widget.set_focused(false);
+            widget.get_cursor_view()
+        }
+        None => (0, 0),
+    };
+    let palette = Palette::new(editor, return_focus, return_cursor);
+    let cursor_position = palette.get_cursor_view();
+    editor.add_widget(palette);
+    Some(cursor_position)
+}
@@ -0,0 +1,109 @@
+use crate::{
+    fold::Folds,
+    widget::widget::{CursorPosition, WidgetType},
+};
+
+use super::{
+    builtin::focus_panel,
+    keymap::Keymap,
+    registry::{Command, CommandContext, CommandRegistry},
+};
+
+pub fn register_fold_commands(registry: &mut CommandRegistry) {
+    registry.register(Command::new(
+        "fold",
+        "Close the innermost fold at the cursor",
+        vec![],
+        fold,
+    ));
+    registry.register(Command::new(
+        "unfold",
+        "Open the closed fold at the cursor",
+        vec![],
+        unfold,
+    ));
+    registry.register(Command::new(
+        "fold-toggle",
+        "Open the closed fold at the cursor, or close the innermost one",
+        vec![],
+        fold_toggle,
+    ));
+    registry.register(Command::new(
+        "foldall",
+        "Close every fold of the buffer",
+        vec![],
+        foldall,
+    ));
+    registry.register(Command::new(
+        "unfoldall",
+        "Open every fold of the buffer",
+        vec![],
+        unfoldall,
+    ));
+}
+
+pub fn bind_fold_keys(keymap: &mut Keymap) {
+    keymap.bind("ctrl+k", ":fold-toggle");
+}
+
+/// Changes the folds of the panel at the line of its cursor, the cursor goes to
+/// the first line of the fold now hiding it
+fn change_folds(
+    ctx: &mut CommandContext,
+    change: fn(folds: &mut Folds, line: usize) -> bool,
+    failure: &str,
+) -> Option<CursorPosition> {
+    let editor = &mut *ctx.editor;
+    let panel = editor.get_widget_mut(WidgetType::Panel)?;
+    let line = panel.get_buffer().char_to_line(panel.get_text_position());
+    let folds = panel.get_folds_mut()?;
+    let changed = change(folds, line);
+    let shown_line = folds.shown_line(line);
+    if shown_line != line {
+        let line_start = panel.get_buffer().line_to_char(shown_line);
+        panel.set_text_position(line_start);
+    }
+    if !changed {
+        editor.error(failure);
+        return None;
+    }
+    focus_panel(editor)
+}
+
+fn fold(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    change_folds(ctx, Folds::close, "No open fold at the cursor")
+}
+
+fn unfold(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    change_folds(ctx, Folds::open, "No closed fold at the cursor")
+}
+
+fn fold_toggle(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    change_folds(
+        ctx,
+        |folds, line| folds.open(line) || folds.close(line),
+        "No fold at the cursor",
+    )
+}
+
+fn foldall(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    change_folds(
+        ctx,
+        |folds, _| {
+            folds.set_all_closed(true);
+            !folds.is_empty()
+        },
+        "The buffer has no folds",
+    )
+}
+
+fn unfoldall(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    change_folds(
+        ctx,
+        |folds, _| {
+            folds.set_all_closed(false);
+            !folds.is_empty()
+        },
+        "The buffer has no folds",
+    )
+}
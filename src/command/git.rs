@@ -0,0 +1,98 @@
+use crate::{
+    editor::TextEditor,
+    git::{Hunk, Hunks},
+    widget::widget::{CursorPosition, WidgetType},
+};
+
+use super::{
+    builtin::focus_panel,
+    registry::{Command, CommandContext, CommandRegistry},
+};
+
+pub fn register_git_commands(registry: &mut CommandRegistry) {
+    registry.register(Command::new(
+        "gitnext",
+        "Go to the next lines changed since the last commit",
+        vec![],
+        gitnext,
+    ));
+    registry.register(Command::new(
+        "gitprev",
+        "Go to the previous lines changed since the last commit",
+        vec![],
+        gitprev,
+    ));
+    registry.register(Command::new(
+        "gitrevert",
+        "Put back the lines of the last commit changed at the cursor",
+        vec![],
+        gitrevert,
+    ));
+}
+
+/// The hunk `find` picks from the line of the cursor, or why there is none
+fn find_hunk(
+    editor: &mut TextEditor,
+    find: fn(hunks: &Hunks, line: usize) -> Option<&Hunk>,
+    failure: &str,
+) -> Result<Hunk, String> {
+    let panel = editor
+        .get_widget_mut(WidgetType::Panel)
+        .ok_or_else(|| failure.to_string())?;
+    let line = panel.get_buffer().char_to_line(panel.get_text_position());
+    // Through the mutable hunks, which are diffed again if the text changed
+    match panel.get_hunks_mut() {
+        Some(hunks) if hunks.has_head() => find(hunks, line)
+            .cloned()
+            .ok_or_else(|| failure.to_string()),
+        _ => Err("The buffer is not a file of the last commit".to_string()),
+    }
+}
+
+/// Puts the cursor on the first line of the hunk `find` picks
+fn goto_hunk(
+    ctx: &mut CommandContext,
+    find: fn(hunks: &Hunks, line: usize) -> Option<&Hunk>,
+    failure: &str,
+) -> Option<CursorPosition> {
+    let editor = &mut *ctx.editor;
+    match find_hunk(editor, find, failure) {
+        Ok(hunk) => {
+            editor.goto_position(hunk.start + 1, None);
+            focus_panel(editor)
+        }
+        Err(e) => {
+            editor.error(e);
+            None
+        }
+    }
+}
+
+fn gitnext(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    goto_hunk(ctx, Hunks::next, "No change after the cursor")
+}
+
+fn gitprev(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    goto_hunk(ctx, Hunks::previous, "No change before the cursor")
+}
+
+fn gitrevert(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let editor = &mut *ctx.editor;
+    if !editor.check_writable() {
+        return None;
+    }
+    let hunk = match find_hunk(editor, Hunks::at, "No change at the cursor") {
+        Ok(hunk) => hunk,
+        Err(e) => {
+            editor.error(e);
+            return None;
+        }
+    };
+    let panel = editor.get_widget_mut(WidgetType::Panel)?;
+    let start = panel.get_buffer().line_to_char(hunk.start);
+    let end = panel.get_buffer().line_to_char(hunk.start + hunk.len);
+    panel.replace_range(start, end, &hunk.removed.concat());
+    panel.set_text_position(start);
+    editor.written = true;
+    focus_panel(editor)
+}
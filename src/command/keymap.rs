@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Name of a key chord, e.g. `ctrl+shift+p`, modifiers in a fixed order
+pub fn key_name(key: &KeyEvent) -> Option<String> {
+    let code = match key.code {
+        KeyCode::Char(' ') => "space".to_string(),
+        KeyCode::Char(c) => c.to_lowercase().to_string(),
+        KeyCode::F(n) => format!("f{}", n),
+        KeyCode::Up => "up".to_string(),
+        KeyCode::Down => "down".to_string(),
+        KeyCode::Left => "left".to_string(),
+        KeyCode::Right => "right".to_string(),
+        KeyCode::PageUp => "pageup".to_string(),
+        KeyCode::PageDown => "pagedown".to_string(),
+        KeyCode::Home => "home".to_string(),
+        KeyCode::End => "end".to_string(),
+        KeyCode::Tab => "tab".to_string(),
+        KeyCode::BackTab => "backtab".to_string(),
+        KeyCode::Enter => "enter".to_string(),
+        KeyCode::Esc => "esc".to_string(),
+        KeyCode::Backspace => "backspace".to_string(),
+        KeyCode::Delete => "delete".to_string(),
+        KeyCode::Insert => "insert".to_string(),
+        _ => return None,
+    };
+    let mut name = String::new();
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        name.push_str("ctrl+");
+    }
+    if key.modifiers.contains(KeyModifiers::ALT) {
+        name.push_str("alt+");
+    }
+    // An uppercase char already tells shift was pressed
+    let is_upper_char = matches!(key.code, KeyCode::Char(c) if c.is_uppercase());
+    if key.modifiers.contains(KeyModifiers::SHIFT) || is_upper_char {
+        name.push_str("shift+");
+    }
+    name.push_str(&code);
+    Some(name)
+}
+
+/// Puts a user written chord such as `Shift+Ctrl+P` in the `key_name` form
+pub fn normalize_key_name(chord: &str) -> String {
+    let parts: Vec<String> = chord.split('+').map(|p| p.trim().to_lowercase()).collect();
+    let (key, modifiers) = match parts.split_last() {
+        Some(split) => split,
+        None => return String::new(),
+    };
+    let mut name = String::new();
+    for modifier in ["ctrl", "alt", "shift"] {
+        if modifiers
+            .iter()
+            .any(|m| m == modifier || (m == "control" && modifier == "ctrl"))
+        {
+            name.push_str(modifier);
+            name.push('+');
+        }
+    }
+    name.push_str(key);
+    name
+}
+
+/// Maps key chords to the command lines they run
+#[derive(Default)]
+pub struct Keymap {
+    bindings: HashMap<String, String>,
+}
+
+impl Keymap {
+    pub fn bind(&mut self, chord: &str, command_line: &str) {
+        self.bindings
+            .insert(normalize_key_name(chord), command_line.to_string());
+    }
+
+    pub fn get(&self, key: &KeyEvent) -> Option<&String> {
+        self.bindings.get(&key_name(key)?)
+    }
+
+    /// Chords running the command `name`, sorted
+    pub fn bindings_for(&self, name: &str) -> Vec<String> {
+        let mut chords: Vec<String> = self
+            .bindings
+            .iter()
+            .filter(|(_, line)| {
+                line.trim_start_matches(':')
+                    .split(|c: char| c.is_whitespace() || c == '!')
+                    .next()
+                    == Some(name)
+            })
+            .map(|(chord, _)| chord.clone())
+            .collect();
+        chords.sort();
+        chords
+    }
+}
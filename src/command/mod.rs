@@ -0,0 +1,8 @@
+pub mod builtin;
+pub mod fold;
+pub mod git;
+pub mod keymap;
+pub mod movement;
+pub mod parser;
+pub mod registry;
+pub mod shell;
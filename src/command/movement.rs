@@ -0,0 +1,294 @@
+use crate::widget::widget::{CursorPosition, ProcessEvent};
+
+use super::{
+    keymap::Keymap,
+    registry::{Command, CommandContext, CommandRegistry},
+};
+
+/// Moves the cursor of the focused widget, whatever it is, dropping its selection
+fn move_focused(
+    ctx: &mut CommandContext,
+    motion: fn(widget: &mut dyn ProcessEvent),
+) -> Option<CursorPosition> {
+    let widget = ctx.editor.focused_widget_mut()?.as_mut();
+    widget.set_selection_anchor(None);
+    motion(widget);
+    Some(widget.update_cursor_position_and_view())
+}
+
+/// Moves the cursor of the focused widget, the selection follows it
+fn select_focused(
+    ctx: &mut CommandContext,
+    motion: fn(widget: &mut dyn ProcessEvent),
+) -> Option<CursorPosition> {
+    let widget = ctx.editor.focused_widget_mut()?.as_mut();
+    if widget.get_selection_anchor().is_none() {
+        widget.set_selection_anchor(Some(widget.get_text_position()));
+    }
+    motion(widget);
+    Some(widget.update_cursor_position_and_view())
+}
+
+pub fn register_movement_commands(registry: &mut CommandRegistry) {
+    registry.register(Command::new(
+        "cursor-left",
+        "Move the cursor one char left",
+        vec![],
+        cursor_left,
+    ));
+    registry.register(Command::new(
+        "cursor-right",
+        "Move the cursor one char right",
+        vec![],
+        cursor_right,
+    ));
+    registry.register(Command::new(
+        "cursor-up",
+        "Move the cursor one line up",
+        vec![],
+        cursor_up,
+    ));
+    registry.register(Command::new(
+        "cursor-down",
+        "Move the cursor one line down",
+        vec![],
+        cursor_down,
+    ));
+    registry.register(Command::new(
+        "select-left",
+        "Extend the selection one char left",
+        vec![],
+        select_left,
+    ));
+    registry.register(Command::new(
+        "select-right",
+        "Extend the selection one char right",
+        vec![],
+        select_right,
+    ));
+    registry.register(Command::new(
+        "select-up",
+        "Extend the selection one line up",
+        vec![],
+        select_up,
+    ));
+    registry.register(Command::new(
+        "select-down",
+        "Extend the selection one line down",
+        vec![],
+        select_down,
+    ));
+    registry.register(Command::new(
+        "word-left",
+        "Move the cursor to the start of the previous word",
+        vec![],
+        word_left,
+    ));
+    registry.register(Command::new(
+        "word-right",
+        "Move the cursor to the end of the next word",
+        vec![],
+        word_right,
+    ));
+    registry.register(Command::new(
+        "page-up",
+        "Move the cursor one page up",
+        vec![],
+        page_up,
+    ));
+    registry.register(Command::new(
+        "page-down",
+        "Move the cursor one page down",
+        vec![],
+        page_down,
+    ));
+    registry.register(Command::new(
+        "buffer-start",
+        "Move the cursor to the start of the buffer",
+        vec![],
+        buffer_start,
+    ));
+    registry.register(Command::new(
+        "buffer-end",
+        "Move the cursor to the end of the buffer",
+        vec![],
+        buffer_end,
+    ));
+}
+
+pub fn bind_movement_keys(keymap: &mut Keymap) {
+    keymap.bind("left", ":cursor-left");
+    keymap.bind("right", ":cursor-right");
+    keymap.bind("up", ":cursor-up");
+    keymap.bind("down", ":cursor-down");
+    keymap.bind("shift+left", ":select-left");
+    keymap.bind("shift+right", ":select-right");
+    keymap.bind("shift+up", ":select-up");
+    keymap.bind("shift+down", ":select-down");
+    keymap.bind("ctrl+left", ":word-left");
+    keymap.bind("ctrl+right", ":word-right");
+    keymap.bind("pageup", ":page-up");
+    keymap.bind("pagedown", ":page-down");
+    keymap.bind("home", ":buffer-start");
+    keymap.bind("end", ":buffer-end");
+}
+
+fn char_at(widget: &dyn ProcessEvent, pos: usize) -> char {
+    widget.get_buffer().char(pos)
+}
+
+fn move_left(widget: &mut dyn ProcessEvent) {
+    if widget.get_text_position() > 0 {
+        widget.set_text_position(widget.get_text_position() - 1);
+    }
+}
+
+fn move_right(widget: &mut dyn ProcessEvent) {
+    if widget.get_text_position() < widget.get_buffer().len_chars() {
+        widget.set_text_position(widget.get_text_position() + 1);
+    }
+}
+
+/// Up and down go over a closed fold as over a single line
+fn move_up(widget: &mut dyn ProcessEvent) {
+    let line = widget.get_buffer().char_to_line(widget.get_text_position());
+    if line > 0 {
+        let prev_line = widget
+            .get_folds()
+            .map_or(line - 1, |folds| folds.previous_line(line));
+        let line_start = widget.get_buffer().line_to_char(line);
+        let mut column = widget.get_text_position() - line_start;
+        let len_prev_line = widget.get_buffer().line(prev_line).len_chars();
+        if column >= len_prev_line {
+            column = len_prev_line - 1;
+        }
+        if len_prev_line > 1 {
+            widget.set_text_position(widget.get_buffer().line_to_char(prev_line) + column);
+        } else {
+            widget.set_text_position(widget.get_buffer().line_to_char(prev_line));
+        }
+    } else {
+        widget.set_text_position(0);
+    }
+}
+
+fn move_down(widget: &mut dyn ProcessEvent) {
+    let line = widget.get_buffer().char_to_line(widget.get_text_position());
+    let next_line = widget
+        .get_folds()
+        .map_or(line + 1, |folds| folds.next_line(line));
+    if widget.get_buffer().len_lines() >= 2 && next_line < widget.get_buffer().len_lines() - 1 {
+        let line_start = widget.get_buffer().line_to_char(line);
+        let mut column = widget.get_text_position() - line_start;
+        let len_next_line = widget.get_buffer().line(next_line).len_chars();
+        if column >= len_next_line {
+            column = len_next_line - 1;
+        }
+        if len_next_line > 1 {
+            widget.set_text_position(widget.get_buffer().line_to_char(next_line) + column);
+        } else {
+            widget.set_text_position(widget.get_buffer().line_to_char(next_line));
+        }
+    } else {
+        widget.set_text_position(widget.get_buffer().len_chars());
+    }
+}
+
+fn move_word_left(widget: &mut dyn ProcessEvent) {
+    let mut pos = widget.get_text_position();
+    while pos > 0 && char_at(widget, pos - 1).is_whitespace() {
+        pos -= 1;
+    }
+    let mut has_punc = false;
+    while pos > 0 && char_at(widget, pos - 1).is_ascii_punctuation() {
+        has_punc = true;
+        pos -= 1;
+    }
+    while !has_punc && pos > 0 && char_at(widget, pos - 1).is_alphanumeric() {
+        pos -= 1;
+    }
+    widget.set_text_position(pos);
+}
+
+fn move_word_right(widget: &mut dyn ProcessEvent) {
+    let len = widget.get_buffer().len_chars();
+    let mut pos = widget.get_text_position();
+    while pos < len && char_at(widget, pos).is_whitespace() {
+        pos += 1;
+    }
+    let mut has_punc = false;
+    while pos < len && char_at(widget, pos).is_ascii_punctuation() {
+        has_punc = true;
+        pos += 1;
+    }
+    while !has_punc && pos < len && char_at(widget, pos).is_alphanumeric() {
+        pos += 1;
+    }
+    widget.set_text_position(pos);
+}
+
+fn cursor_left(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    move_focused(ctx, move_left)
+}
+
+fn cursor_right(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    move_focused(ctx, move_right)
+}
+
+fn cursor_up(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    move_focused(ctx, move_up)
+}
+
+fn cursor_down(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    move_focused(ctx, move_down)
+}
+
+fn select_left(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    select_focused(ctx, move_left)
+}
+
+fn select_right(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    select_focused(ctx, move_right)
+}
+
+fn select_up(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    select_focused(ctx, move_up)
+}
+
+fn select_down(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    select_focused(ctx, move_down)
+}
+
+fn word_left(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    move_focused(ctx, move_word_left)
+}
+
+fn word_right(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    move_focused(ctx, move_word_right)
+}
+
+fn page_up(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    move_focused(ctx, |widget| {
+        for _i in 0..widget.get_height() - 1 {
+            move_up(widget);
+        }
+    })
+}
+
+fn page_down(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    move_focused(ctx, |widget| {
+        for _i in 0..widget.get_height() - 1 {
+            move_down(widget);
+        }
+    })
+}
+
+fn buffer_start(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    move_focused(ctx, |widget| widget.set_text_position(0))
+}
+
+fn buffer_end(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    move_focused(ctx, |widget| {
+        widget.set_text_position(widget.get_buffer().len_chars())
+    })
+}
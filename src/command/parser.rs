@@ -0,0 +1,674 @@
+use std::{fmt, path::PathBuf};
+
+use regex::Regex;
+use ropey::Rope;
+
+/// Type of value an argument accepts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArgKind {
+    String,
+    Path,
+    Int,
+    /// `/regex/`, anything else is searched literally
+    Regex,
+    Enum(&'static [&'static str]),
+    /// the raw remainder of the line, e.g. a shell command
+    Rest,
+}
+
+impl fmt::Display for ArgKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgKind::String => write!(f, "string"),
+            ArgKind::Path => write!(f, "path"),
+            ArgKind::Int => write!(f, "int"),
+            ArgKind::Regex => write!(f, "regex"),
+            ArgKind::Enum(choices) => write!(f, "{}", choices.join("|")),
+            ArgKind::Rest => write!(f, "text"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub optional: bool,
+}
+
+impl ArgSpec {
+    pub const fn required(name: &'static str, kind: ArgKind) -> Self {
+        Self {
+            name,
+            kind,
+            optional: false,
+        }
+    }
+
+    pub const fn optional(name: &'static str, kind: ArgKind) -> Self {
+        Self {
+            name,
+            kind,
+            optional: true,
+        }
+    }
+}
+
+impl fmt::Display for ArgSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.optional {
+            write!(f, "[{}]", self.name)
+        } else {
+            write!(f, "<{}>", self.name)
+        }
+    }
+}
+
+/// A typed argument
+#[derive(Clone, Debug)]
+pub enum Arg {
+    String(String),
+    Path(PathBuf),
+    Int(i64),
+    Regex(Regex),
+    Enum(String),
+    Rest(String),
+}
+
+impl Arg {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Arg::String(s) | Arg::Enum(s) | Arg::Rest(s) => s,
+            Arg::Path(p) => p.to_str().unwrap_or(""),
+            Arg::Int(_) => "",
+            Arg::Regex(r) => r.as_str(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    /// a bare or quoted word, quotes removed
+    Word(String),
+    /// the content of a `/regex/`
+    Regex(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Address {
+    /// 1-based line number
+    Line(usize),
+    /// `.`
+    Current,
+    /// `$`
+    Last,
+    /// `'<`, first line of the selection
+    SelectionStart,
+    /// `'>`, last line of the selection
+    SelectionEnd,
+}
+
+impl Address {
+    /// 0-based line index in `buffer`, `selection` holds the first and last selected lines
+    fn resolve(
+        &self,
+        buffer: &Rope,
+        current_line: usize,
+        selection: Option<(usize, usize)>,
+    ) -> Result<usize, ParseError> {
+        let last = buffer.len_lines().saturating_sub(1);
+        match *self {
+            Address::SelectionStart => selection
+                .map(|(start, _)| start)
+                .ok_or(ParseError::NoSelection),
+            Address::SelectionEnd => selection.map(|(_, end)| end).ok_or(ParseError::NoSelection),
+            Address::Line(0) => Err(ParseError::InvalidRange("0".to_string())),
+            Address::Line(n) if n - 1 > last => Err(ParseError::InvalidRange(n.to_string())),
+            Address::Line(n) => Ok(n - 1),
+            Address::Current => Ok(current_line),
+            Address::Last => Ok(last),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Range {
+    /// `%`
+    Whole,
+    Lines(Address, Address),
+}
+
+impl Range {
+    /// First and last 0-based line indexes covered by the range
+    pub fn resolve(
+        &self,
+        buffer: &Rope,
+        current_line: usize,
+        selection: Option<(usize, usize)>,
+    ) -> Result<(usize, usize), ParseError> {
+        match self {
+            Range::Whole => Ok((0, buffer.len_lines().saturating_sub(1))),
+            Range::Lines(start, end) => {
+                let start = start.resolve(buffer, current_line, selection)?;
+                let end = end.resolve(buffer, current_line, selection)?;
+                if start > end {
+                    return Err(ParseError::BackwardsRange);
+                }
+                Ok((start, end))
+            }
+        }
+    }
+}
+
+/// A command line split into its parts, before the arguments are typed
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParsedCommand {
+    pub range: Option<Range>,
+    /// empty when the line only holds a range, e.g. `:42`
+    pub name: String,
+    /// `:quit!`
+    pub bang: bool,
+    /// everything after the name, split by `tokenize` when binding
+    pub raw_args: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    NotACommand,
+    UnterminatedQuote,
+    UnterminatedRegex,
+    InvalidRange(String),
+    BackwardsRange,
+    NoSelection,
+    UnknownCommand(String),
+    RangeNotAllowed(String),
+    MissingArgument(&'static str),
+    TooManyArguments(String),
+    InvalidInt(String),
+    InvalidRegex(String),
+    InvalidChoice(String, &'static [&'static str]),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::NotACommand => write!(f, "Commands start with ':'"),
+            ParseError::UnterminatedQuote => write!(f, "Unterminated quote"),
+            ParseError::UnterminatedRegex => write!(f, "Unterminated regex, missing '/'"),
+            ParseError::InvalidRange(range) => write!(f, "Invalid range: {}", range),
+            ParseError::BackwardsRange => write!(f, "Backwards range"),
+            ParseError::NoSelection => write!(f, "No selection for '< or '>"),
+            ParseError::UnknownCommand(name) => write!(f, "Unknown command: {}", name),
+            ParseError::RangeNotAllowed(name) => write!(f, "{} does not accept a range", name),
+            ParseError::MissingArgument(name) => write!(f, "Missing argument: {}", name),
+            ParseError::TooManyArguments(arg) => write!(f, "Too many arguments: {}", arg),
+            ParseError::InvalidInt(value) => write!(f, "Not a number: {}", value),
+            ParseError::InvalidRegex(e) => write!(f, "Invalid regex: {}", e),
+            ParseError::InvalidChoice(value, choices) => {
+                write!(f, "Invalid value {}, expected {}", value, choices.join("|"))
+            }
+        }
+    }
+}
+
+fn parse_address(chars: &[char], idx: &mut usize) -> Result<Option<Address>, ParseError> {
+    match chars.get(*idx) {
+        Some('.') => {
+            *idx += 1;
+            Ok(Some(Address::Current))
+        }
+        Some('$') => {
+            *idx += 1;
+            Ok(Some(Address::Last))
+        }
+        Some('\'') => {
+            let address = match chars.get(*idx + 1) {
+                Some('<') => Address::SelectionStart,
+                Some('>') => Address::SelectionEnd,
+                _ => return Err(ParseError::InvalidRange(chars[*idx..].iter().collect())),
+            };
+            *idx += 2;
+            Ok(Some(address))
+        }
+        Some(c) if c.is_ascii_digit() => {
+            let start = *idx;
+            while *idx < chars.len() && chars[*idx].is_ascii_digit() {
+                *idx += 1;
+            }
+            let number: String = chars[start..*idx].iter().collect();
+            number
+                .parse()
+                .map(|n| Some(Address::Line(n)))
+                .map_err(|_| ParseError::InvalidRange(number))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn parse_range(chars: &[char], idx: &mut usize) -> Result<Option<Range>, ParseError> {
+    if chars.get(*idx) == Some(&'%') {
+        *idx += 1;
+        return Ok(Some(Range::Whole));
+    }
+    let start = match parse_address(chars, idx)? {
+        Some(address) => address,
+        None => return Ok(None),
+    };
+    if chars.get(*idx) != Some(&',') {
+        return Ok(Some(Range::Lines(start, start)));
+    }
+    *idx += 1;
+    match parse_address(chars, idx)? {
+        Some(end) => Ok(Some(Range::Lines(start, end))),
+        None => Err(ParseError::InvalidRange(chars[..*idx].iter().collect())),
+    }
+}
+
+/// Splits arguments on whitespace, handling quotes, escapes and `/regex/`
+pub fn tokenize(args: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = args.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let first = match chars.peek() {
+            Some(c) => *c,
+            None => break,
+        };
+        // A slash only starts a regex at the start of an argument
+        let word_start = chars.clone();
+        if first == '/' {
+            chars.next();
+            let mut pattern = String::new();
+            let mut terminated = false;
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' if chars.peek() == Some(&'/') => pattern.push(chars.next().unwrap()),
+                    '\\' => {
+                        pattern.push(c);
+                        if let Some(next) = chars.next() {
+                            pattern.push(next);
+                        }
+                    }
+                    '/' => {
+                        terminated = true;
+                        break;
+                    }
+                    _ => pattern.push(c),
+                }
+            }
+            if !terminated {
+                return Err(ParseError::UnterminatedRegex);
+            }
+            // `/etc/hosts` is a path, the closing slash of a regex ends the argument
+            if chars.peek().is_none_or(|c| c.is_whitespace()) {
+                tokens.push(Token::Regex(pattern));
+                continue;
+            }
+            chars = word_start;
+        }
+        let mut word = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            chars.next();
+            match c {
+                '"' => loop {
+                    match chars.next() {
+                        Some('\\') => match chars.next() {
+                            Some(escaped @ ('"' | '\\')) => word.push(escaped),
+                            Some(other) => {
+                                word.push('\\');
+                                word.push(other);
+                            }
+                            None => return Err(ParseError::UnterminatedQuote),
+                        },
+                        Some('"') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(ParseError::UnterminatedQuote),
+                    }
+                },
+                '\'' => loop {
+                    match chars.next() {
+                        Some('\\') if chars.peek() == Some(&'\'') => {
+                            word.push(chars.next().unwrap())
+                        }
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(ParseError::UnterminatedQuote),
+                    }
+                },
+                '\\' => {
+                    if let Some(escaped) = chars.next() {
+                        word.push(escaped);
+                    }
+                }
+                _ => word.push(c),
+            }
+        }
+        tokens.push(Token::Word(word));
+    }
+    Ok(tokens)
+}
+
+/// Parses `:[range]name[!] args` and `:[range]!shell command`
+pub fn parse(line: &str) -> Result<ParsedCommand, ParseError> {
+    let chars: Vec<char> = match line.strip_prefix(':') {
+        Some(rest) => rest.chars().collect(),
+        None => return Err(ParseError::NotACommand),
+    };
+    let mut idx = 0;
+    while idx < chars.len() && chars[idx].is_whitespace() {
+        idx += 1;
+    }
+    let range = parse_range(&chars, &mut idx)?;
+    // `:!cmd` and `:%!cmd`, the command runs the rest of the line through the shell
+    if chars.get(idx) == Some(&'!') {
+        let raw_args: String = chars[idx + 1..].iter().collect();
+        return Ok(ParsedCommand {
+            range,
+            name: "!".to_string(),
+            bang: false,
+            raw_args: raw_args.trim_start().to_string(),
+        });
+    }
+    let name_start = idx;
+    while idx < chars.len()
+        && (chars[idx].is_alphanumeric() || chars[idx] == '-' || chars[idx] == '_')
+    {
+        idx += 1;
+    }
+    let name: String = chars[name_start..idx].iter().collect();
+    let bang = chars.get(idx) == Some(&'!');
+    if bang {
+        idx += 1;
+    }
+    let raw_args: String = chars[idx..].iter().collect();
+    if !raw_args.is_empty() && !raw_args.starts_with(char::is_whitespace) {
+        return Err(ParseError::UnknownCommand(
+            chars[name_start..].iter().collect::<String>(),
+        ));
+    }
+    Ok(ParsedCommand {
+        range,
+        name,
+        bang,
+        raw_args: raw_args.trim_start().to_string(),
+    })
+}
+
+fn bind_arg(spec: &ArgSpec, token: &Token) -> Result<Arg, ParseError> {
+    match (spec.kind, token) {
+        (ArgKind::Regex, Token::Regex(pattern)) => Regex::new(pattern)
+            .map(Arg::Regex)
+            .map_err(|e| ParseError::InvalidRegex(e.to_string())),
+        (ArgKind::Regex, Token::Word(word)) => Regex::new(&regex::escape(word))
+            .map(Arg::Regex)
+            .map_err(|e| ParseError::InvalidRegex(e.to_string())),
+        (_, Token::Regex(pattern)) => bind_arg(spec, &Token::Word(format!("/{}/", pattern))),
+        (ArgKind::String, Token::Word(word)) => Ok(Arg::String(word.clone())),
+        (ArgKind::Rest, Token::Word(word)) => Ok(Arg::Rest(word.clone())),
+        (ArgKind::Path, Token::Word(word)) => Ok(Arg::Path(PathBuf::from(word))),
+        (ArgKind::Int, Token::Word(word)) => word
+            .parse()
+            .map(Arg::Int)
+            .map_err(|_| ParseError::InvalidInt(word.clone())),
+        (ArgKind::Enum(choices), Token::Word(word)) => {
+            if choices.contains(&word.as_str()) {
+                Ok(Arg::Enum(word.clone()))
+            } else {
+                Err(ParseError::InvalidChoice(word.clone(), choices))
+            }
+        }
+    }
+}
+
+/// Types the arguments of `command` according to `specs`
+///
+/// An `ArgKind::Rest` spec must come first, the line is then not tokenized.
+pub fn bind_args(specs: &[ArgSpec], command: &ParsedCommand) -> Result<Vec<Arg>, ParseError> {
+    let mut args = Vec::new();
+    if let Some(spec) = specs.first().filter(|spec| spec.kind == ArgKind::Rest) {
+        if !command.raw_args.is_empty() {
+            args.push(Arg::Rest(command.raw_args.clone()));
+        } else if !spec.optional {
+            return Err(ParseError::MissingArgument(spec.name));
+        }
+        return Ok(args);
+    }
+    let tokens = tokenize(&command.raw_args)?;
+    let mut tokens = tokens.iter();
+    for spec in specs {
+        match tokens.next() {
+            Some(token) => args.push(bind_arg(spec, token)?),
+            None if spec.optional => break,
+            None => return Err(ParseError::MissingArgument(spec.name)),
+        }
+    }
+    if let Some(extra) = tokens.next() {
+        let extra = match extra {
+            Token::Word(word) => word.clone(),
+            Token::Regex(pattern) => format!("/{}/", pattern),
+        };
+        return Err(ParseError::TooManyArguments(extra));
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_name_and_arguments() {
+        let command = parse(":open src/main.rs").unwrap();
+        assert_eq!(command.name, "open");
+        assert_eq!(command.range, None);
+        assert!(!command.bang);
+        assert_eq!(command.raw_args, "src/main.rs");
+    }
+
+    #[test]
+    fn slash_inside_a_word_is_not_a_regex() {
+        let tokens = tokenize("a/b/c /re/ x/").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Word("a/b/c".to_string()),
+                Token::Regex("re".to_string()),
+                Token::Word("x/".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn regex_keeps_spaces_and_escaped_slashes() {
+        let tokens = tokenize(r"/a b\/c\d/").unwrap();
+        assert_eq!(tokens, vec![Token::Regex(r"a b/c\d".to_string())]);
+    }
+
+    #[test]
+    fn quotes_are_removed() {
+        let tokens = tokenize(r#""hello world" 'it''s' "a \"b\"" c\ d"#).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Word("hello world".to_string()),
+                Token::Word("its".to_string()),
+                Token::Word("a \"b\"".to_string()),
+                Token::Word("c d".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_quote_and_regex_are_errors() {
+        assert_eq!(tokenize("\"abc"), Err(ParseError::UnterminatedQuote));
+        assert_eq!(tokenize("'abc"), Err(ParseError::UnterminatedQuote));
+        assert_eq!(tokenize("/abc"), Err(ParseError::UnterminatedRegex));
+        assert_eq!(
+            tokenize("/etc/hosts /a b/"),
+            Ok(vec![
+                Token::Word("/etc/hosts".to_string()),
+                Token::Regex("a b".to_string())
+            ])
+        );
+    }
+
+    #[test]
+    fn parses_ranges() {
+        let command = parse(":10,20write").unwrap();
+        assert_eq!(
+            command.range,
+            Some(Range::Lines(Address::Line(10), Address::Line(20)))
+        );
+        assert_eq!(command.name, "write");
+
+        let command = parse(":%sort").unwrap();
+        assert_eq!(command.range, Some(Range::Whole));
+
+        let command = parse(":.,$").unwrap();
+        assert_eq!(
+            command.range,
+            Some(Range::Lines(Address::Current, Address::Last))
+        );
+        assert_eq!(command.name, "");
+
+        let command = parse(":42").unwrap();
+        assert_eq!(
+            command.range,
+            Some(Range::Lines(Address::Line(42), Address::Line(42)))
+        );
+
+        let command = parse(":'<,'>!sort").unwrap();
+        assert_eq!(
+            command.range,
+            Some(Range::Lines(Address::SelectionStart, Address::SelectionEnd))
+        );
+
+        assert!(matches!(parse(":10,"), Err(ParseError::InvalidRange(_))));
+        assert!(matches!(parse(":'x"), Err(ParseError::InvalidRange(_))));
+    }
+
+    #[test]
+    fn resolves_ranges() {
+        let buffer = Rope::from_str("a\nb\nc\nd");
+        assert_eq!(Range::Whole.resolve(&buffer, 0, None), Ok((0, 3)));
+        let range = Range::Lines(Address::Line(2), Address::Last);
+        assert_eq!(range.resolve(&buffer, 0, None), Ok((1, 3)));
+        let range = Range::Lines(Address::Current, Address::Current);
+        assert_eq!(range.resolve(&buffer, 2, None), Ok((2, 2)));
+        let range = Range::Lines(Address::Line(3), Address::Line(1));
+        assert_eq!(
+            range.resolve(&buffer, 0, None),
+            Err(ParseError::BackwardsRange)
+        );
+        let range = Range::Lines(Address::Line(9), Address::Line(9));
+        assert!(range.resolve(&buffer, 0, None).is_err());
+        let range = Range::Lines(Address::SelectionStart, Address::SelectionEnd);
+        assert_eq!(range.resolve(&buffer, 0, Some((1, 2))), Ok((1, 2)));
+        assert_eq!(
+            range.resolve(&buffer, 0, None),
+            Err(ParseError::NoSelection)
+        );
+    }
+
+    #[test]
+    fn parses_bang() {
+        let command = parse(":quit!").unwrap();
+        assert_eq!(command.name, "quit");
+        assert!(command.bang);
+        assert!(matches!(
+            parse(":quit?"),
+            Err(ParseError::UnknownCommand(_))
+        ));
+    }
+
+    #[test]
+    fn parses_shell_filters() {
+        let command = parse(":%!jq .").unwrap();
+        assert_eq!(command.range, Some(Range::Whole));
+        assert_eq!(command.name, "!");
+        assert!(!command.bang);
+        assert_eq!(command.raw_args, "jq .");
+
+        let command = parse(":!sort -u").unwrap();
+        assert_eq!(command.range, None);
+        assert_eq!(command.name, "!");
+        assert_eq!(command.raw_args, "sort -u");
+
+        let command = parse(":r !date").unwrap();
+        assert_eq!(command.name, "r");
+        assert_eq!(command.raw_args, "!date");
+    }
+
+    #[test]
+    fn requires_leading_colon() {
+        assert_eq!(parse("ls -la"), Err(ParseError::NotACommand));
+    }
+
+    #[test]
+    fn binds_typed_arguments() {
+        static MODES: &[&str] = &["unix", "dos"];
+        let specs = [
+            ArgSpec::required("line", ArgKind::Int),
+            ArgSpec::required("mode", ArgKind::Enum(MODES)),
+            ArgSpec::optional("path", ArgKind::Path),
+        ];
+        let args = bind_args(&specs, &parse(":x 12 dos").unwrap()).unwrap();
+        assert_eq!(args.len(), 2);
+        assert!(matches!(args[0], Arg::Int(12)));
+        assert!(matches!(&args[1], Arg::Enum(mode) if mode == "dos"));
+
+        assert_eq!(
+            bind_args(&specs, &parse(":x twelve dos").unwrap()).unwrap_err(),
+            ParseError::InvalidInt("twelve".to_string())
+        );
+        assert_eq!(
+            bind_args(&specs, &parse(":x 1 mac").unwrap()).unwrap_err(),
+            ParseError::InvalidChoice("mac".to_string(), MODES)
+        );
+        assert_eq!(
+            bind_args(&specs, &parse(":x 1").unwrap()).unwrap_err(),
+            ParseError::MissingArgument("mode")
+        );
+        assert_eq!(
+            bind_args(&specs, &parse(":x 1 dos a b").unwrap()).unwrap_err(),
+            ParseError::TooManyArguments("b".to_string())
+        );
+    }
+
+    #[test]
+    fn literal_patterns_are_escaped() {
+        let specs = [ArgSpec::required("pattern", ArgKind::Regex)];
+        let args = bind_args(&specs, &parse(":find a.b").unwrap()).unwrap();
+        match &args[0] {
+            Arg::Regex(regex) => {
+                assert!(regex.is_match("a.b"));
+                assert!(!regex.is_match("axb"));
+            }
+            _ => panic!("expected a regex"),
+        }
+        let args = bind_args(&specs, &parse(":find /a.b/").unwrap()).unwrap();
+        assert!(matches!(&args[0], Arg::Regex(regex) if regex.is_match("axb")));
+        assert!(matches!(
+            bind_args(&specs, &parse(":find /(/").unwrap()),
+            Err(ParseError::InvalidRegex(_))
+        ));
+    }
+
+    #[test]
+    fn rest_takes_the_raw_line() {
+        let specs = [ArgSpec::required("command", ArgKind::Rest)];
+        let args = bind_args(&specs, &parse(":run echo 'a  b' | wc").unwrap()).unwrap();
+        assert_eq!(args[0].as_str(), "echo 'a  b' | wc");
+        let args = bind_args(&specs, &parse(":run echo it's").unwrap()).unwrap();
+        assert_eq!(args[0].as_str(), "echo it's");
+        assert_eq!(
+            bind_args(&specs, &parse(":run").unwrap()).unwrap_err(),
+            ParseError::MissingArgument("command")
+        );
+    }
+}
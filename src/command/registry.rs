@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use crate::{completion::Completer, editor::TextEditor, widget::widget::CursorPosition};
+
+use super::parser::{self, Arg, ArgSpec, ParseError, Range};
+
+/// What caused a command to run
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Trigger {
+    /// a key typed in the command line, only incremental commands run
+    Typing,
+    /// Enter in the command line
+    Submit,
+    /// a keybinding or the command palette
+    Key,
+}
+
+/// A command line once parsed and its arguments typed
+#[derive(Clone, Debug)]
+pub struct Invocation {
+    pub name: String,
+    pub range: Option<Range>,
+    pub bang: bool,
+    pub args: Vec<Arg>,
+}
+
+/// What a command handler gets to work with
+pub struct CommandContext<'a> {
+    pub editor: &'a mut TextEditor,
+    pub invocation: Invocation,
+    pub trigger: Trigger,
+}
+
+impl<'a> CommandContext<'a> {
+    pub fn arg(&self, idx: usize) -> Option<&Arg> {
+        self.invocation.args.get(idx)
+    }
+}
+
+pub type CommandHandler = fn(ctx: &mut CommandContext) -> Option<CursorPosition>;
+
+pub struct Command {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub description: String,
+    pub args: Vec<ArgSpec>,
+    pub accepts_range: bool,
+    /// runs on every key typed in the command line, not only on Enter
+    pub incremental: bool,
+    /// completes the arguments of the command
+    pub completer: Option<Completer>,
+    pub handler: CommandHandler,
+}
+
+impl Command {
+    pub fn new(name: &str, description: &str, args: Vec<ArgSpec>, handler: CommandHandler) -> Self {
+        Self {
+            name: name.to_string(),
+            aliases: Vec::new(),
+            description: description.to_string(),
+            args,
+            accepts_range: false,
+            incremental: false,
+            completer: None,
+            handler,
+        }
+    }
+
+    pub fn with_alias(mut self, alias: &str) -> Self {
+        self.aliases.push(alias.to_string());
+        self
+    }
+
+    pub fn with_range(mut self) -> Self {
+        self.accepts_range = true;
+        self
+    }
+
+    pub fn with_completer(mut self, completer: Completer) -> Self {
+        self.completer = Some(completer);
+        self
+    }
+
+    pub fn incremental(mut self) -> Self {
+        self.incremental = true;
+        self
+    }
+
+    pub fn usage(&self) -> String {
+        let mut usage = format!(":{}", self.name);
+        for arg in &self.args {
+            usage.push_str(&format!(" {}", arg));
+        }
+        usage
+    }
+}
+
+/// Every command the editor knows, looked up by name or alias
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: HashMap<String, Command>,
+    aliases: HashMap<String, String>,
+}
+
+impl CommandRegistry {
+    /// Adds a command, replacing any command registered under the same name
+    pub fn register(&mut self, command: Command) {
+        for alias in &command.aliases {
+            self.aliases.insert(alias.clone(), command.name.clone());
+        }
+        self.commands.insert(command.name.clone(), command);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Command> {
+        match self.aliases.get(name) {
+            Some(name) => self.commands.get(name),
+            None => self.commands.get(name),
+        }
+    }
+
+    /// Names of the commands, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.commands.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Parses `line` and types its arguments with the schema of the command
+    pub fn parse(&self, line: &str) -> Result<Invocation, ParseError> {
+        let parsed = parser::parse(line)?;
+        // `:42` jumps to line 42
+        let name = if parsed.name.is_empty() && parsed.range.is_some() {
+            "goto"
+        } else {
+            parsed.name.as_str()
+        };
+        let command = match self.get(name) {
+            Some(command) => command,
+            None => return Err(ParseError::UnknownCommand(name.to_string())),
+        };
+        if parsed.range.is_some() && !command.accepts_range {
+            return Err(ParseError::RangeNotAllowed(command.name.clone()));
+        }
+        let args = parser::bind_args(&command.args, &parsed)?;
+        Ok(Invocation {
+            name: command.name.clone(),
+            range: parsed.range,
+            bang: parsed.bang,
+            args,
+        })
+    }
+}
@@ -0,0 +1,133 @@
+use std::path::Path;
+
+use crate::{
+    file_io,
+    widget::{
+        terminal_pane::Placement,
+        widget::{CursorPosition, WidgetType},
+    },
+};
+
+use super::{
+    builtin::focus_panel,
+    keymap::Keymap,
+    parser::{ArgKind, ArgSpec},
+    registry::{Command, CommandContext, CommandRegistry},
+};
+
+pub fn register_shell_commands(registry: &mut CommandRegistry) {
+    registry.register(
+        Command::new(
+            "!",
+            "Filter the range, the selection or the buffer through a shell command",
+            vec![ArgSpec::required("command", ArgKind::Rest)],
+            filter,
+        )
+        .with_range(),
+    );
+    registry.register(
+        Command::new(
+            "read",
+            "Insert a file at the cursor, or the output of `!command`",
+            vec![ArgSpec::required("file", ArgKind::Rest)],
+            read,
+        )
+        .with_alias("r"),
+    );
+    registry.register(
+        Command::new(
+            "terminal",
+            "Show, focus or hide the terminal pane, at the bottom or in a split",
+            vec![ArgSpec::optional(
+                "placement",
+                ArgKind::Enum(Placement::NAMES),
+            )],
+            terminal,
+        )
+        .with_alias("term"),
+    );
+}
+
+pub fn bind_shell_keys(keymap: &mut Keymap) {
+    keymap.bind("ctrl+t", ":terminal");
+}
+
+fn filter(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let command = ctx.arg(0)?.as_str().trim().to_string();
+    let range = ctx.invocation.range;
+    let editor = &mut *ctx.editor;
+    if !editor.check_writable() {
+        return None;
+    }
+    let panel = editor.get_widget_mut(WidgetType::Panel)?;
+    let buffer = panel.get_buffer();
+    let (start, end) = match range {
+        // A range always covers whole lines
+        Some(range) => {
+            let current_line = buffer.char_to_line(panel.get_text_position());
+            let (first, last) =
+                match range.resolve(buffer, current_line, panel.get_selection_lines()) {
+                    Ok(lines) => lines,
+                    Err(e) => {
+                        editor.error(e.to_string());
+                        return None;
+                    }
+                };
+            let end = if last + 1 < buffer.len_lines() {
+                buffer.line_to_char(last + 1)
+            } else {
+                buffer.len_chars()
+            };
+            (buffer.line_to_char(first), end)
+        }
+        None => panel
+            .get_selection()
+            .unwrap_or((0, panel.get_buffer().len_chars())),
+    };
+    let id = panel.get_id();
+    if let Err(e) = editor.run_filter(&command, id, WidgetType::Panel, start, end) {
+        editor.error(format!("Failed to run `{}`: {}", command, e));
+    }
+    focus_panel(editor)
+}
+
+fn read(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let arg = ctx.arg(0)?.as_str().trim().to_string();
+    let editor = &mut *ctx.editor;
+    if !editor.check_writable() {
+        return None;
+    }
+    let panel = editor.get_widget_mut(WidgetType::Panel)?;
+    let position = panel.get_text_position();
+    let id = panel.get_id();
+    if let Some(command) = arg.strip_prefix('!') {
+        let command = command.trim();
+        if let Err(e) = editor.run_filter(command, id, WidgetType::Panel, position, position) {
+            editor.error(format!("Failed to run `{}`: {}", command, e));
+        }
+        return focus_panel(editor);
+    }
+    match file_io::read_file(Path::new(&arg)) {
+        Ok((content, _)) => {
+            panel.replace_range(position, position, &content);
+            editor.written = true;
+            editor.info(format!("Inserted {}", arg));
+        }
+        Err(e) => editor.error(e.to_string()),
+    }
+    focus_panel(editor)
+}
+
+fn terminal(ctx: &mut CommandContext) -> Option<CursorPosition> {
+    let placement = ctx
+        .arg(0)
+        .and_then(|placement| Placement::from_name(placement.as_str()));
+    let editor = &mut *ctx.editor;
+    match editor.toggle_terminal(placement) {
+        Ok(pos) => pos,
+        Err(e) => {
+            editor.error(e);
+            None
+        }
+    }
+}
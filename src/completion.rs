@@ -0,0 +1,190 @@
+use std::{env, fs, path::PathBuf};
+
+use crate::editor::TextEditor;
+use crate::encoding::{Encoding, LineEnding};
+
+/// Returns the candidates for the argument being typed.
+pub type Completer = fn(editor: &TextEditor, arg: &str) -> Vec<String>;
+
+#[derive(Clone, Debug, Default)]
+pub struct Completion {
+    /// char index in the command line where the completed word starts
+    pub start: usize,
+    pub candidates: Vec<String>,
+    /// the candidate currently inserted, None while only the common prefix is
+    pub selected: Option<usize>,
+}
+
+impl Completion {
+    pub fn new(start: usize, candidates: Vec<String>) -> Self {
+        Self {
+            start,
+            candidates,
+            selected: None,
+        }
+    }
+
+    pub fn next(&mut self) -> &str {
+        let idx = match self.selected {
+            Some(idx) if idx + 1 < self.candidates.len() => idx + 1,
+            _ => 0,
+        };
+        self.selected = Some(idx);
+        &self.candidates[idx]
+    }
+
+    pub fn prev(&mut self) -> &str {
+        let idx = match self.selected {
+            Some(idx) if idx > 0 => idx - 1,
+            _ => self.candidates.len() - 1,
+        };
+        self.selected = Some(idx);
+        &self.candidates[idx]
+    }
+}
+
+/// Longest prefix shared by every candidate.
+pub fn common_prefix(candidates: &[String]) -> String {
+    let mut prefix = match candidates.first() {
+        Some(first) => first.clone(),
+        None => return String::new(),
+    };
+    for candidate in &candidates[1..] {
+        let len = prefix
+            .chars()
+            .zip(candidate.chars())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a.len_utf8())
+            .sum();
+        prefix.truncate(len);
+    }
+    prefix
+}
+
+/// Files and directories matching `arg`, directories end with a `/`.
+pub fn complete_paths(_editor: &TextEditor, arg: &str) -> Vec<String> {
+    let (dir_part, file_prefix) = match arg.rfind('/') {
+        Some(idx) => (&arg[..idx + 1], &arg[idx + 1..]),
+        None => ("", arg),
+    };
+    let dir = if dir_part.is_empty() {
+        PathBuf::from(".")
+    } else if let Some(rest) = dir_part.strip_prefix("~/") {
+        PathBuf::from(env::var("HOME").unwrap_or_default()).join(rest)
+    } else {
+        PathBuf::from(dir_part)
+    };
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut candidates = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with(file_prefix) {
+            continue;
+        }
+        // Hidden files are only listed once the user typed the dot
+        if name.starts_with('.') && !file_prefix.starts_with('.') {
+            continue;
+        }
+        let mut candidate = format!("{}{}", dir_part, name);
+        if entry.path().is_dir() {
+            candidate.push('/');
+        }
+        candidates.push(candidate);
+    }
+    candidates.sort();
+    candidates
+}
+
+pub fn complete_themes(editor: &TextEditor, arg: &str) -> Vec<String> {
+    editor
+        .highlighter
+        .theme_names()
+        .into_iter()
+        .filter(|name| name.starts_with(arg))
+        .collect()
+}
+
+pub fn complete_filetypes(editor: &TextEditor, arg: &str) -> Vec<String> {
+    editor
+        .highlighter
+        .filetype_names()
+        .into_iter()
+        .filter(|name| name.starts_with(arg))
+        .collect()
+}
+
+pub fn complete_buffers(editor: &TextEditor, arg: &str) -> Vec<String> {
+    let mut candidates: Vec<String> = editor
+        .buffer_names()
+        .into_iter()
+        .filter(|name| name.starts_with(arg))
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+pub fn complete_commands(editor: &TextEditor, arg: &str) -> Vec<String> {
+    editor
+        .commands
+        .names()
+        .into_iter()
+        .filter(|name| name.starts_with(arg))
+        .collect()
+}
+
+/// Options of `:set` with their values, e.g. `fileformat=dos`
+pub fn complete_options(_editor: &TextEditor, arg: &str) -> Vec<String> {
+    let fileformats = LineEnding::NAMES
+        .iter()
+        .map(|name| format!("fileformat={}", name));
+    let encodings = Encoding::NAMES
+        .iter()
+        .map(|name| format!("fileencoding={}", name));
+    let flags = ["readonly", "noreadonly"]
+        .iter()
+        .map(|name| name.to_string());
+    fileformats
+        .chain(encodings)
+        .chain(flags)
+        .filter(|option| option.starts_with(arg))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn finds_common_prefixes() {
+        assert_eq!(common_prefix(&[]), "");
+        assert_eq!(common_prefix(&strings(&["theme"])), "theme");
+        assert_eq!(common_prefix(&strings(&["save", "saveas", "sav"])), "sav");
+        assert_eq!(common_prefix(&strings(&["write", "quit"])), "");
+        // Cut between chars, not inside one
+        assert_eq!(common_prefix(&strings(&["caf\u{e9}", "caf\u{e8}"])), "caf");
+    }
+
+    #[test]
+    fn cycles_through_candidates() {
+        let mut completion = Completion::new(3, strings(&["a", "b", "c"]));
+        assert_eq!(completion.selected, None);
+        assert_eq!(completion.next(), "a");
+        assert_eq!(completion.next(), "b");
+        assert_eq!(completion.next(), "c");
+        assert_eq!(completion.next(), "a");
+        assert_eq!(completion.prev(), "c");
+        assert_eq!(completion.prev(), "b");
+        assert_eq!(completion.selected, Some(1));
+
+        // Backwards from the common prefix starts at the last one
+        let mut completion = Completion::new(0, strings(&["a", "b"]));
+        assert_eq!(completion.prev(), "b");
+    }
+}
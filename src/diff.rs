@@ -0,0 +1,274 @@
+/// Lines kept around the changes of a hunk
+static CONTEXT: usize = 3;
+
+/// One line of the shortest edit turning the old text into the new one
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Edit<'a> {
+    Keep(&'a str),
+    Delete(&'a str),
+    Insert(&'a str),
+}
+
+/// Deleted and inserted lines beyond which the lines between the first and the
+/// last change are not diffed further, the memory of the search grows with
+/// their square
+static MAX_CHANGES: usize = 2000;
+
+/// Diffs `old` and `new` line by line with the Myers algorithm
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<Edit<'a>> {
+    diff_lines_bounded(old, new, MAX_CHANGES)
+}
+
+fn diff_lines_bounded<'a>(old: &'a str, new: &'a str, max_changes: usize) -> Vec<Edit<'a>> {
+    let a: Vec<&str> = old.split_inclusive('\n').collect();
+    let b: Vec<&str> = new.split_inclusive('\n').collect();
+    let prefix = a.iter().zip(&b).take_while(|(a, b)| a == b).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old, new) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    // Too many changes are all deleted, then all inserted
+    let changes = shortest_edits(old, new, max_changes).unwrap_or_else(|| {
+        let deleted = old.iter().map(|line| Edit::Delete(line));
+        deleted
+            .chain(new.iter().map(|line| Edit::Insert(line)))
+            .collect()
+    });
+    let kept = |lines: &[&'a str]| {
+        lines
+            .iter()
+            .map(|line| Edit::Keep(line))
+            .collect::<Vec<_>>()
+    };
+    let mut edits = kept(&a[..prefix]);
+    edits.extend(changes);
+    edits.extend(kept(&a[a.len() - suffix..]));
+    edits
+}
+
+/// The shortest edit turning the lines `a` into the lines `b`, `None` when it
+/// deletes and inserts more than `max_changes` lines
+///
+/// The time and the memory it takes grow with the number of changes, callers
+/// diffing on every key bound it.
+pub fn shortest_edits<'a>(
+    a: &[&'a str],
+    b: &[&'a str],
+    max_changes: usize,
+) -> Option<Vec<Edit<'a>>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let offset = n + m;
+    let max_d = max_changes.min(offset as usize) as isize;
+    let mut v = vec![0isize; 2 * offset as usize + 2];
+    // The furthest x of the diagonals k = x - y from -d to d, before each number
+    // of edits d
+    let mut trace = Vec::new();
+    let mut found = false;
+    'search: for d in 0..=max_d {
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let idx = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                found = true;
+                break 'search;
+            }
+        }
+    }
+    if !found {
+        return None;
+    }
+
+    // Walk back from the end through the furthest points of each d
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let furthest = |k: isize| v[(d + k) as usize];
+        let k = x - y;
+        // The first snake starts from the origin
+        let (prev_x, prev_y) = if d == 0 {
+            (0, 0)
+        } else {
+            let prev_k = if k == -d || (k != d && furthest(k - 1) < furthest(k + 1)) {
+                k + 1
+            } else {
+                k - 1
+            };
+            (furthest(prev_k), furthest(prev_k) - prev_k)
+        };
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Keep(a[x as usize - 1]));
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit::Insert(b[y as usize - 1]));
+            } else {
+                edits.push(Edit::Delete(a[x as usize - 1]));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    edits.reverse();
+    Some(edits)
+}
+
+/// The differences between `old` and `new` in the unified format of `diff -u`,
+/// empty when they are the same
+pub fn unified(old_name: &str, new_name: &str, old: &str, new: &str) -> String {
+    let edits = diff_lines(old, new);
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Keep(_)))
+        .map(|(idx, _)| idx)
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    // Changes closer than twice the context share a hunk
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for idx in changes {
+        let start = idx.saturating_sub(CONTEXT);
+        let end = (idx + CONTEXT + 1).min(edits.len());
+        match hunks.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    // Line numbers of both sides where each edit starts
+    let mut old_line = 1;
+    let mut new_line = 1;
+    let mut positions = Vec::with_capacity(edits.len());
+    for edit in &edits {
+        positions.push((old_line, new_line));
+        match edit {
+            Edit::Keep(_) => {
+                old_line += 1;
+                new_line += 1;
+            }
+            Edit::Delete(_) => old_line += 1,
+            Edit::Insert(_) => new_line += 1,
+        }
+    }
+    for (start, end) in hunks {
+        let hunk = &edits[start..end];
+        let old_len = hunk
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Insert(_)))
+            .count();
+        let new_len = hunk
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Delete(_)))
+            .count();
+        let (old_start, new_start) = positions[start];
+        // An empty side is numbered after the line it follows
+        let old_start = if old_len == 0 {
+            old_start - 1
+        } else {
+            old_start
+        };
+        let new_start = if new_len == 0 {
+            new_start - 1
+        } else {
+            new_start
+        };
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start, old_len, new_start, new_len
+        ));
+        for edit in hunk {
+            let (prefix, line) = match edit {
+                Edit::Keep(line) => (' ', line),
+                Edit::Delete(line) => ('-', line),
+                Edit::Insert(line) => ('+', line),
+            };
+            out.push(prefix);
+            out.push_str(line);
+            if !line.ends_with('\n') {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_shortest_edits() {
+        assert_eq!(diff_lines("", ""), vec![]);
+        assert_eq!(
+            diff_lines("a\nb\nc\n", "a\nc\nd\n"),
+            vec![
+                Edit::Keep("a\n"),
+                Edit::Delete("b\n"),
+                Edit::Keep("c\n"),
+                Edit::Insert("d\n"),
+            ]
+        );
+        assert_eq!(diff_lines("", "a"), vec![Edit::Insert("a")]);
+        assert_eq!(diff_lines("a\n", ""), vec![Edit::Delete("a\n")]);
+        let edits = diff_lines("a\nb\nc\na\nb\nb\na\n", "c\nb\na\nb\na\nc\n");
+        let changes = edits
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Keep(_)))
+            .count();
+        assert_eq!(changes, 5);
+        assert_eq!(shortest_edits(&["a\n"], &["b\n"], 1), None);
+        assert_eq!(
+            shortest_edits(&["a\n"], &["b\n"], 2),
+            Some(vec![Edit::Delete("a\n"), Edit::Insert("b\n")])
+        );
+        // Past the bound the changed lines are replaced as a whole
+        assert_eq!(
+            diff_lines_bounded("a\nb\nc\nd\n", "a\nc\nb\nd\n", 1),
+            vec![
+                Edit::Keep("a\n"),
+                Edit::Delete("b\n"),
+                Edit::Delete("c\n"),
+                Edit::Insert("c\n"),
+                Edit::Insert("b\n"),
+                Edit::Keep("d\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_unified_diffs() {
+        assert_eq!(unified("a", "b", "same\n", "same\n"), "");
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\n11\n";
+        assert_eq!(
+            unified("old", "new", old, new),
+            "--- old\n+++ new\n\
+             @@ -1,6 +1,6 @@\n 1\n 2\n-3\n+three\n 4\n 5\n 6\n\
+             @@ -8,3 +8,4 @@\n 8\n 9\n 10\n+11\n"
+        );
+        assert_eq!(
+            unified("old", "new", "", "new"),
+            "--- old\n+++ new\n@@ -0,0 +1,1 @@\n+new\n\\ No newline at end of file\n"
+        );
+    }
+}
@@ -1,29 +1,103 @@
-use std::{collections::LinkedList, path::PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use ropey::Rope;
 
 use crossterm::{
-    cursor,
-    event::{Event, KeyModifiers},
-    execute, queue, terminal,
+    event::Event,
+    style::{Color, ContentStyle},
 };
-use std::io::{stdout, Write};
+use syntect::LoadingError;
 
 use crate::{
-    action::Action,
-    widget::widget::{ProcessEvent, WidgetType},
+    backend::{Backend, CrosstermBackend},
+    cli::FileArg,
+    command::{
+        builtin::{self, Search},
+        fold, git,
+        keymap::Keymap,
+        movement,
+        parser::ParseError,
+        registry::{CommandContext, CommandRegistry, Trigger},
+        shell,
+    },
+    encoding::{self, FileFormat},
+    file_io::{self, FileError},
+    filter::Filter,
+    highlight::Highlighter,
+    job::{JobEvent, JobManager},
+    large_file,
+    message::{Level, Messages},
+    swap::{self, SwapFiles},
+    watch::{FileChange, FileWatch},
+    widget::{
+        popup::Popup,
+        prompt::{Answer, Choice, Prompt},
+        terminal_pane::{Placement, TerminalPane},
+        widget::{BorderStyle, ProcessEvent, RenderContext, WidgetType},
+    },
 };
 
 use super::widget::widget::CursorPosition;
 
+/// Longer messages are cut, `:messages` shows them whole
+static MAX_MESSAGE_LINES: usize = 5;
+
 pub struct TextEditor {
     /// save_path
     pub save_path: PathBuf,
+    /// encoding and line endings the buffer is saved with
+    pub file_format: FileFormat,
+    /// the file is too big to be highlighted, backed up or searched all at once
+    pub large_file: bool,
+    /// edits are refused, `:w <path>` still saves a copy
+    pub readonly: bool,
+    /// where the histories of the command line and the prompts are kept, none
+    /// keeping them for the session only
+    pub history_dir: Option<PathBuf>,
     pub written: bool,
     pub saved: bool,
 
     pub running: bool,
+    /// what commands have to tell the user
+    pub messages: Messages,
+
+    /// `:` commands, also run by keybindings
+    pub commands: CommandRegistry,
+    pub keymap: Keymap,
+    /// command lines to run once every widget handled the event
+    pending_commands: Vec<String>,
+    pub search: Search,
+    /// popup showing the output of the last command
+    pub output_popup: Option<(WidgetType, usize)>,
+    /// shell commands running in the background
+    pub jobs: JobManager,
+    /// job whose output streams into the output popup, and what it printed so far
+    output_job: Option<(usize, String)>,
+    /// jobs whose output will replace part of a buffer
+    filters: Vec<Filter>,
+    /// where the terminal pane goes when shown
+    pub terminal_placement: Placement,
+    /// the terminal pane while hidden, its shell keeps running
+    hidden_terminal: Option<Box<dyn ProcessEvent>>,
+    /// backups of the unsaved buffer
+    pub swap: SwapFiles,
+    /// notices when another program changes the file of the buffer
+    pub file_watch: FileWatch,
+    /// the files given on the command line or opened since, with where the cursor
+    /// was left, switched to with `:buffer`
+    pub buffers: Vec<FileArg>,
 
     pub width: usize,
     pub height: usize,
+    /// where the widgets are drawn
+    pub backend: Box<dyn Backend>,
+    /// the syntaxes and themes the widgets are drawn with
+    pub highlighter: Arc<Highlighter>,
+    /// the filetype set with `:setfiletype`, kept until another file is opened
+    pub filetype: Option<String>,
     /// widgets
     widgets: Vec<Box<dyn ProcessEvent>>,
     new_widgets: Vec<Box<dyn ProcessEvent>>,
@@ -34,9 +108,38 @@ pub struct TextEditor {
 
 impl TextEditor {
     pub fn new(save_path: &PathBuf, width: usize, height: usize) -> Self {
+        let mut commands = CommandRegistry::default();
+        builtin::register_builtin_commands(&mut commands);
+        movement::register_movement_commands(&mut commands);
+        fold::register_fold_commands(&mut commands);
+        git::register_git_commands(&mut commands);
+        shell::register_shell_commands(&mut commands);
+        let mut keymap = Keymap::default();
+        builtin::bind_default_keys(&mut keymap);
+        movement::bind_movement_keys(&mut keymap);
+        fold::bind_fold_keys(&mut keymap);
+        shell::bind_shell_keys(&mut keymap);
         Self {
             running: true,
+            messages: Messages::default(),
+            commands,
+            keymap,
+            pending_commands: Vec::new(),
+            search: Search::default(),
+            output_popup: None,
+            jobs: JobManager::new(),
+            output_job: None,
+            filters: Vec::new(),
+            terminal_placement: Placement::Bottom,
+            hidden_terminal: None,
+            swap: SwapFiles::new(),
+            file_watch: FileWatch::new(save_path),
+            buffers: Vec::new(),
             save_path: save_path.clone(),
+            file_format: FileFormat::default(),
+            large_file: false,
+            readonly: false,
+            history_dir: None,
             widgets: Vec::new(),
             new_widgets: Vec::new(),
             focused_widget_id: 0,
@@ -46,6 +149,9 @@ impl TextEditor {
             biggest_id: 0,
             width,
             height,
+            backend: Box::new(CrosstermBackend),
+            highlighter: Highlighter::shared(),
+            filetype: None,
         }
     }
 
@@ -54,7 +160,7 @@ impl TextEditor {
         let idx = self.biggest_id;
         self.biggest_id += 1;
         self.widgets.last_mut().unwrap().set_id(idx);
-        eprintln!(
+        log::debug!(
             "add_widget: {}, type {}",
             idx,
             self.widgets.last().unwrap().get_type()
@@ -69,11 +175,637 @@ impl TextEditor {
         } else if self.widgets.last().unwrap().get_focused() {
             self.focused_widget_id = idx;
         }
-        execute!(stdout(), cursor::MoveTo(pos.0 as u16, pos.1 as u16)).unwrap();
-        stdout().flush().unwrap();
+        self.backend.move_to(pos.0 as u16, pos.1 as u16);
+        self.backend.flush();
         return idx;
     }
 
+    /// Parses and runs a `:` command line
+    pub fn execute_command_line(
+        &mut self,
+        line: &str,
+        trigger: Trigger,
+    ) -> Result<Option<CursorPosition>, ParseError> {
+        let invocation = self.commands.parse(line)?;
+        let command = self.commands.get(&invocation.name).unwrap();
+        if trigger == Trigger::Typing && !command.incremental {
+            return Ok(None);
+        }
+        let handler = command.handler;
+        let mut ctx = CommandContext {
+            editor: self,
+            invocation,
+            trigger,
+        };
+        Ok(handler(&mut ctx))
+    }
+
+    /// Tells the user something went well
+    pub fn info(&mut self, text: impl Into<String>) {
+        self.messages.push(Level::Info, text);
+    }
+
+    pub fn warn(&mut self, text: impl Into<String>) {
+        self.messages.push(Level::Warning, text);
+    }
+
+    /// Tells the user something failed, shown until a key is pressed
+    pub fn error(&mut self, text: impl Into<String>) {
+        self.messages.push(Level::Error, text);
+    }
+
+    /// Asks `label` on the command line row, the answer runs its command lines
+    /// and the focused widget gets the keys back
+    pub fn prompt(&mut self, label: &str, answer: Answer) -> CursorPosition {
+        let return_focus = self.focused_widget_id;
+        let return_cursor = match self.focused_widget_mut() {
+            Some(widget) => {
+                widget.set_focused(false);
+                widget.get_cursor_view()
+            }
+            None => (0, 0),
+        };
+        let prompt = Prompt::new(self, label, answer, return_focus, return_cursor);
+        let cursor_position = prompt.get_cursor_view();
+        self.add_widget(prompt);
+        cursor_position
+    }
+
+    /// Asks whether to save the changes of the panel before running `then`,
+    /// which runs with a `!` when they are discarded
+    pub fn ask_to_save(&mut self, then: &str) -> CursorPosition {
+        let question = format!(
+            "Save changes to {}? [y]es/[n]o/[c]ancel",
+            self.save_path.display()
+        );
+        let (name, args) = then.split_once(' ').unwrap_or((then, ""));
+        let discard = format!("{}! {}", name, args);
+        let choices = vec![
+            Choice::new('y', &[":save", then]),
+            Choice::new('n', &[discard.trim_end()]),
+            Choice::new('c', &[]),
+        ];
+        self.prompt(&question, Answer::Choices(choices))
+    }
+
+    /// Runs `line` with the keybinding trigger once the current event has been handled
+    pub fn queue_command(&mut self, line: &str) {
+        self.pending_commands.push(line.to_string());
+    }
+
+    /// Runs a shell command in the background, its output streams into the output popup
+    pub fn run_shell(&mut self, command: &str) -> Option<CursorPosition> {
+        match self.jobs.spawn(command, None) {
+            Ok(id) => {
+                self.output_job = Some((id, String::new()));
+                Some(self.show_output_popup(String::new(), Some("sh")))
+            }
+            Err(e) => {
+                self.error(format!("Failed to run `{}`: {}", command, e));
+                None
+            }
+        }
+    }
+
+    /// Pipes the chars `start..end` of a widget to a shell command, its stdout replaces
+    /// them once it exits successfully
+    ///
+    /// With `start == end` nothing is piped and the output is inserted.
+    pub fn run_filter(
+        &mut self,
+        command: &str,
+        widget_id: usize,
+        widget_type: WidgetType,
+        start: usize,
+        end: usize,
+    ) -> Result<(), String> {
+        let widget = self
+            .get_widget_id_mut(widget_id, widget_type)
+            .ok_or("no such widget")?;
+        let original = widget.get_buffer().slice(start..end).to_string();
+        let input = if start == end {
+            None
+        } else {
+            Some(original.clone())
+        };
+        let job = self.jobs.spawn(command, input)?;
+        self.filters.push(Filter {
+            job,
+            widget_id,
+            widget_type,
+            start,
+            end,
+            original,
+            stdout: String::new(),
+            stderr: String::new(),
+        });
+        Ok(())
+    }
+
+    /// Collects what the jobs sent since the last tick and redraws if needed
+    ///
+    /// Called by the main loop between events, also turns the spinner.
+    pub fn tick(&mut self) {
+        self.update_swap();
+        let mut redraw = self.check_file_changes();
+        redraw |= self.messages.expire();
+        for widget in &mut self.widgets {
+            redraw |= widget.tick();
+        }
+        if let Some(terminal) = &mut self.hidden_terminal {
+            terminal.tick();
+            if terminal.is_closed() {
+                self.hidden_terminal = None;
+            }
+        }
+        redraw |= self.remove_closed_widgets();
+        let was_running = self.jobs.is_running();
+        let updates = self.jobs.poll();
+        if updates.is_empty() && !was_running && !redraw {
+            return;
+        }
+        for update in updates {
+            if let Some(idx) = self.filters.iter().position(|f| f.job == update.id) {
+                if self.filters[idx].update(&update.event) {
+                    let filter = self.filters.remove(idx);
+                    filter.finish(self, &update.command, update.event);
+                }
+                continue;
+            }
+            let shown = matches!(&self.output_job, Some((id, _)) if *id == update.id);
+            let (text, message) = match update.event {
+                JobEvent::Stdout(text) | JobEvent::Stderr(text) => (text, None),
+                JobEvent::Exited(Some(code)) => (
+                    format!("[exit status {}]\n", code),
+                    Some((
+                        if code == 0 {
+                            Level::Info
+                        } else {
+                            Level::Warning
+                        },
+                        format!("Exit status {}: `{}`", code, update.command),
+                    )),
+                ),
+                JobEvent::Exited(None) => (
+                    "[killed]\n".to_string(),
+                    Some((Level::Warning, format!("Killed: `{}`", update.command))),
+                ),
+                JobEvent::Failed(e) => (
+                    format!("[failed: {}]\n", e),
+                    Some((
+                        Level::Error,
+                        format!("Failed to run `{}`: {}", update.command, e),
+                    )),
+                ),
+            };
+            if shown {
+                if let Some((_, output)) = &mut self.output_job {
+                    // The exit status goes on its own line
+                    if message.is_some() && !output.is_empty() && !output.ends_with('\n') {
+                        output.push('\n');
+                    }
+                    output.push_str(&text);
+                    let output = output.clone();
+                    self.update_output_popup(output);
+                }
+            }
+            if let Some((level, message)) = message {
+                self.messages.push(level, message);
+            }
+        }
+        self.refresh();
+        // The terminal moves its cursor by itself
+        let (cursor_position, is_cursor_visible) = match self.focused_widget_mut() {
+            Some(widget) if widget.get_type() == WidgetType::Terminal => {
+                (widget.get_cursor_view(), widget.is_cursor_visible())
+            }
+            _ => (self.old_cursor_position, true),
+        };
+        self.render(cursor_position, is_cursor_visible);
+    }
+
+    /// Backs the panel up while it has unsaved changes
+    fn update_swap(&mut self) {
+        // Writing gigabytes every few seconds would cost more than it saves
+        if self.large_file || self.save_path.as_os_str().is_empty() {
+            return;
+        }
+        let text = match self.get_widget(WidgetType::Panel) {
+            Some(panel) => panel.get_buffer().clone(),
+            None => return,
+        };
+        if self.written {
+            self.swap.update(&self.save_path, &text);
+        } else {
+            self.swap.remove(&self.save_path);
+        }
+    }
+
+    /// Whether the panel may be edited, tells why not in the status bar
+    pub fn check_writable(&mut self) -> bool {
+        if self.readonly {
+            self.warn("Read-only buffer");
+        }
+        !self.readonly
+    }
+
+    /// Offers to recover the buffer when its swap file is newer than the file
+    pub fn check_swap(&mut self) {
+        // Text read from stdin has no file, nor swap file
+        if self.save_path.as_os_str().is_empty() {
+            return;
+        }
+        let swap = match swap::recoverable(&self.save_path) {
+            Some(swap) => swap,
+            None => return,
+        };
+        self.show_output_popup(
+            format!(
+                "{} is newer than {}, the editor may have crashed.\n\
+                 Recover it? Enter loads it, :recover! deletes it, Esc keeps it for later\n",
+                swap.display(),
+                self.save_path.display()
+            ),
+            None,
+        );
+        self.queue_command(":command-line :recover");
+    }
+
+    /// Reloads the panel when another program changed its file, or asks what to do
+    /// when it has unsaved changes, returns whether there was a change
+    fn check_file_changes(&mut self) -> bool {
+        let change = match self.file_watch.poll() {
+            Some(change) => change,
+            None => return false,
+        };
+        let path = self.save_path.display().to_string();
+        match change {
+            FileChange::Deleted => {
+                self.warn(format!("{} was deleted", path));
+            }
+            FileChange::Modified if !self.written => match self.reload() {
+                Ok(_) => self.info(format!("Reloaded {}", path)),
+                Err(e) => self.error(e.to_string()),
+            },
+            FileChange::Modified => {
+                self.show_output_popup(
+                    format!(
+                        "{} was changed by another program.\n\
+                         Reload it? Enter discards your changes, :diff shows them, Esc keeps them\n",
+                        path
+                    ),
+                    None,
+                );
+                if let Ok(Some(pos)) =
+                    self.execute_command_line(":command-line :reload!", Trigger::Key)
+                {
+                    self.old_cursor_position = pos;
+                }
+            }
+        }
+        true
+    }
+
+    /// Reads the file of the panel again, the cursor stays
+    pub fn reload(&mut self) -> Result<Option<CursorPosition>, FileError> {
+        let (text, format) = file_io::read_rope(&self.save_path)?;
+        self.file_format = format;
+        self.large_file = large_file::is_large(&self.save_path);
+        self.written = false;
+        self.file_watch.reset(&self.save_path);
+        let panel = match self.get_widget_mut(WidgetType::Panel) {
+            Some(panel) => panel,
+            None => return Ok(None),
+        };
+        let position = panel.get_text_position();
+        panel.set_buffer(text);
+        panel.set_text_position(position.min(panel.get_buffer().len_chars()));
+        let pos = panel.update_cursor_position_and_view();
+        // The new text may have gained a shebang or a modeline, or been committed
+        let path = self.save_path.clone();
+        self.detect_filetype(&path);
+        self.diff_with_head(&path);
+        Ok(Some(pos))
+    }
+
+    /// Drops the widgets asking to be closed, returns whether there were some
+    fn remove_closed_widgets(&mut self) -> bool {
+        let closed: Vec<(usize, WidgetType)> = self
+            .widgets
+            .iter()
+            .filter(|widget| widget.is_closed())
+            .map(|widget| (widget.get_id(), widget.get_type()))
+            .collect();
+        for (id, typ) in &closed {
+            self.remove_widget_id(*id, *typ);
+            if self.focused_widget_id == *id {
+                self.focus_panel();
+            }
+        }
+        if !closed.is_empty() {
+            self.layout();
+        }
+        !closed.is_empty()
+    }
+
+    /// Gives the focus back to the panel
+    fn focus_panel(&mut self) -> Option<CursorPosition> {
+        let panel = self.get_widget_mut(WidgetType::Panel)?;
+        panel.set_focused(true);
+        let pos = panel.update_cursor_position_and_view();
+        self.focused_widget_id = panel.get_id();
+        Some(pos)
+    }
+
+    /// Where the terminal pane goes: (x, y, width, height)
+    fn terminal_geometry(&self) -> (usize, usize, usize, usize) {
+        // The status bar and the command line stay under everything
+        let height = self.height.saturating_sub(2);
+        match self.terminal_placement {
+            Placement::Bottom => {
+                let terminal_height = (height / 3).max(3).min(height);
+                (0, height - terminal_height, self.width, terminal_height)
+            }
+            Placement::Split => (self.width / 2, 0, self.width - self.width / 2, height),
+        }
+    }
+
+    /// Shares the screen between the panel, its line numbers and the terminal pane
+    pub fn layout(&mut self) {
+        let line_number_width = self
+            .get_widget(WidgetType::LineNumber)
+            .map_or(0, |line_number| line_number.get_width());
+        let mut panel_width = self.width.saturating_sub(line_number_width);
+        let mut panel_height = self.height.saturating_sub(2);
+        let (x, y, width, height) = self.terminal_geometry();
+        if let Some(terminal) = self.get_widget_mut(WidgetType::Terminal) {
+            terminal.set_x(x);
+            terminal.set_y(y);
+            terminal.set_width(width);
+            terminal.set_height(height);
+            terminal.update_cursor_position_and_view();
+            match self.terminal_placement {
+                Placement::Bottom => panel_height = y,
+                Placement::Split => panel_width = x.saturating_sub(line_number_width),
+            }
+        }
+        if let Some(line_number) = self.get_widget_mut(WidgetType::LineNumber) {
+            line_number.set_height(panel_height);
+        }
+        if let Some(panel) = self.get_widget_mut(WidgetType::Panel) {
+            panel.set_width(panel_width);
+            panel.set_height(panel_height);
+            panel.update_cursor_position_and_view();
+        }
+    }
+
+    /// Shows the terminal pane, starting a shell the first time, focuses it, or
+    /// hides it when it already has the focus
+    ///
+    /// A new `placement` moves the pane instead of hiding it.
+    pub fn toggle_terminal(
+        &mut self,
+        placement: Option<Placement>,
+    ) -> Result<Option<CursorPosition>, String> {
+        let moved = placement.is_some_and(|placement| placement != self.terminal_placement);
+        if let Some(placement) = placement {
+            self.terminal_placement = placement;
+        }
+        if let Some(terminal) = self.get_widget(WidgetType::Terminal) {
+            let id = terminal.get_id();
+            if self.focused_widget_id == id && !moved {
+                self.hidden_terminal = self.remove_widget_id(id, WidgetType::Terminal);
+                self.layout();
+                return Ok(self.focus_panel());
+            }
+            self.layout();
+            return Ok(self.focus_terminal());
+        }
+        let terminal = match self.hidden_terminal.take() {
+            Some(terminal) => terminal,
+            None => {
+                let (x, y, width, height) = self.terminal_geometry();
+                let shell = std::env::var("SHELL").unwrap_or("sh".to_string());
+                let mut terminal = TerminalPane::new(
+                    shell,
+                    x,
+                    y,
+                    width,
+                    height,
+                    Color::White,
+                    Color::Reset,
+                    false,
+                    true,
+                    BorderStyle::None,
+                );
+                terminal
+                    .start()
+                    .map_err(|e| format!("Failed to start {}: {}", terminal.command, e))?;
+                terminal
+            }
+        };
+        self.add_widget(terminal);
+        self.layout();
+        Ok(self.focus_terminal())
+    }
+
+    fn focus_terminal(&mut self) -> Option<CursorPosition> {
+        if let Some(focused) = self.focused_widget_mut() {
+            focused.set_focused(false);
+        }
+        let terminal = self.get_widget_mut(WidgetType::Terminal)?;
+        terminal.set_focused(true);
+        let pos = terminal.update_cursor_position_and_view();
+        self.focused_widget_id = terminal.get_id();
+        Some(pos)
+    }
+
+    /// Position and size of the output popup for `text`: (y, width, height)
+    fn output_popup_geometry(&self, text: &str) -> (usize, usize, usize) {
+        let mut new_line_count = 0;
+        let mut max_len = 0;
+        let mut curr_len = 0;
+        text.chars().for_each(|c| {
+            curr_len += 1;
+            if c == '\n' {
+                if curr_len > max_len {
+                    max_len = curr_len;
+                }
+                curr_len = 0;
+                new_line_count += 1;
+            }
+        });
+
+        new_line_count += 1;
+        // The last line may not end with a newline, and empty output still needs a box
+        max_len = max_len.max(curr_len + 1).max(3);
+        let command_line_y = self.height as i32 - 1;
+        let y = if command_line_y - new_line_count - 2 < 0 {
+            0
+        } else {
+            command_line_y - new_line_count - 2
+        };
+        if new_line_count > self.height as i32 - 2 {
+            new_line_count = self.height as i32 - 2;
+        }
+        max_len = max_len.min(self.width);
+        (y as usize, max_len, new_line_count as usize)
+    }
+
+    /// Shows `text` in a popup above the command line, highlighted with the syntax of `extension`
+    pub fn show_output_popup(&mut self, text: String, extension: Option<&str>) -> CursorPosition {
+        // Delete the old popup
+        if let Some((typ, id)) = self.output_popup {
+            self.remove_widget_id(id, typ);
+            self.output_popup = None;
+        }
+        let (y, width, height) = self.output_popup_geometry(&text);
+        let mut widget = Popup::new(
+            text,
+            0,
+            y,
+            width,
+            height,
+            Color::Grey,
+            Color::Blue,
+            true,
+            true,
+            BorderStyle::Dashed,
+        );
+        let syntax = extension
+            .and_then(|extension| self.highlighter.find_syntax_by_extension(extension))
+            .unwrap_or(self.highlighter.plain_text());
+        widget.set_syntax(Some(syntax));
+        widget.set_theme(Some("base16-eighties.dark".to_string()));
+        widget.set_z_idx(10);
+        let new_position = widget.get_cursor_view();
+        self.output_popup = Some((WidgetType::Popup, self.add_widget(widget)));
+        new_position
+    }
+
+    /// Replaces the text of the output popup, following its end, if it is still open
+    fn update_output_popup(&mut self, text: String) {
+        let (typ, id) = match self.output_popup {
+            Some(popup) => popup,
+            None => return,
+        };
+        let (y, width, height) = self.output_popup_geometry(&text);
+        if let Some(popup) = self.get_widget_id_mut(id, typ) {
+            popup.set_buffer(Rope::from_str(&text));
+            popup.set_y(y);
+            popup.set_width(width);
+            popup.set_height(height);
+            // The empty line after the last newline is not worth scrolling to
+            let trailing = if text.ends_with('\n') { 2 } else { 1 };
+            let last_line = popup.get_buffer().len_lines().saturating_sub(trailing);
+            let last_line_start = popup.get_buffer().line_to_char(last_line);
+            popup.set_text_position(last_line_start);
+            popup.update_cursor_position_and_view();
+        }
+    }
+
+    /// Names of the buffers that can be switched to
+    pub fn buffer_names(&self) -> Vec<String> {
+        self.buffers
+            .iter()
+            .map(|buffer| buffer.path.to_string_lossy().to_string())
+            .collect()
+    }
+
+    /// Keeps where the cursor is in the panel's buffer, for when it is shown again
+    pub fn remember_position(&mut self) {
+        let (line, column) = match self.get_widget(WidgetType::Panel) {
+            Some(panel) => {
+                let buffer = panel.get_buffer();
+                let position = panel.get_text_position().min(buffer.len_chars());
+                let line = buffer.char_to_line(position);
+                (line + 1, position - buffer.line_to_char(line) + 1)
+            }
+            None => return,
+        };
+        let path = self.save_path.clone();
+        if let Some(buffer) = self.buffers.iter_mut().find(|buffer| buffer.path == path) {
+            buffer.line = Some(line);
+            buffer.column = Some(column);
+        }
+    }
+
+    /// Adds the `.sublime-syntax` files of `dir` to the syntaxes of this editor,
+    /// the panel is highlighted again in case one of them knows its file
+    pub fn add_syntaxes(&mut self, dir: &Path) -> Result<(), LoadingError> {
+        Arc::make_mut(&mut self.highlighter).add_syntaxes(dir)?;
+        // The added syntaxes were linked again, or may win over those of the widgets
+        for widget in &mut self.widgets {
+            let name = match widget.get_syntax() {
+                Some(syntax) => syntax.name.clone(),
+                None => continue,
+            };
+            let syntax = self.highlighter.find_syntax_by_name(&name);
+            widget.set_syntax(syntax);
+        }
+        let path = self.save_path.clone();
+        self.detect_filetype(&path);
+        Ok(())
+    }
+
+    /// Highlights the panel with the syntax of the filetype set by `:setfiletype`,
+    /// or else with the syntax detected for its text as the file `path`
+    pub fn detect_filetype(&mut self, path: &Path) {
+        let highlighter = self.highlighter.clone();
+        let filetype = self.filetype.clone();
+        let large_file = self.large_file;
+        let panel = match self.get_widget_mut(WidgetType::Panel) {
+            Some(panel) => panel,
+            None => return,
+        };
+        let syntax = match filetype.and_then(|name| highlighter.syntax_for_filetype(&name)) {
+            Some(syntax) => syntax,
+            // Large files are not highlighted
+            None if large_file => highlighter.plain_text(),
+            None => highlighter.syntax_for(path, panel.get_buffer()),
+        };
+        log::debug!("highlighting {} as {}", path.display(), syntax.name);
+        panel.set_syntax(Some(syntax));
+    }
+
+    /// Diffs the panel against the file `path` as it is at HEAD, to mark the lines
+    /// changed since the last commit
+    pub fn diff_with_head(&mut self, path: &Path) {
+        // Large files are not diffed
+        let head = if self.large_file || path.as_os_str().is_empty() {
+            None
+        } else {
+            crate::git::head_content(path).map(|bytes| encoding::decode(&bytes).0)
+        };
+        let panel = match self.get_widget_mut(WidgetType::Panel) {
+            Some(panel) => panel,
+            None => return,
+        };
+        let text = panel.get_buffer().clone();
+        if let Some(hunks) = panel.get_hunks_mut() {
+            hunks.set_head(head, &text);
+        }
+    }
+
+    /// Puts the cursor of the panel on `line` and `column`, both from 1, clamped
+    /// to the text
+    pub fn goto_position(&mut self, line: usize, column: Option<usize>) -> Option<CursorPosition> {
+        let panel = self.get_widget_mut(WidgetType::Panel)?;
+        let buffer = panel.get_buffer();
+        let line = line
+            .saturating_sub(1)
+            .min(buffer.len_lines().saturating_sub(1));
+        let line_len = buffer.line(line).len_chars();
+        let line_len = match buffer.line(line).chars().last() {
+            Some('\n') => line_len - 1,
+            _ => line_len,
+        };
+        let column = column.unwrap_or(1).saturating_sub(1).min(line_len);
+        let position = buffer.line_to_char(line) + column;
+        panel.set_text_position(position);
+        Some(panel.update_cursor_position_and_view())
+    }
+
     pub fn get_widget(&self, id: WidgetType) -> Option<&Box<dyn ProcessEvent>> {
         for widget in &self.widgets {
             if widget.get_type() == id {
@@ -108,6 +840,14 @@ impl TextEditor {
         None
     }
 
+    pub fn focused_widget_mut(&mut self) -> Option<&mut Box<dyn ProcessEvent>> {
+        let id = self.focused_widget_id;
+        self.widgets
+            .iter_mut()
+            .chain(self.new_widgets.iter_mut())
+            .find(|widget| widget.get_id() == id)
+    }
+
     pub fn get_widget_mut(&mut self, id: WidgetType) -> Option<&mut Box<dyn ProcessEvent>> {
         for widget in &mut self.widgets {
             if widget.get_type() == id {
@@ -142,275 +882,98 @@ impl TextEditor {
         None
     }
 
-    pub fn remove_widget_id(&mut self, id: usize, expected_type: WidgetType) {
+    pub fn remove_widget_id(
+        &mut self,
+        id: usize,
+        expected_type: WidgetType,
+    ) -> Option<Box<dyn ProcessEvent>> {
         let mut idx = 0;
-        eprintln!("remove_widget_id: {}, type {}", id, expected_type);
+        log::debug!("remove_widget_id: {}, type {}", id, expected_type);
         for widget in &self.widgets {
-            eprintln!("widget: {}, type {}", widget.get_id(), widget.get_type());
+            log::trace!("widget: {}, type {}", widget.get_id(), widget.get_type());
             if widget.get_id() == id && widget.get_type() == expected_type {
-                self.widgets.remove(idx);
-                return;
+                return Some(self.widgets.remove(idx));
             }
             idx += 1;
         }
         idx = 0;
         for widget in &self.new_widgets {
             if widget.get_id() == id && widget.get_type() == expected_type {
-                self.new_widgets.remove(idx);
-                return;
+                return Some(self.new_widgets.remove(idx));
             }
             idx += 1;
         }
+        None
     }
 
     pub fn render(&mut self, cursor_position: CursorPosition, is_cursor_visible: bool) {
-        queue!(std::io::stdout(), terminal::Clear(terminal::ClearType::All)).unwrap();
-        for widget in &mut self.widgets {
-            widget.render();
-        }
-        if is_cursor_visible {
-            queue!(stdout(), cursor::Show).unwrap();
-        } else {
-            queue!(stdout(), cursor::Hide).unwrap();
+        self.backend.clear();
+        let mut ctx = RenderContext {
+            out: &mut *self.backend,
+            highlighter: &self.highlighter,
+        };
+        for widget in &self.widgets {
+            widget.render(&mut ctx);
         }
-        queue!(
-            stdout(),
-            cursor::MoveTo(cursor_position.0 as u16, cursor_position.1 as u16)
-        )
-        .unwrap();
+        self.render_message();
+        self.backend.show_cursor(is_cursor_visible);
+        self.backend
+            .move_to(cursor_position.0 as u16, cursor_position.1 as u16);
 
         // Flush the terminal
-        stdout().flush().unwrap();
+        self.backend.flush();
         self.old_cursor_position = cursor_position;
     }
 
-    fn process_uncaught_event(
-        &mut self,
-        widget: &mut Box<dyn ProcessEvent>,
-        event: &Event,
-    ) -> Option<CursorPosition> {
+    /// Draws the current message as a toast in the top right corner, wrapped to
+    /// half of the screen
+    fn render_message(&mut self) {
+        let width = (self.width / 2).max(20);
+        let screen_width = self.width;
+        let message = match self.messages.current() {
+            Some(message) => message,
+            None => return,
+        };
+        let mut lines: Vec<String> = Vec::new();
+        for text_line in message.text.lines() {
+            let chars: Vec<char> = text_line.chars().collect();
+            for chunk in chars.chunks(width - 2) {
+                lines.push(chunk.iter().collect());
+            }
+        }
+        let style = ContentStyle {
+            foreground_color: Some(Color::Black),
+            background_color: Some(message.level.color()),
+            ..ContentStyle::default()
+        };
+        let box_width = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0)
+            + 2;
+        let x = screen_width.saturating_sub(box_width) as u16;
+        for (y, line) in lines.iter().take(MAX_MESSAGE_LINES).enumerate() {
+            let text = format!(" {:<width$} ", line, width = box_width - 2);
+            self.backend.move_to(x, y as u16);
+            self.backend.print(&text, style);
+        }
+    }
+
+    fn process_uncaught_event(&mut self, event: &Event) -> Option<CursorPosition> {
         match event {
             Event::Resize(_x, _y) => {
                 for widget in &mut self.widgets {
                     // widget.set_width(*x as usize);
                     // widget.set_height(*y as usize);
-                    eprintln!("Resizing: {}, {}", widget.get_width(), widget.get_height());
+                    log::debug!("Resizing: {}, {}", widget.get_width(), widget.get_height());
                 }
             }
             Event::Key(key) => {
-                if key.modifiers == KeyModifiers::CONTROL {
-                    match key.code {
-                        crossterm::event::KeyCode::Char('z') => {
-                            eprintln!("undo");
-                            return widget.undo();
-                        }
-                        crossterm::event::KeyCode::Char('y') => {
-                            return widget.redo();
-                        }
-                        crossterm::event::KeyCode::Char('q')
-                        | crossterm::event::KeyCode::Char('c') => {
-                            self.running = false;
-                            return None;
-                        }
-                        crossterm::event::KeyCode::Right => {
-                            while widget.get_text_position() < widget.get_buffer().len_chars()
-                                && widget
-                                    .get_buffer()
-                                    .char(widget.get_text_position())
-                                    .is_whitespace()
-                            {
-                                widget.set_text_position(widget.get_text_position() + 1);
-                            }
-                            let mut has_punc = false;
-                            while widget.get_text_position() < widget.get_buffer().len_chars()
-                                && widget
-                                    .get_buffer()
-                                    .char(widget.get_text_position())
-                                    .is_ascii_punctuation()
-                            {
-                                has_punc = true;
-                                widget.set_text_position(widget.get_text_position() + 1);
-                            }
-                            while !has_punc
-                                && widget.get_text_position() < widget.get_buffer().len_chars()
-                                && widget
-                                    .get_buffer()
-                                    .char(widget.get_text_position())
-                                    .is_alphanumeric()
-                            {
-                                widget.set_text_position(widget.get_text_position() + 1);
-                            }
-
-                            return Some(widget.update_cursor_position_and_view());
-                        }
-                        crossterm::event::KeyCode::Left => {
-                            while widget.get_text_position() > 0
-                                && widget
-                                    .get_buffer()
-                                    .char(widget.get_text_position() - 1)
-                                    .is_whitespace()
-                            {
-                                widget.set_text_position(widget.get_text_position() - 1);
-                            }
-                            let mut has_punc = false;
-                            while widget.get_text_position() > 0
-                                && widget
-                                    .get_buffer()
-                                    .char(widget.get_text_position() - 1)
-                                    .is_ascii_punctuation()
-                            {
-                                has_punc = true;
-                                widget.set_text_position(widget.get_text_position() - 1);
-                            }
-                            while !has_punc
-                                && widget.get_text_position() > 0
-                                && widget
-                                    .get_buffer()
-                                    .char(widget.get_text_position() - 1)
-                                    .is_alphanumeric()
-                            {
-                                widget.set_text_position(widget.get_text_position() - 1);
-                            }
-
-                            return Some(widget.update_cursor_position_and_view());
-                        }
-                        _ => {}
-                    }
-                }
-                if key.modifiers == KeyModifiers::NONE {
-                    match key.code {
-                        crossterm::event::KeyCode::Right => {
-                            if widget.get_text_position() < widget.get_buffer().len_chars() {
-                                widget.set_text_position(widget.get_text_position() + 1);
-                            }
-                            return Some(widget.update_cursor_position_and_view());
-                        }
-                        crossterm::event::KeyCode::Left => {
-                            if widget.get_text_position() > 0 {
-                                widget.set_text_position(widget.get_text_position() - 1);
-                            }
-                            return Some(widget.update_cursor_position_and_view());
-                        }
-                        crossterm::event::KeyCode::Up => {
-                            let line = widget.get_buffer().byte_to_line(widget.get_text_position());
-                            if line > 0 {
-                                let line_start = widget.get_buffer().line_to_byte(line);
-                                let mut column = widget.get_text_position() - line_start;
-                                let len_prev_line = widget.get_buffer().line(line - 1).len_chars();
-                                if column >= len_prev_line {
-                                    column = len_prev_line - 1;
-                                }
-                                if len_prev_line > 1 {
-                                    widget.set_text_position(
-                                        widget.get_buffer().line_to_byte(line - 1) + column,
-                                    );
-                                } else {
-                                    widget.set_text_position(
-                                        widget.get_buffer().line_to_byte(line - 1),
-                                    );
-                                }
-                            } else {
-                                widget.set_text_position(0);
-                            }
-                            return Some(widget.update_cursor_position_and_view());
-                        }
-                        crossterm::event::KeyCode::PageUp => {
-                            for _i in 0..widget.get_height() - 1 {
-                                let line =
-                                    widget.get_buffer().byte_to_line(widget.get_text_position());
-                                if line > 0 {
-                                    let line_start = widget.get_buffer().line_to_byte(line);
-                                    let mut column = widget.get_text_position() - line_start;
-                                    let len_prev_line =
-                                        widget.get_buffer().line(line - 1).len_chars();
-                                    if column >= len_prev_line {
-                                        column = len_prev_line - 1;
-                                    }
-                                    if len_prev_line > 1 {
-                                        widget.set_text_position(
-                                            widget.get_buffer().line_to_byte(line - 1) + column,
-                                        );
-                                    } else {
-                                        widget.set_text_position(
-                                            widget.get_buffer().line_to_byte(line - 1),
-                                        );
-                                    }
-                                } else {
-                                    widget.set_text_position(0);
-                                }
-                            }
-                            return Some(widget.update_cursor_position_and_view());
-                        }
-                        crossterm::event::KeyCode::Down => {
-                            let line = widget.get_buffer().byte_to_line(widget.get_text_position());
-                            eprintln!(
-                                "line: {}, len_lines: {}",
-                                line,
-                                widget.get_buffer().len_lines()
-                            );
-                            if widget.get_buffer().len_lines() >= 2
-                                && line < widget.get_buffer().len_lines() - 2
-                            {
-                                let line_start = widget.get_buffer().line_to_byte(line);
-                                let mut column = widget.get_text_position() - line_start;
-                                let len_next_line = widget.get_buffer().line(line + 1).len_chars();
-                                if column >= len_next_line {
-                                    column = len_next_line - 1;
-                                }
-                                if len_next_line > 1 {
-                                    widget.set_text_position(
-                                        widget.get_buffer().line_to_byte(line + 1) + column,
-                                    );
-                                } else {
-                                    widget.set_text_position(
-                                        widget.get_buffer().line_to_byte(line + 1),
-                                    );
-                                }
-                            } else {
-                                widget.set_text_position(widget.get_buffer().len_chars());
-                            }
-                            return Some(widget.update_cursor_position_and_view());
-                        }
-                        crossterm::event::KeyCode::PageDown => {
-                            for _i in 0..widget.get_height() - 1 {
-                                let line =
-                                    widget.get_buffer().byte_to_line(widget.get_text_position());
-                                if widget.get_buffer().len_lines() >= 2
-                                    && line < widget.get_buffer().len_lines() - 2
-                                {
-                                    let line_start = widget.get_buffer().line_to_byte(line);
-                                    let mut column = widget.get_text_position() - line_start;
-                                    let len_next_line =
-                                        widget.get_buffer().line(line + 1).len_chars();
-                                    if column >= len_next_line {
-                                        column = len_next_line - 1;
-                                    }
-                                    if len_next_line > 1 {
-                                        widget.set_text_position(
-                                            widget.get_buffer().line_to_byte(line + 1) + column,
-                                        );
-                                    } else {
-                                        widget.set_text_position(
-                                            widget.get_buffer().line_to_byte(line + 1),
-                                        );
-                                    }
-                                } else {
-                                    widget.set_text_position(widget.get_buffer().len_chars());
-                                }
-                            }
-                            return Some(widget.update_cursor_position_and_view());
-                        }
-                        crossterm::event::KeyCode::Home => {
-                            widget.set_text_position(0);
-                            return Some(widget.update_cursor_position_and_view());
-                        }
-
-                        crossterm::event::KeyCode::End => {
-                            widget.set_text_position(widget.get_buffer().len_chars());
-                            return Some(widget.update_cursor_position_and_view());
-                        }
-                        _ => {}
-                    }
+                // Keybindings run once the widget is back with the others
+                if let Some(command_line) = self.keymap.get(key) {
+                    self.pending_commands.push(command_line.clone());
+                    return None;
                 }
             }
             _ => {}
@@ -418,7 +981,19 @@ impl TextEditor {
         None
     }
 
+    /// Lets every widget update itself after a change made outside of its event
+    fn refresh(&mut self) {
+        for idx in 0..self.widgets.len() {
+            let mut widget = self.widgets.remove(idx);
+            widget.event(self, &Event::FocusGained);
+            self.widgets.insert(idx, widget);
+        }
+    }
+
     pub fn event(&mut self, event: &Event) {
+        if let Event::Key(_) = event {
+            self.messages.dismiss();
+        }
         let mut cursor_position: (i32, i32) = (0, 0);
         let mut is_cursor_visible = true;
         self.new_widgets.clear();
@@ -434,7 +1009,7 @@ impl TextEditor {
                         self.new_widgets.push(widget);
                     }
                 } else {
-                    if let Some(pos) = self.process_uncaught_event(&mut widget, event) {
+                    if let Some(pos) = self.process_uncaught_event(event) {
                         cursor_position = pos;
                     } else {
                         cursor_position = self.old_cursor_position;
@@ -455,6 +1030,21 @@ impl TextEditor {
         self.widgets
             .sort_by(|a, b| a.get_z_idx().cmp(&b.get_z_idx()));
         self.widgets.sort_by(|a, b| a.get_id().cmp(&b.get_id()));
+        let pending_commands = std::mem::take(&mut self.pending_commands);
+        for command_line in &pending_commands {
+            match self.execute_command_line(command_line, Trigger::Key) {
+                Ok(Some(pos)) => {
+                    cursor_position = pos;
+                    self.old_cursor_position = pos;
+                    is_cursor_visible = true;
+                }
+                Ok(None) => {}
+                Err(e) => self.error(e.to_string()),
+            }
+        }
+        if !pending_commands.is_empty() {
+            self.refresh();
+        }
         self.render(cursor_position, is_cursor_visible);
     }
 }
@@ -0,0 +1,367 @@
+use std::fmt;
+
+static UTF8_BOM: &[u8] = &[0xef, 0xbb, 0xbf];
+static UTF16_LE_BOM: &[u8] = &[0xff, 0xfe];
+static UTF16_BE_BOM: &[u8] = &[0xfe, 0xff];
+
+/// How the bytes of a file map to text
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Encoding {
+    Utf8,
+    /// UTF-8 starting with a byte order mark
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    /// ISO-8859-1, every byte is a char, used when nothing else fits
+    Latin1,
+}
+
+impl Encoding {
+    pub const NAMES: &'static [&'static str] =
+        &["utf-8", "utf-8-bom", "utf-16le", "utf-16be", "latin-1"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf8Bom => "utf-8-bom",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "latin-1",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-8-bom" | "utf8-bom" => Some(Encoding::Utf8Bom),
+            "utf-16le" | "utf-16" | "utf16le" | "utf16" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            "latin-1" | "latin1" | "iso-8859-1" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// What ends the lines of a file, buffers always use `\n`
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    pub const NAMES: &'static [&'static str] = &["unix", "dos"];
+
+    /// Name of the `fileformat` option
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "unix",
+            LineEnding::Crlf => "dos",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "unix" => Some(LineEnding::Lf),
+            "dos" => Some(LineEnding::Crlf),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The encoding and line endings of a file, kept to save it the way it was read
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct FileFormat {
+    pub encoding: Encoding,
+    pub line_ending: LineEnding,
+    /// A UTF-16 file starts with a byte order mark, UTF-8 has its own encoding
+    /// for it
+    pub bom: bool,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::Utf8,
+            line_ending: LineEnding::Lf,
+            bom: false,
+        }
+    }
+}
+
+/// Decodes `bytes` in the encoding they look like, lines end with `\n` in the text
+pub fn decode(bytes: &[u8]) -> (String, FileFormat) {
+    let encoding = detect_encoding(bytes);
+    match decode_with(bytes, encoding) {
+        Ok(text) => normalize(text, encoding, has_bom(bytes, encoding)),
+        Err(_) => normalize(latin1(bytes), Encoding::Latin1, false),
+    }
+}
+
+/// Decodes `bytes` in a chosen encoding, fails when they are not valid in it
+pub fn decode_as(bytes: &[u8], encoding: Encoding) -> Result<(String, FileFormat), String> {
+    let text = decode_with(bytes, encoding)?;
+    Ok(normalize(text, encoding, has_bom(bytes, encoding)))
+}
+
+/// Encodes `text`, written with `\n`, in the format of the file
+pub fn encode(text: &str, format: FileFormat) -> Result<Vec<u8>, String> {
+    let text = match format.line_ending {
+        LineEnding::Lf => text.to_string(),
+        LineEnding::Crlf => text.replace('\n', "\r\n"),
+    };
+    let bytes = match format.encoding {
+        Encoding::Utf8 => text.into_bytes(),
+        Encoding::Utf8Bom => [UTF8_BOM, text.as_bytes()].concat(),
+        Encoding::Utf16Le => utf16_bom(UTF16_LE_BOM, format.bom)
+            .iter()
+            .copied()
+            .chain(text.encode_utf16().flat_map(|unit| unit.to_le_bytes()))
+            .collect(),
+        Encoding::Utf16Be => utf16_bom(UTF16_BE_BOM, format.bom)
+            .iter()
+            .copied()
+            .chain(text.encode_utf16().flat_map(|unit| unit.to_be_bytes()))
+            .collect(),
+        Encoding::Latin1 => {
+            let mut bytes = Vec::with_capacity(text.len());
+            for c in text.chars() {
+                match u8::try_from(c) {
+                    Ok(byte) => bytes.push(byte),
+                    Err(_) => return Err(format!("'{}' cannot be written in latin-1", c)),
+                }
+            }
+            bytes
+        }
+    };
+    Ok(bytes)
+}
+
+fn utf16_bom(bom: &'static [u8], written: bool) -> &'static [u8] {
+    if written {
+        bom
+    } else {
+        &[]
+    }
+}
+
+/// Whether `bytes` start with the byte order mark of UTF-16 `encoding`
+fn has_bom(bytes: &[u8], encoding: Encoding) -> bool {
+    match encoding {
+        Encoding::Utf16Le => bytes.starts_with(UTF16_LE_BOM),
+        Encoding::Utf16Be => bytes.starts_with(UTF16_BE_BOM),
+        _ => false,
+    }
+}
+
+fn detect_encoding(bytes: &[u8]) -> Encoding {
+    if bytes.starts_with(UTF8_BOM) {
+        return Encoding::Utf8Bom;
+    }
+    if bytes.starts_with(UTF16_LE_BOM) {
+        return Encoding::Utf16Le;
+    }
+    if bytes.starts_with(UTF16_BE_BOM) {
+        return Encoding::Utf16Be;
+    }
+    // Without a BOM, mostly ASCII UTF-16 has a zero in every other byte, zeros are
+    // valid UTF-8 too so this is checked first
+    if bytes.len().is_multiple_of(2) {
+        let half = bytes.len() / 2;
+        let zeros_at = |parity: usize| {
+            bytes
+                .iter()
+                .skip(parity)
+                .step_by(2)
+                .filter(|b| **b == 0)
+                .count()
+        };
+        if zeros_at(1) * 2 > half && decode_with(bytes, Encoding::Utf16Le).is_ok() {
+            return Encoding::Utf16Le;
+        }
+        if zeros_at(0) * 2 > half && decode_with(bytes, Encoding::Utf16Be).is_ok() {
+            return Encoding::Utf16Be;
+        }
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return Encoding::Utf8;
+    }
+    Encoding::Latin1
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| *b as char).collect()
+}
+
+fn decode_with(bytes: &[u8], encoding: Encoding) -> Result<String, String> {
+    let invalid = |e: &dyn fmt::Display| format!("Not valid {}: {}", encoding, e);
+    match encoding {
+        Encoding::Utf8 | Encoding::Utf8Bom => {
+            let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes);
+            String::from_utf8(bytes.to_vec()).map_err(|e| invalid(&e))
+        }
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let little_endian = encoding == Encoding::Utf16Le;
+            let bom = if little_endian {
+                UTF16_LE_BOM
+            } else {
+                UTF16_BE_BOM
+            };
+            let bytes = bytes.strip_prefix(bom).unwrap_or(bytes);
+            if !bytes.len().is_multiple_of(2) {
+                return Err(invalid(&"odd number of bytes"));
+            }
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|pair| {
+                    if little_endian {
+                        u16::from_le_bytes([pair[0], pair[1]])
+                    } else {
+                        u16::from_be_bytes([pair[0], pair[1]])
+                    }
+                })
+                .collect();
+            String::from_utf16(&units).map_err(|e| invalid(&e))
+        }
+        Encoding::Latin1 => Ok(latin1(bytes)),
+    }
+}
+
+/// Replaces CRLF by `\n`, the line ending used by most lines is kept for saving
+fn normalize(text: String, encoding: Encoding, bom: bool) -> (String, FileFormat) {
+    let crlf = text.matches("\r\n").count();
+    let lf = text.matches('\n').count() - crlf;
+    let line_ending = if crlf > lf {
+        LineEnding::Crlf
+    } else {
+        LineEnding::Lf
+    };
+    let text = if crlf > 0 {
+        text.replace("\r\n", "\n")
+    } else {
+        text
+    };
+    (
+        text,
+        FileFormat {
+            encoding,
+            line_ending,
+            bom,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(encoding: Encoding, line_ending: LineEnding) -> FileFormat {
+        FileFormat {
+            encoding,
+            line_ending,
+            bom: false,
+        }
+    }
+
+    #[test]
+    fn detects_encodings() {
+        assert_eq!(
+            decode("h\u{e9}\n".as_bytes()),
+            ("h\u{e9}\n".to_string(), FileFormat::default())
+        );
+        assert_eq!(decode(b"\xef\xbb\xbfhi\n").1.encoding, Encoding::Utf8Bom);
+        assert_eq!(decode(b"\xef\xbb\xbfhi\n").0, "hi\n");
+        let bom = |encoding| FileFormat {
+            bom: true,
+            ..format(encoding, LineEnding::Lf)
+        };
+        assert_eq!(
+            decode(b"\xff\xfeh\0i\0"),
+            ("hi".to_string(), bom(Encoding::Utf16Le))
+        );
+        assert_eq!(
+            decode(b"\xfe\xff\0h\0i"),
+            ("hi".to_string(), bom(Encoding::Utf16Be))
+        );
+        assert_eq!(
+            decode(b"h\0i\0\n\0"),
+            (
+                "hi\n".to_string(),
+                format(Encoding::Utf16Le, LineEnding::Lf)
+            )
+        );
+        assert_eq!(
+            decode(b"caf\xe9\n"),
+            (
+                "caf\u{e9}\n".to_string(),
+                format(Encoding::Latin1, LineEnding::Lf)
+            )
+        );
+        assert_eq!(decode(b""), (String::new(), FileFormat::default()));
+    }
+
+    #[test]
+    fn detects_line_endings() {
+        assert_eq!(
+            decode(b"a\r\nb\r\n"),
+            (
+                "a\nb\n".to_string(),
+                format(Encoding::Utf8, LineEnding::Crlf)
+            )
+        );
+        // Mixed files are normalized, the most used ending wins
+        assert_eq!(
+            decode(b"a\r\nb\nc\n"),
+            ("a\nb\nc\n".to_string(), FileFormat::default())
+        );
+        // A lone \r is not a line ending
+        assert_eq!(decode(b"a\rb\n").0, "a\rb\n");
+    }
+
+    #[test]
+    fn round_trips() {
+        let files: [&[u8]; 7] = [
+            b"a\nb\n",
+            b"\xef\xbb\xbfa\r\nb\r\n",
+            b"\xff\xfea\0\r\0\n\0",
+            b"\xfe\xff\0a\0\n",
+            b"a\0b\0\n\0",
+            b"\0a\0b\0\n",
+            b"caf\xe9\r\n",
+        ];
+        for bytes in files {
+            let (text, format) = decode(bytes);
+            assert_eq!(encode(&text, format).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn converts() {
+        let dos = format(Encoding::Utf8, LineEnding::Crlf);
+        assert_eq!(encode("a\nb\n", dos).unwrap(), b"a\r\nb\r\n");
+        let latin1 = format(Encoding::Latin1, LineEnding::Lf);
+        assert_eq!(
+            encode("\u{20ac}", latin1),
+            Err("'\u{20ac}' cannot be written in latin-1".to_string())
+        );
+        assert!(decode_as(b"caf\xe9", Encoding::Utf8).is_err());
+        assert_eq!(
+            decode_as("caf\u{e9}".as_bytes(), Encoding::Latin1)
+                .unwrap()
+                .0,
+            "caf\u{c3}\u{a9}"
+        );
+    }
+}
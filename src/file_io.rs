@@ -0,0 +1,239 @@
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
+    os::unix::fs::{fchown, MetadataExt},
+    path::{Path, PathBuf},
+    process,
+};
+
+use ropey::Rope;
+
+use crate::{
+    encoding::{self, FileFormat},
+    large_file,
+};
+
+/// Why a file could not be read or written, shown in the status bar
+#[derive(Debug)]
+pub enum FileError {
+    NotFound(PathBuf),
+    PermissionDenied(PathBuf),
+    IsADirectory(PathBuf),
+    DiskFull(PathBuf),
+    Other(PathBuf, io::Error),
+}
+
+impl FileError {
+    pub fn new(path: &Path, e: io::Error) -> Self {
+        let path = path.to_path_buf();
+        match e.kind() {
+            ErrorKind::NotFound => FileError::NotFound(path),
+            ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem => {
+                FileError::PermissionDenied(path)
+            }
+            ErrorKind::IsADirectory => FileError::IsADirectory(path),
+            ErrorKind::StorageFull | ErrorKind::QuotaExceeded => FileError::DiskFull(path),
+            _ => FileError::Other(path, e),
+        }
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileError::NotFound(path) => write!(f, "No such file: {}", path.display()),
+            FileError::PermissionDenied(path) => {
+                write!(f, "Permission denied: {}", path.display())
+            }
+            FileError::IsADirectory(path) => write!(f, "{} is a directory", path.display()),
+            FileError::DiskFull(path) => {
+                write!(f, "No space left to write {}", path.display())
+            }
+            FileError::Other(path, e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for FileError {}
+
+pub fn read_bytes(path: &Path) -> Result<Vec<u8>, FileError> {
+    if path.is_dir() {
+        return Err(FileError::IsADirectory(path.to_path_buf()));
+    }
+    fs::read(path).map_err(|e| FileError::new(path, e))
+}
+
+/// Reads a file in the encoding it looks like, lines end with `\n` in the text
+pub fn read_file(path: &Path) -> Result<(String, FileFormat), FileError> {
+    Ok(encoding::decode(&read_bytes(path)?))
+}
+
+/// Reads a file into a rope, large files through [`large_file::load`]
+pub fn read_rope(path: &Path) -> Result<(Rope, FileFormat), FileError> {
+    if large_file::is_large(path) {
+        return large_file::load(path);
+    }
+    let (text, format) = read_file(path)?;
+    Ok((Rope::from_str(&text), format))
+}
+
+/// Reads everything piped to the editor, in the encoding it looks like
+pub fn read_stdin() -> Result<(Rope, FileFormat), FileError> {
+    let mut bytes = Vec::new();
+    io::stdin()
+        .lock()
+        .read_to_end(&mut bytes)
+        .map_err(|e| FileError::new(Path::new("-"), e))?;
+    Ok(large_file::rope_from_bytes(&bytes))
+}
+
+/// Writes `content` to a temporary file next to `path`, syncs it and renames it
+/// over `path`, so a failed save never leaves a truncated file
+///
+/// The permissions and the owner of the old file are kept, a symlink is kept
+/// and its target is replaced.
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<(), FileError> {
+    let target = resolve(path).map_err(|e| FileError::new(path, e))?;
+    if target.is_dir() {
+        return Err(FileError::IsADirectory(path.to_path_buf()));
+    }
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let temp = dir.join(format!(".{}.{}.tmp", name, process::id()));
+    let res = write_temp(&temp, &target, content).and_then(|()| fs::rename(&temp, &target));
+    if let Err(e) = res {
+        let _ = fs::remove_file(&temp);
+        return Err(FileError::new(path, e));
+    }
+    // The rename itself is only durable once the directory is synced
+    if let Ok(dir) = File::open(&dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// The file `path` leads to, a dangling symlink leading to the file it names
+fn resolve(path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    // The limit of the kernel, a longer chain is most likely a loop
+    for _ in 0..40 {
+        match fs::canonicalize(&path) {
+            Ok(target) => return Ok(target),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        match fs::read_link(&path) {
+            // A relative target is relative to the directory of the link
+            Ok(link) => path = path.parent().unwrap_or(Path::new("")).join(link),
+            // A new file
+            Err(_) => return Ok(path),
+        }
+    }
+    Err(io::Error::other("too many levels of symbolic links"))
+}
+
+fn write_temp(temp: &Path, target: &Path, content: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(temp)?;
+    file.write_all(content)?;
+    if let Ok(metadata) = fs::metadata(target) {
+        // Only root may give a file away, keeping our own ownership is fine then
+        if let Err(e) = fchown(&file, Some(metadata.uid()), Some(metadata.gid())) {
+            if e.kind() != ErrorKind::PermissionDenied {
+                return Err(e);
+            }
+        }
+        // After the owner, changing it clears the setuid and setgid bits
+        file.set_permissions(metadata.permissions())?;
+    }
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::harness::TestDir;
+
+    #[test]
+    fn replaces_and_keeps_permissions() {
+        let test_dir = TestDir::new("file_io-replace");
+        let dir = &test_dir.0;
+        let path = dir.join("file.txt");
+        fs::write(&path, "old content that is longer").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        // No temporary file is left behind
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 1);
+
+        let new_path = dir.join("new.txt");
+        write_atomic(&new_path, b"created").unwrap();
+        assert_eq!(read_file(&new_path).unwrap().0, "created");
+    }
+
+    #[test]
+    fn keeps_symlinks() {
+        let test_dir = TestDir::new("file_io-symlink");
+        let dir = &test_dir.0;
+        let target = dir.join("target.txt");
+        let link = dir.join("link.txt");
+        fs::write(&target, "old").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        write_atomic(&link, b"new").unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+
+        // A dangling link creates its target
+        let new_target = dir.join("sub/new.txt");
+        let new_link = dir.join("new-link.txt");
+        fs::create_dir(dir.join("sub")).unwrap();
+        std::os::unix::fs::symlink("sub/new.txt", &new_link).unwrap();
+        write_atomic(&new_link, b"created").unwrap();
+        assert!(fs::symlink_metadata(&new_link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&new_target).unwrap(), "created");
+
+        let looping = dir.join("loop.txt");
+        std::os::unix::fs::symlink(&looping, &looping).unwrap();
+        assert!(write_atomic(&looping, b"").is_err());
+    }
+
+    #[test]
+    fn reports_errors() {
+        let test_dir = TestDir::new("file_io-errors");
+        let dir = &test_dir.0;
+        assert!(matches!(
+            read_file(&dir.join("missing")),
+            Err(FileError::NotFound(_))
+        ));
+        assert!(matches!(read_file(dir), Err(FileError::IsADirectory(_))));
+        assert!(matches!(
+            write_atomic(dir, b""),
+            Err(FileError::IsADirectory(_))
+        ));
+        assert!(matches!(
+            write_atomic(&dir.join("missing/file"), b""),
+            Err(FileError::NotFound(_))
+        ));
+        let err = read_file(&dir.join("missing")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("No such file: {}", dir.join("missing").display())
+        );
+    }
+}
@@ -0,0 +1,267 @@
+//! Which syntax highlights a buffer, from its file name and its first lines
+
+use std::path::Path;
+
+use ropey::Rope;
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+
+/// Lines at each end of the buffer searched for a modeline, as vim does
+static MODELINE_LINES: usize = 5;
+
+/// Lines searched for a diff in a buffer without a file
+static DIFF_LINES: usize = 100;
+
+/// File names syntect does not know, with the syntaxes they are highlighted
+/// with, the first one loaded wins
+///
+/// A pattern is a name, or a name starting or ending with `*`.
+static FILE_NAMES: &[(&str, &[&str])] = &[
+    ("Dockerfile", &["Dockerfile", "Bourne Again Shell (bash)"]),
+    ("Dockerfile.*", &["Dockerfile", "Bourne Again Shell (bash)"]),
+    ("*.dockerfile", &["Dockerfile", "Bourne Again Shell (bash)"]),
+    (
+        "Containerfile",
+        &["Dockerfile", "Bourne Again Shell (bash)"],
+    ),
+    ("Cargo.lock", &["TOML"]),
+    ("Pipfile", &["TOML"]),
+    ("poetry.lock", &["TOML"]),
+    ("flake.lock", &["JSON"]),
+    ("composer.lock", &["JSON"]),
+    (".babelrc", &["JSON"]),
+    (".eslintrc", &["JSON"]),
+    (".zshrc", &["Bourne Again Shell (bash)"]),
+    (".zprofile", &["Bourne Again Shell (bash)"]),
+    (".envrc", &["Bourne Again Shell (bash)"]),
+    ("PKGBUILD", &["Bourne Again Shell (bash)"]),
+    ("*.ebuild", &["Bourne Again Shell (bash)"]),
+    ("justfile", &["Just", "Makefile"]),
+    ("Jenkinsfile", &["Groovy"]),
+    ("CMakeLists.txt", &["CMake"]),
+    ("COMMIT_EDITMSG", &["Git Commit"]),
+];
+
+/// Interpreters of `#!` lines, with the syntaxes of their scripts
+static INTERPRETERS: &[(&str, &str)] = &[
+    ("python", "Python"),
+    ("pypy", "Python"),
+    ("sh", "Bourne Again Shell (bash)"),
+    ("bash", "Bourne Again Shell (bash)"),
+    ("dash", "Bourne Again Shell (bash)"),
+    ("ksh", "Bourne Again Shell (bash)"),
+    ("zsh", "Bourne Again Shell (bash)"),
+    ("node", "JavaScript"),
+    ("deno", "JavaScript"),
+    ("ruby", "Ruby"),
+    ("perl", "Perl"),
+    ("php", "PHP"),
+    ("lua", "Lua"),
+    ("make", "Makefile"),
+    ("tclsh", "Tcl"),
+    ("wish", "Tcl"),
+    ("Rscript", "R"),
+    ("runghc", "Haskell"),
+    ("runhaskell", "Haskell"),
+    ("ocaml", "OCaml"),
+    ("escript", "Erlang"),
+    ("groovy", "Groovy"),
+    ("scala", "Scala"),
+    ("rust-script", "Rust"),
+];
+
+/// Filetypes of vim, as written in modelines, named neither like a syntax nor
+/// like an extension, with the syntaxes they fall back on
+static ALIASES: &[(&str, &str)] = &[
+    ("text", "Plain Text"),
+    ("shell", "Bourne Again Shell (bash)"),
+    ("dockerfile", "Bourne Again Shell (bash)"),
+    ("objc", "Objective-C"),
+    ("objcpp", "Objective-C++"),
+];
+
+/// The syntax of the buffer of `path`, an empty path being no file
+///
+/// A modeline wins over the file name, which wins over the first lines. The
+/// syntaxes are searched in `sets` in order, the last one holding plain text.
+pub fn detect<'a>(sets: &[&'a SyntaxSet], path: &Path, text: &Rope) -> &'a SyntaxReference {
+    let plain_text = sets[sets.len() - 1].find_syntax_plain_text();
+    if let Some(syntax) = modeline(text).and_then(|filetype| find(sets, &filetype)) {
+        return syntax;
+    }
+    by_file_name(sets, path)
+        .filter(|syntax| syntax.name != plain_text.name)
+        .or_else(|| by_shebang(sets, text))
+        .or_else(|| {
+            let first_line = text.lines().next()?.to_string();
+            first_of(sets, |set| set.find_syntax_by_first_line(&first_line))
+        })
+        .or_else(|| {
+            if !path.as_os_str().is_empty() {
+                return None;
+            }
+            // The output of `git diff` or `git show`, piped in
+            let is_diff = text
+                .lines()
+                .take(DIFF_LINES)
+                .map(|line| line.to_string())
+                .any(|line| line.starts_with("diff --git") || line.starts_with("@@ "));
+            first_of(sets, |set| set.find_syntax_by_name("Diff")).filter(|_| is_diff)
+        })
+        .unwrap_or(plain_text)
+}
+
+/// The syntax of a filetype, as given to `:setfiletype` or in a modeline: the
+/// name of a syntax, any case, or one of its extensions
+pub fn find<'a>(sets: &[&'a SyntaxSet], filetype: &str) -> Option<&'a SyntaxReference> {
+    let by_name = |name: &str| {
+        first_of(sets, |set| {
+            set.syntaxes()
+                .iter()
+                .find(|syntax| syntax.name.eq_ignore_ascii_case(name))
+        })
+    };
+    by_name(filetype)
+        .or_else(|| first_of(sets, |set| set.find_syntax_by_extension(filetype)))
+        .or_else(|| {
+            let (_, name) = ALIASES.iter().find(|(alias, _)| *alias == filetype)?;
+            by_name(name)
+        })
+}
+
+/// The first syntax `find` finds in one of `sets`
+fn first_of<'a>(
+    sets: &[&'a SyntaxSet],
+    find: impl Fn(&'a SyntaxSet) -> Option<&'a SyntaxReference>,
+) -> Option<&'a SyntaxReference> {
+    sets.iter().find_map(|set| find(set))
+}
+
+/// The filetype set by a vim modeline, `vim: ft=rust` or `vim: set ft=rust:`,
+/// in the first or last lines
+fn modeline(text: &Rope) -> Option<String> {
+    let len_lines = text.len_lines();
+    let head = 0..MODELINE_LINES.min(len_lines);
+    let tail = len_lines.saturating_sub(MODELINE_LINES).max(head.end)..len_lines;
+    head.chain(tail)
+        .find_map(|idx| parse_modeline(&text.line(idx).to_string()))
+}
+
+fn parse_modeline(line: &str) -> Option<String> {
+    let options = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        let start = line.find(marker)?;
+        // The marker starts the line or follows a blank, like in `# vim:`
+        let after_blank = line[..start].ends_with(char::is_whitespace);
+        (start == 0 || after_blank).then(|| &line[start + marker.len()..])
+    })?;
+    let options = options.trim_start();
+    let options = options
+        .strip_prefix("set ")
+        .or_else(|| options.strip_prefix("se "))
+        .unwrap_or(options);
+    options
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .filter_map(|option| option.split_once('='))
+        .find(|(name, _)| matches!(*name, "ft" | "filetype" | "syn" | "syntax"))
+        .map(|(_, value)| value.to_string())
+}
+
+fn by_file_name<'a>(sets: &[&'a SyntaxSet], path: &Path) -> Option<&'a SyntaxReference> {
+    let file_name = path.file_name()?.to_str()?;
+    let matches = |pattern: &str| match (pattern.strip_prefix('*'), pattern.strip_suffix('*')) {
+        (Some(suffix), _) => file_name.ends_with(suffix),
+        (_, Some(prefix)) => file_name.starts_with(prefix),
+        _ => file_name == pattern,
+    };
+    FILE_NAMES
+        .iter()
+        .filter(|(pattern, _)| matches(pattern))
+        .flat_map(|(_, names)| names.iter())
+        .find_map(|name| first_of(sets, |set| set.find_syntax_by_name(name)))
+        // syntect lists names like `Makefile` or `.bashrc` among the extensions
+        .or_else(|| first_of(sets, |set| set.find_syntax_by_extension(file_name)))
+        .or_else(|| {
+            let extension = path.extension()?.to_str()?;
+            first_of(sets, |set| set.find_syntax_by_extension(extension))
+        })
+}
+
+/// The syntax of the interpreter of a `#!` line, `/usr/bin/env` being skipped
+fn by_shebang<'a>(sets: &[&'a SyntaxSet], text: &Rope) -> Option<&'a SyntaxReference> {
+    let first_line = text.lines().next()?.to_string();
+    let mut words = first_line.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        // `env -S python3 -u`
+        program = words.find(|word| !word.starts_with('-') && !word.contains('='))?;
+    }
+    // `python3.12`
+    let interpreter = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    let (_, name) = INTERPRETERS
+        .iter()
+        .find(|(candidate, _)| *candidate == interpreter)?;
+    first_of(sets, |set| set.find_syntax_by_name(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect_name(path: &str, text: &str) -> String {
+        let syntaxes = SyntaxSet::load_defaults_nonewlines();
+        let syntax = detect(&[&syntaxes], Path::new(path), &Rope::from_str(text));
+        syntax.name.clone()
+    }
+
+    #[test]
+    fn detects_filetypes() {
+        assert_eq!(detect_name("src/main.rs", ""), "Rust");
+        assert_eq!(detect_name("Makefile", "all:\n"), "Makefile");
+        assert_eq!(
+            detect_name("/home/me/.bashrc", ""),
+            "Bourne Again Shell (bash)"
+        );
+        assert_eq!(
+            detect_name("Dockerfile", "FROM rust\n"),
+            "Bourne Again Shell (bash)"
+        );
+        assert_eq!(detect_name("Cargo.lock", "version = 3\n"), "Plain Text");
+        // Shebangs, through env and its options
+        assert_eq!(detect_name("run", "#!/usr/bin/env python3\n"), "Python");
+        assert_eq!(
+            detect_name("run", "#!/usr/bin/env -S node --flag\n"),
+            "JavaScript"
+        );
+        assert_eq!(
+            detect_name("notes.txt", "#!/bin/sh\n"),
+            "Bourne Again Shell (bash)"
+        );
+        assert_eq!(detect_name("run", "#!/opt/bin/unknown\n"), "Plain Text");
+        // syntect's first line patterns
+        assert_eq!(detect_name("page", "<!DOCTYPE html>\n"), "HTML");
+        // Modelines win over the extension
+        assert_eq!(
+            detect_name("a.txt", "x\n\n# vim: ft=python ts=4\n"),
+            "Python"
+        );
+        assert_eq!(detect_name("a.rs", "/* vim: set filetype=c : */\n"), "C");
+        assert_eq!(detect_name("a.md", "// vim: ft=unknown\n"), "Markdown");
+        assert_eq!(detect_name("a", "evim:ft=rust\n"), "Plain Text");
+        // A diff read from stdin, not a file showing one
+        assert_eq!(detect_name("", "commit 1\n\ndiff --git a/x b/x\n"), "Diff");
+        assert_eq!(
+            detect_name("a.txt", "commit 1\n\ndiff --git a/x b/x\n"),
+            "Plain Text"
+        );
+    }
+
+    #[test]
+    fn finds_filetypes_by_name() {
+        let syntaxes = SyntaxSet::load_defaults_nonewlines();
+        let name = |filetype| find(&[&syntaxes], filetype).map(|syntax| syntax.name.clone());
+        assert_eq!(name("rust").as_deref(), Some("Rust"));
+        assert_eq!(name("sh").as_deref(), Some("Bourne Again Shell (bash)"));
+        assert_eq!(name("text").as_deref(), Some("Plain Text"));
+        assert_eq!(name("JSON").as_deref(), Some("JSON"));
+        assert_eq!(name("nope"), None);
+    }
+}
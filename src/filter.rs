@@ -0,0 +1,111 @@
+use crate::{editor::TextEditor, job::JobEvent, widget::widget::WidgetType};
+
+/// A shell command whose stdout replaces part of a buffer once it exits
+pub struct Filter {
+    pub job: usize,
+    pub widget_id: usize,
+    pub widget_type: WidgetType,
+    /// chars replaced, `start == end` only inserts
+    pub start: usize,
+    pub end: usize,
+    /// the replaced text, to tell whether the buffer changed while the command ran
+    pub original: String,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Filter {
+    /// Collects the output, returns whether the command is done
+    pub fn update(&mut self, event: &JobEvent) -> bool {
+        match event {
+            JobEvent::Stdout(text) => self.stdout.push_str(text),
+            JobEvent::Stderr(text) => self.stderr.push_str(text),
+            JobEvent::Exited(_) | JobEvent::Failed(_) => return true,
+        }
+        false
+    }
+
+    /// Replaces the text by stdout if the command succeeded, the buffer is left
+    /// untouched otherwise and stderr is shown
+    ///
+    /// Tells the user how it went.
+    pub fn finish(self, editor: &mut TextEditor, command: &str, event: JobEvent) {
+        let code = match event {
+            JobEvent::Exited(Some(code)) => code,
+            JobEvent::Exited(None) => {
+                return editor.warn(format!("Killed, buffer unchanged: `{}`", command));
+            }
+            JobEvent::Failed(e) => {
+                return editor.error(format!("Failed to run `{}`: {}", command, e));
+            }
+            JobEvent::Stdout(_) | JobEvent::Stderr(_) => unreachable!("output is not an exit"),
+        };
+        if code != 0 {
+            if !self.stderr.is_empty() {
+                editor.show_output_popup(self.stderr, Some("sh"));
+            }
+            return editor.error(format!(
+                "Exit status {}, buffer unchanged: `{}`",
+                code, command
+            ));
+        }
+        let widget = match editor.get_widget_id_mut(self.widget_id, self.widget_type) {
+            Some(widget) => widget,
+            None => return editor.warn(format!("The buffer filtered by `{}` is closed", command)),
+        };
+        let buffer = widget.get_buffer();
+        let unchanged =
+            self.end <= buffer.len_chars() && buffer.slice(self.start..self.end) == self.original;
+        if !unchanged {
+            return editor.warn(format!(
+                "The buffer changed while `{}` ran, not replaced",
+                command
+            ));
+        }
+        widget.replace_range(self.start, self.end, &self.stdout);
+        widget.update_cursor_position_and_view();
+        editor.written = true;
+        if self.start == self.end {
+            editor.info(format!("Inserted the output of `{}`", command));
+        } else {
+            editor.info(format!("Filtered through `{}`", command));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyModifiers};
+
+    use crate::harness::{harness, key_event};
+
+    #[test]
+    fn replaces_the_selection() {
+        let (mut harness, _dir, _) = harness("filter-selection", "c\nb\na\nlast\n");
+        for _ in 0..3 {
+            harness.event(key_event(KeyCode::Down, KeyModifiers::SHIFT));
+        }
+        harness.command(":!sort");
+        harness.wait_for_jobs();
+        assert_eq!(harness.text(), "a\nb\nc\nlast\n");
+        assert!(harness.editor.written);
+        assert!(harness.rows(1)[0].ends_with("Filtered through `sort`"));
+    }
+
+    #[test]
+    fn keeps_the_buffer_when_the_command_fails() {
+        let (mut harness, _dir, _) = harness("filter-failure", "c\nb\na\n");
+        harness.command(":!echo out; echo oops >&2; exit 2");
+        harness.wait_for_jobs();
+        assert_eq!(harness.text(), "c\nb\na\n");
+        assert!(!harness.editor.written);
+        let screen = harness.screen.lines().join("\n");
+        assert!(
+            screen.contains("Exit status 2, buffer unchanged"),
+            "{}",
+            screen
+        );
+        // stderr is shown
+        assert!(screen.contains("oops"), "{}", screen);
+    }
+}
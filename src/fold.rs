@@ -0,0 +1,425 @@
+//! Ranges of lines of a buffer that can be folded into their first line
+
+use ropey::{Rope, RopeSlice};
+
+/// Buffers beyond this are not folded, their folds would be computed again on
+/// every key
+static MAX_FOLDED_BYTES: usize = 4 * 1024 * 1024;
+
+/// Columns of a tab when comparing indentations
+static TAB_WIDTH: usize = 4;
+
+/// Syntaxes whose blocks are delimited by braces, others fold by indentation
+static BRACE_SYNTAXES: &[&str] = &[
+    "ActionScript",
+    "C",
+    "C#",
+    "C++",
+    "CSS",
+    "D",
+    "Go",
+    "Groovy",
+    "Java",
+    "JavaScript",
+    "JSON",
+    "Objective-C",
+    "Objective-C++",
+    "PHP",
+    "Rust",
+    "Scala",
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FoldMethod {
+    /// a line and the more indented lines after it
+    #[default]
+    Indent,
+    /// the lines from an opening brace to its closing one
+    Braces,
+}
+
+impl FoldMethod {
+    pub fn for_syntax(name: &str) -> Self {
+        if BRACE_SYNTAXES.contains(&name) {
+            FoldMethod::Braces
+        } else {
+            FoldMethod::Indent
+        }
+    }
+}
+
+/// Lines `start` to `end` included, `start` stays shown when the fold is closed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fold {
+    pub start: usize,
+    pub end: usize,
+    pub closed: bool,
+}
+
+impl Fold {
+    fn contains(&self, line: usize) -> bool {
+        self.start <= line && line <= self.end
+    }
+
+    /// Whether the fold is closed over `line`, its first line is never hidden
+    fn hides(&self, line: usize) -> bool {
+        self.closed && self.start < line && line <= self.end
+    }
+}
+
+/// The folds of a buffer, computed again when it changes
+///
+/// Folds nest but never overlap, at most one starts on a line.
+#[derive(Default)]
+pub struct Folds {
+    /// sorted by their first line
+    folds: Vec<Fold>,
+    method: FoldMethod,
+    /// lines of the text the folds were computed for
+    len_lines: usize,
+}
+
+impl Folds {
+    pub fn set_method(&mut self, method: FoldMethod, text: &Rope) {
+        self.method = method;
+        self.update(text, None);
+    }
+
+    /// Computes the folds of a new text, all open
+    pub fn reset(&mut self, text: &Rope) {
+        self.folds.clear();
+        self.update(text, None);
+    }
+
+    /// Computes the folds again after `text` was edited on `edited_line`, the
+    /// folds closed before stay closed unless the edit touched them
+    pub fn update(&mut self, text: &Rope, edited_line: Option<usize>) {
+        let delta = text.len_lines() as isize - self.len_lines as isize;
+        let closed: Vec<usize> = self
+            .folds
+            .iter()
+            .filter(|fold| fold.closed)
+            .filter_map(|fold| match edited_line {
+                Some(line) if fold.contains(line) => None,
+                Some(line) if fold.start > line => fold.start.checked_add_signed(delta),
+                _ => Some(fold.start),
+            })
+            .collect();
+        self.len_lines = text.len_lines();
+        self.folds = if text.len_bytes() > MAX_FOLDED_BYTES {
+            Vec::new()
+        } else {
+            match self.method {
+                FoldMethod::Indent => indent_folds(text),
+                FoldMethod::Braces => brace_folds(text),
+            }
+        };
+        for fold in &mut self.folds {
+            fold.closed = closed.contains(&fold.start);
+        }
+    }
+
+    /// The fold starting on `line`, shown as ▸ or ▾ by the line numbers
+    pub fn starting_at(&self, line: usize) -> Option<&Fold> {
+        self.folds.iter().find(|fold| fold.start == line)
+    }
+
+    /// The closed fold whose lines after `line` are hidden, `line` being shown
+    pub fn closed_at(&self, line: usize) -> Option<&Fold> {
+        self.starting_at(line)
+            .filter(|fold| fold.closed && !self.is_hidden(line))
+    }
+
+    pub fn is_hidden(&self, line: usize) -> bool {
+        self.folds.iter().any(|fold| fold.hides(line))
+    }
+
+    /// The closed folds that no other closed fold hides
+    fn outer_closed(&self) -> impl Iterator<Item = &Fold> {
+        let mut hidden_until = None;
+        self.folds.iter().filter(move |fold| {
+            if !fold.closed || hidden_until.is_some_and(|end| fold.start <= end) {
+                return false;
+            }
+            hidden_until = Some(fold.end);
+            true
+        })
+    }
+
+    /// The row of `line` when every closed fold takes a single row
+    pub fn row(&self, line: usize) -> usize {
+        let hidden: usize = self
+            .outer_closed()
+            .take_while(|fold| fold.start < line)
+            .map(|fold| fold.end.min(line - 1) - fold.start)
+            .sum();
+        line - hidden
+    }
+
+    /// The line shown on `row`, the reverse of [`Folds::row`]
+    pub fn line(&self, row: usize) -> usize {
+        let mut line = row;
+        for fold in self.outer_closed() {
+            if fold.start >= line {
+                break;
+            }
+            line += fold.end - fold.start;
+        }
+        line
+    }
+
+    /// The line shown for `line`: itself, or the first line of the fold hiding it
+    pub fn shown_line(&self, line: usize) -> usize {
+        self.outer_closed()
+            .find(|fold| fold.hides(line))
+            .map_or(line, |fold| fold.start)
+    }
+
+    /// The first line shown after `line`
+    pub fn next_line(&self, line: usize) -> usize {
+        match self.closed_at(line) {
+            Some(fold) => fold.end + 1,
+            None => line + 1,
+        }
+    }
+
+    /// The last line shown before `line`, which is not 0
+    pub fn previous_line(&self, line: usize) -> usize {
+        self.shown_line(line - 1)
+    }
+
+    /// Closes the innermost open fold over `line`, returns whether there was one
+    pub fn close(&mut self, line: usize) -> bool {
+        let innermost = self
+            .folds
+            .iter_mut()
+            .rev()
+            .find(|fold| !fold.closed && fold.contains(line));
+        match innermost {
+            Some(fold) => {
+                fold.closed = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Opens the outermost closed fold over `line`, returns whether there was one
+    pub fn open(&mut self, line: usize) -> bool {
+        match self
+            .folds
+            .iter_mut()
+            .find(|fold| fold.closed && fold.contains(line))
+        {
+            Some(fold) => {
+                fold.closed = false;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Opens the folds hiding `line`, e.g. the cursor moved there
+    pub fn reveal(&mut self, line: usize) {
+        for fold in &mut self.folds {
+            if fold.hides(line) {
+                fold.closed = false;
+            }
+        }
+    }
+
+    pub fn set_all_closed(&mut self, closed: bool) {
+        for fold in &mut self.folds {
+            fold.closed = closed;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.folds.is_empty()
+    }
+}
+
+/// Columns of the indentation of `line`, None for a blank line
+fn indentation(line: RopeSlice) -> Option<usize> {
+    let mut columns = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => columns += 1,
+            '\t' => columns += TAB_WIDTH - columns % TAB_WIDTH,
+            c if c.is_whitespace() => {}
+            _ => return Some(columns),
+        }
+    }
+    None
+}
+
+/// A fold for each line followed by more indented ones, blank lines between
+/// them included
+fn indent_folds(text: &Rope) -> Vec<Fold> {
+    let mut folds = Vec::new();
+    // lines whose fold is still open, with their indentation
+    let mut stack: Vec<(usize, usize)> = Vec::new();
+    let mut last_line = 0;
+    let mut close = |stack: &mut Vec<(usize, usize)>, min_indent: usize, last_line: usize| {
+        while let Some(&(start, indent)) = stack.last() {
+            if indent < min_indent {
+                break;
+            }
+            stack.pop();
+            if last_line > start {
+                folds.push(Fold {
+                    start,
+                    end: last_line,
+                    closed: false,
+                });
+            }
+        }
+    };
+    for (idx, line) in text.lines().enumerate() {
+        let indent = match indentation(line) {
+            Some(indent) => indent,
+            None => continue,
+        };
+        close(&mut stack, indent, last_line);
+        stack.push((idx, indent));
+        last_line = idx;
+    }
+    close(&mut stack, 0, last_line);
+    folds.sort_by_key(|fold| fold.start);
+    folds
+}
+
+/// A fold from each line opening a brace to the line closing it, or to the line
+/// before when that one opens a fold of its own, like `} else {`
+fn brace_folds(text: &Rope) -> Vec<Fold> {
+    let mut pairs: Vec<(usize, usize)> = Vec::new();
+    let mut open: Vec<usize> = Vec::new();
+    let mut in_block_comment = false;
+    for (idx, line) in text.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let mut in_string = false;
+        let mut pos = 0;
+        while pos < chars.len() {
+            let c = chars[pos];
+            let next = chars.get(pos + 1).copied();
+            if in_block_comment {
+                if c == '*' && next == Some('/') {
+                    in_block_comment = false;
+                    pos += 1;
+                }
+            } else if in_string {
+                match c {
+                    '\\' => pos += 1,
+                    '"' => in_string = false,
+                    _ => {}
+                }
+            } else {
+                match (c, next) {
+                    ('/', Some('/')) => break,
+                    ('/', Some('*')) => {
+                        in_block_comment = true;
+                        pos += 1;
+                    }
+                    ('"', _) => in_string = true,
+                    // A char like '{'
+                    ('\'', Some(_)) if chars.get(pos + 2) == Some(&'\'') => pos += 2,
+                    ('{', _) => open.push(idx),
+                    ('}', _) => {
+                        if let Some(start) = open.pop() {
+                            pairs.push((start, idx));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            pos += 1;
+        }
+    }
+    pairs.retain(|&(start, close)| close > start);
+    // The outermost pair of a line, e.g. `match x { Some(y) => {`
+    pairs.sort_by_key(|&(start, close)| (start, std::cmp::Reverse(close)));
+    pairs.dedup_by_key(|&mut (start, _)| start);
+    let starts: Vec<usize> = pairs.iter().map(|&(start, _)| start).collect();
+    pairs
+        .iter()
+        .filter_map(|&(start, close)| {
+            let end = if starts.binary_search(&close).is_ok() {
+                close - 1
+            } else {
+                close
+            };
+            (end > start).then_some(Fold {
+                start,
+                end,
+                closed: false,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(method: FoldMethod, text: &str) -> Vec<(usize, usize)> {
+        let mut folds = Folds::default();
+        folds.set_method(method, &Rope::from_str(text));
+        folds
+            .folds
+            .iter()
+            .map(|fold| (fold.start, fold.end))
+            .collect()
+    }
+
+    #[test]
+    fn folds_by_indentation() {
+        let text = "def f():\n    x = 1\n    if x:\n        y()\n\n    z()\nw = 2\n";
+        assert_eq!(ranges(FoldMethod::Indent, text), vec![(0, 5), (2, 3)]);
+        assert_eq!(ranges(FoldMethod::Indent, "a\n\tb\n  c\n"), vec![(0, 2)]);
+    }
+
+    #[test]
+    fn folds_by_braces() {
+        let text = "fn f() {\n    if x {\n        a('{');\n    } else {\n        b(\"}\"); // }\n    }\n}\n/* {\n*/\n";
+        assert_eq!(
+            ranges(FoldMethod::Braces, text),
+            vec![(0, 6), (1, 2), (3, 5)]
+        );
+        // Opened and closed on the same line
+        assert_eq!(ranges(FoldMethod::Braces, "let a = {};\n"), vec![]);
+    }
+
+    #[test]
+    fn hides_the_lines_of_closed_folds() {
+        let text = Rope::from_str("a {\n  b {\n    c\n  }\n}\nd\n");
+        let mut folds = Folds::default();
+        folds.set_method(FoldMethod::Braces, &text);
+        assert!(folds.close(2));
+        assert!(folds.is_hidden(3) && !folds.is_hidden(1));
+        assert_eq!(folds.next_line(1), 4);
+        assert!(folds.close(1));
+        assert_eq!(
+            folds.closed_at(0),
+            Some(&Fold {
+                start: 0,
+                end: 4,
+                closed: true
+            })
+        );
+        // The nested fold is hidden too
+        assert_eq!(folds.closed_at(1), None);
+        assert_eq!((folds.row(5), folds.line(1)), (1, 5));
+        assert_eq!(folds.shown_line(3), 0);
+        assert_eq!(folds.previous_line(5), 0);
+        // Reopened one level at a time
+        assert!(folds.open(0));
+        assert_eq!(folds.row(5), 3);
+        assert_eq!(folds.line(2), 4);
+        // An edit above moves the closed folds along
+        let text = Rope::from_str("x\na {\n  b {\n    c\n  }\n}\nd\n");
+        folds.update(&text, Some(0));
+        assert!(folds.is_hidden(4));
+        folds.reveal(4);
+        assert!(!folds.is_hidden(4));
+    }
+}
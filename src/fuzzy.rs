@@ -0,0 +1,84 @@
+/// Scores how well `pattern` matches `candidate`, `None` if it does not match
+///
+/// Every char of the pattern must appear in order in the candidate, ignoring case.
+/// Consecutive chars and chars starting a word score more, gaps score less.
+pub fn fuzzy_score(pattern: &str, candidate: &str) -> Option<i64> {
+    let pattern: Vec<char> = pattern.chars().flat_map(|c| c.to_lowercase()).collect();
+    if pattern.is_empty() {
+        return Some(0);
+    }
+    let chars: Vec<char> = candidate.chars().collect();
+    let mut score = 0;
+    let mut pattern_idx = 0;
+    let mut last_match: Option<usize> = None;
+    for (i, c) in chars.iter().enumerate() {
+        if pattern_idx == pattern.len() {
+            break;
+        }
+        if !c.to_lowercase().eq(pattern[pattern_idx].to_lowercase()) {
+            continue;
+        }
+        score += 1;
+        let word_start = i == 0 || !chars[i - 1].is_alphanumeric();
+        if word_start {
+            score += 8;
+        }
+        match last_match {
+            Some(last) if last + 1 == i => score += 5,
+            Some(last) => score -= (i - last - 1).min(5) as i64,
+            None => score -= i.min(5) as i64,
+        }
+        last_match = Some(i);
+        pattern_idx += 1;
+    }
+    if pattern_idx < pattern.len() {
+        return None;
+    }
+    Some(score)
+}
+
+/// Indices of the candidates matching `pattern`, best match first
+///
+/// Candidates scoring the same keep their order.
+pub fn fuzzy_filter<S: AsRef<str>>(pattern: &str, candidates: &[S]) -> Vec<usize> {
+    let mut matches: Vec<(usize, i64)> = candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(i, c)| fuzzy_score(pattern, c.as_ref()).map(|score| (i, score)))
+        .collect();
+    matches.sort_by_key(|&(i, score)| (-score, i));
+    matches.into_iter().map(|(i, _)| i).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(pattern: &str, candidate: &str) -> i64 {
+        fuzzy_score(pattern, candidate).unwrap()
+    }
+
+    #[test]
+    fn scores_matches() {
+        assert_eq!(fuzzy_score("", "anything"), Some(0));
+        assert_eq!(fuzzy_score("xyz", "find"), None);
+        // Every char, in order
+        assert_eq!(fuzzy_score("dnif", "find"), None);
+        assert_eq!(fuzzy_score("FI", "find"), fuzzy_score("fi", "find"));
+        // A prefix beats the same chars inside a word
+        assert!(score("fi", "find") > score("fi", "config"));
+        // Chars starting words beat chars inside them
+        assert!(score("sf", "set-filetype") > score("sf", "sourcefile"));
+        // Consecutive chars beat scattered ones
+        assert!(score("the", "theme") > score("the", "toggle-help"));
+    }
+
+    #[test]
+    fn filters_best_first() {
+        let candidates = ["config", "find", "quit", "find-next"];
+        assert_eq!(fuzzy_filter("fi", &candidates), [1, 3, 0]);
+        // Ties keep their order
+        assert_eq!(fuzzy_filter("", &candidates), [0, 1, 2, 3]);
+        assert!(fuzzy_filter("zz", &candidates).is_empty());
+    }
+}
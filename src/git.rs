@@ -0,0 +1,728 @@
+//! The lines of a buffer changed since the last commit, its file as it is at
+//! HEAD being read from the object store of its repository
+
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Read},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::ZlibDecoder;
+use memmap2::Mmap;
+use ropey::{Rope, RopeSlice};
+
+use crate::diff::{self, Edit};
+
+/// Buffers beyond this are not diffed, they would be on every key
+static MAX_DIFFED_BYTES: usize = 4 * 1024 * 1024;
+
+/// Deleted and inserted lines beyond which the changed lines are not diffed
+/// further, they make a single modified hunk
+static MAX_CHANGES: usize = 1000;
+
+/// Symbolic refs followed from HEAD before giving up on a loop
+static MAX_SYMREF_DEPTH: usize = 5;
+
+type ObjectId = [u8; 20];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+impl Kind {
+    fn from_name(name: &[u8]) -> io::Result<Self> {
+        match name {
+            b"commit" => Ok(Kind::Commit),
+            b"tree" => Ok(Kind::Tree),
+            b"blob" => Ok(Kind::Blob),
+            b"tag" => Ok(Kind::Tag),
+            _ => Err(invalid("unknown object type")),
+        }
+    }
+
+    /// The type of an object in a pack, deltas excepted
+    fn from_pack_type(typ: u8) -> Option<Self> {
+        match typ {
+            1 => Some(Kind::Commit),
+            2 => Some(Kind::Tree),
+            3 => Some(Kind::Blob),
+            4 => Some(Kind::Tag),
+            _ => None,
+        }
+    }
+}
+
+/// The content at HEAD of the file `path`, `None` out of a repository, for a
+/// file not committed yet or when the repository cannot be read
+pub fn head_content(path: &Path) -> Option<Vec<u8>> {
+    let path = absolute(path)?;
+    let repository = Repository::discover(&path)?;
+    match repository.head_content(&path) {
+        Ok(content) => content,
+        Err(e) => {
+            log::debug!("reading {} at HEAD: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// The path of a file that may not exist yet, from the root, through no link
+fn absolute(path: &Path) -> Option<PathBuf> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    Some(fs::canonicalize(dir).ok()?.join(path.file_name()?))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+struct Repository {
+    /// the `.git` directory, or the one git keeps for a worktree
+    git_dir: PathBuf,
+    /// the directory of the objects and the shared refs, `git_dir` but for
+    /// worktrees
+    common_dir: PathBuf,
+    /// the directory the commits are checked out in
+    work_dir: PathBuf,
+    packs: Vec<Pack>,
+}
+
+impl Repository {
+    /// The repository of the file `path`, searched from its directory up
+    fn discover(path: &Path) -> Option<Self> {
+        path.ancestors().skip(1).find_map(|work_dir| {
+            let dot_git = work_dir.join(".git");
+            let git_dir = if dot_git.is_dir() {
+                dot_git
+            } else {
+                // In a worktree or a submodule `.git` is a file naming the directory
+                let content = fs::read_to_string(&dot_git).ok()?;
+                work_dir.join(content.strip_prefix("gitdir:")?.trim())
+            };
+            let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+                Ok(dir) => git_dir.join(dir.trim()),
+                Err(_) => git_dir.clone(),
+            };
+            let packs = Pack::open_all(&common_dir.join("objects/pack"));
+            Some(Self {
+                git_dir,
+                common_dir,
+                work_dir: work_dir.to_path_buf(),
+                packs,
+            })
+        })
+    }
+
+    /// The content of the file `path`, from the root, in the commit at HEAD
+    fn head_content(&self, path: &Path) -> io::Result<Option<Vec<u8>>> {
+        let relative = match path.strip_prefix(&self.work_dir) {
+            Ok(relative) => relative,
+            Err(_) => return Ok(None),
+        };
+        let commit = self.resolve("HEAD", 0)?;
+        let (kind, commit) = self.read_object(&commit)?;
+        if kind != Kind::Commit {
+            return Err(invalid("HEAD is not a commit"));
+        }
+        let tree = commit
+            .strip_prefix(b"tree ")
+            .and_then(|rest| rest.get(..40))
+            .ok_or_else(|| invalid("commit without a tree"))?;
+        let mut id = parse_hex(std::str::from_utf8(tree).map_err(|_| invalid("bad tree id"))?)?;
+        let mut mode = "40000".to_string();
+        for component in relative.iter() {
+            // Submodules are commits of another repository
+            if mode != "40000" {
+                return Ok(None);
+            }
+            let (kind, tree) = self.read_object(&id)?;
+            if kind != Kind::Tree {
+                return Err(invalid("a directory is not a tree"));
+            }
+            let name = match component.to_str() {
+                Some(name) => name,
+                None => return Ok(None),
+            };
+            (mode, id) = match tree_entry(&tree, name) {
+                Some((entry_mode, entry_id)) => (entry_mode.to_string(), entry_id),
+                None => return Ok(None),
+            };
+        }
+        if matches!(mode.as_str(), "40000" | "160000") {
+            return Ok(None);
+        }
+        let (kind, content) = self.read_object(&id)?;
+        Ok((kind == Kind::Blob).then_some(content))
+    }
+
+    /// The commit a ref like `HEAD` or `refs/heads/main` points to
+    fn resolve(&self, name: &str, depth: usize) -> io::Result<ObjectId> {
+        if depth > MAX_SYMREF_DEPTH {
+            return Err(invalid("too many symbolic refs"));
+        }
+        // HEAD is the worktree's own, branches are shared
+        let content = [&self.git_dir, &self.common_dir]
+            .iter()
+            .find_map(|dir| fs::read_to_string(dir.join(name)).ok());
+        let content = match content {
+            Some(content) => content,
+            None => return self.packed_ref(name),
+        };
+        let content = content.trim();
+        match content.strip_prefix("ref:") {
+            Some(target) => self.resolve(target.trim(), depth + 1),
+            None => parse_hex(content),
+        }
+    }
+
+    /// A ref written in `packed-refs` by `git gc`
+    fn packed_ref(&self, name: &str) -> io::Result<ObjectId> {
+        let packed_refs = fs::read_to_string(self.common_dir.join("packed-refs"))?;
+        let id = packed_refs
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+            .find_map(|line| {
+                let (id, ref_name) = line.split_once(' ')?;
+                (ref_name == name).then_some(id)
+            })
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("no ref {}", name)))?;
+        parse_hex(id)
+    }
+
+    /// An object, loose or in one of the packs
+    fn read_object(&self, id: &ObjectId) -> io::Result<(Kind, Vec<u8>)> {
+        let hex = to_hex(id);
+        let loose = self
+            .common_dir
+            .join("objects")
+            .join(&hex[..2])
+            .join(&hex[2..]);
+        match fs::read(loose) {
+            Ok(compressed) => return parse_loose(&compressed),
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            Err(_) => {}
+        }
+        for pack in &self.packs {
+            if let Some(offset) = pack.find(id)? {
+                return pack.read_at(self, offset);
+            }
+        }
+        Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("no object {}", hex),
+        ))
+    }
+}
+
+/// A file of `objects/`: a header like `blob 12`, a nul, then the content
+fn parse_loose(compressed: &[u8]) -> io::Result<(Kind, Vec<u8>)> {
+    let mut object = Vec::new();
+    ZlibDecoder::new(compressed).read_to_end(&mut object)?;
+    let nul = object
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| invalid("object without a header"))?;
+    let (name, size) =
+        object[..nul].split_at(object[..nul].iter().position(|&b| b == b' ').unwrap_or(nul));
+    let kind = Kind::from_name(name)?;
+    let size: usize = std::str::from_utf8(size)
+        .ok()
+        .and_then(|size| size.trim().parse().ok())
+        .ok_or_else(|| invalid("object without a size"))?;
+    let content = object.split_off(nul + 1);
+    if content.len() != size {
+        return Err(invalid("object of the wrong size"));
+    }
+    Ok((kind, content))
+}
+
+/// The mode and the id of the entry `name` of a tree, entries being a mode, a
+/// space, a name, a nul then a binary id
+fn tree_entry<'a>(tree: &'a [u8], name: &str) -> Option<(&'a str, ObjectId)> {
+    let mut rest = tree;
+    while !rest.is_empty() {
+        let space = rest.iter().position(|&b| b == b' ')?;
+        let nul = rest.iter().position(|&b| b == 0)?;
+        let id = rest.get(nul + 1..nul + 21)?;
+        if rest.get(space + 1..nul)? == name.as_bytes() {
+            let mode = std::str::from_utf8(&rest[..space]).ok()?;
+            return Some((mode, id.try_into().ok()?));
+        }
+        rest = &rest[nul + 21..];
+    }
+    None
+}
+
+fn parse_hex(hex: &str) -> io::Result<ObjectId> {
+    let mut id = [0; 20];
+    if hex.len() != 40 || !hex.is_ascii() {
+        return Err(invalid("bad object id"));
+    }
+    for (idx, byte) in id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16)
+            .map_err(|_| invalid("bad object id"))?;
+    }
+    Ok(id)
+}
+
+fn to_hex(id: &ObjectId) -> String {
+    id.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Inflates an object of a pack, which is followed by the next one
+fn inflate(compressed: &[u8], size: usize) -> io::Result<Vec<u8>> {
+    let mut content = Vec::with_capacity(size);
+    ZlibDecoder::new(compressed)
+        .take(size as u64 + 1)
+        .read_to_end(&mut content)?;
+    if content.len() != size {
+        return Err(invalid("object of the wrong size"));
+    }
+    Ok(content)
+}
+
+/// A pack file with its version 2 index
+struct Pack {
+    index: Mmap,
+    data: Mmap,
+}
+
+impl Pack {
+    /// Offset in the index of the names, after the header and the fanout table
+    const NAMES: usize = 8 + 256 * 4;
+
+    fn open_all(dir: &Path) -> Vec<Self> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "idx"))
+            .filter_map(|path| match Self::open(&path) {
+                Ok(pack) => Some(pack),
+                Err(e) => {
+                    log::debug!("skipping the pack {}: {}", path.display(), e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn open(index_path: &Path) -> io::Result<Self> {
+        // The maps are only read while loading a file, git never rewrites packs
+        // in place
+        let index = unsafe { Mmap::map(&File::open(index_path)?) }?;
+        let data = unsafe { Mmap::map(&File::open(index_path.with_extension("pack"))?) }?;
+        if !index.starts_with(b"\xfftOc\0\0\0\x02") || index.len() < Self::NAMES {
+            return Err(invalid("not a version 2 pack index"));
+        }
+        Ok(Self { index, data })
+    }
+
+    fn read_u32(&self, at: usize) -> io::Result<u32> {
+        let bytes = self
+            .index
+            .get(at..at + 4)
+            .ok_or_else(|| invalid("truncated pack index"))?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Where the object `id` starts in the pack, if it is in it
+    fn find(&self, id: &ObjectId) -> io::Result<Option<usize>> {
+        // The fanout counts the objects whose first byte is at most each value
+        let fanout = |byte: usize| self.read_u32(8 + byte * 4).map(|count| count as usize);
+        let len = fanout(255)?;
+        let mut low = match id[0] {
+            0 => 0,
+            byte => fanout(byte as usize - 1)?,
+        };
+        let mut high = fanout(id[0] as usize)?;
+        while low < high {
+            let mid = (low + high) / 2;
+            let at = Self::NAMES + mid * 20;
+            let name = self
+                .index
+                .get(at..at + 20)
+                .ok_or_else(|| invalid("truncated pack index"))?;
+            match name.cmp(&id[..]) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => {
+                    // The offsets follow the names and their checksums, those
+                    // with the high bit set index a table of 64 bit offsets
+                    let offsets = Self::NAMES + len * 24;
+                    let offset = self.read_u32(offsets + mid * 4)?;
+                    if offset & 0x8000_0000 == 0 {
+                        return Ok(Some(offset as usize));
+                    }
+                    let at = offsets + len * 4 + (offset & 0x7fff_ffff) as usize * 8;
+                    let high = self.read_u32(at)? as u64;
+                    let low = self.read_u32(at + 4)? as u64;
+                    return Ok(Some((high << 32 | low) as usize));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn byte(&self, at: usize) -> io::Result<u8> {
+        self.data
+            .get(at)
+            .copied()
+            .ok_or_else(|| invalid("truncated pack"))
+    }
+
+    /// The object starting at `offset`, its deltas applied to their bases
+    fn read_at(&self, repository: &Repository, offset: usize) -> io::Result<(Kind, Vec<u8>)> {
+        // The type and the size, 4 bits of it then 7 per byte
+        let mut byte = self.byte(offset)?;
+        let typ = (byte >> 4) & 7;
+        let mut size = (byte & 15) as usize;
+        let mut shift = 4;
+        let mut at = offset + 1;
+        while byte & 0x80 != 0 {
+            if shift >= usize::BITS {
+                return Err(invalid("bad object size"));
+            }
+            byte = self.byte(at)?;
+            at += 1;
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+        }
+        if let Some(kind) = Kind::from_pack_type(typ) {
+            return Ok((kind, inflate(&self.data[at..], size)?));
+        }
+        let (kind, base) = match typ {
+            // A delta on the object this far before it
+            6 => {
+                let mut byte = self.byte(at)?;
+                at += 1;
+                let mut distance = (byte & 0x7f) as usize;
+                while byte & 0x80 != 0 {
+                    byte = self.byte(at)?;
+                    at += 1;
+                    distance = distance
+                        .checked_add(1)
+                        .and_then(|distance| distance.checked_mul(128))
+                        .ok_or_else(|| invalid("bad delta base"))?
+                        | (byte & 0x7f) as usize;
+                }
+                let base = offset
+                    .checked_sub(distance)
+                    .ok_or_else(|| invalid("bad delta base"))?;
+                self.read_at(repository, base)?
+            }
+            // A delta on the object of this id
+            7 => {
+                let id = self
+                    .data
+                    .get(at..at + 20)
+                    .ok_or_else(|| invalid("truncated pack"))?;
+                at += 20;
+                repository.read_object(id.try_into().unwrap())?
+            }
+            _ => return Err(invalid("unknown object type")),
+        };
+        let delta = inflate(&self.data[at..], size)?;
+        Ok((kind, apply_delta(&base, &delta)?))
+    }
+}
+
+/// Builds an object from its base and a delta: the sizes of both, then copies of
+/// ranges of the base and inserted bytes
+fn apply_delta(base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let truncated = || invalid("truncated delta");
+    let mut at = 0;
+    let read_size = |at: &mut usize| -> io::Result<usize> {
+        let mut size = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *delta.get(*at).ok_or_else(truncated)?;
+            *at += 1;
+            if shift >= usize::BITS {
+                return Err(invalid("bad delta size"));
+            }
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(size);
+            }
+        }
+    };
+    let base_size = read_size(&mut at)?;
+    let size = read_size(&mut at)?;
+    if base_size != base.len() {
+        return Err(invalid("delta on the wrong base"));
+    }
+    let mut object = Vec::with_capacity(size);
+    while at < delta.len() {
+        let op = delta[at];
+        at += 1;
+        if op & 0x80 != 0 {
+            // The low bits say which bytes of the offset follow, the next ones
+            // which bytes of the length
+            let mut offset = 0;
+            let mut len = 0;
+            for bit in 0..7 {
+                if op & (1 << bit) == 0 {
+                    continue;
+                }
+                let byte = *delta.get(at).ok_or_else(truncated)? as usize;
+                at += 1;
+                if bit < 4 {
+                    offset |= byte << (8 * bit);
+                } else {
+                    len |= byte << (8 * (bit - 4));
+                }
+            }
+            let len = if len == 0 { 0x10000 } else { len };
+            let copied = base
+                .get(offset..offset + len)
+                .ok_or_else(|| invalid("delta copying out of its base"))?;
+            object.extend_from_slice(copied);
+        } else if op != 0 {
+            let inserted = delta.get(at..at + op as usize).ok_or_else(truncated)?;
+            at += op as usize;
+            object.extend_from_slice(inserted);
+        } else {
+            return Err(invalid("bad delta"));
+        }
+    }
+    if object.len() != size {
+        return Err(invalid("delta of the wrong size"));
+    }
+    Ok(object)
+}
+
+/// What a hunk did to the lines of HEAD
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Change {
+    Added,
+    Modified,
+    Removed,
+}
+
+/// Lines of the buffer replacing lines of the file at HEAD
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hunk {
+    /// the first line of the buffer in the hunk, or the line after the removed
+    /// ones
+    pub start: usize,
+    /// lines of the buffer in the hunk, none when lines were only removed
+    pub len: usize,
+    /// the lines at HEAD it replaces, with their `\n`
+    pub removed: Vec<String>,
+}
+
+impl Hunk {
+    pub fn change(&self) -> Change {
+        if self.removed.is_empty() {
+            Change::Added
+        } else if self.len == 0 {
+            Change::Removed
+        } else {
+            Change::Modified
+        }
+    }
+
+    /// Whether the hunk is shown on `line`, removed lines on the line after them
+    fn shown_on(&self, line: usize) -> bool {
+        self.start <= line && line < self.start + self.len.max(1)
+    }
+}
+
+/// The hunks of a buffer against its file at HEAD, diffed again when it changes
+#[derive(Default)]
+pub struct Hunks {
+    /// the text of the file at HEAD, lines ending with `\n`, none out of a
+    /// repository
+    head: Option<String>,
+    /// sorted by their first line
+    hunks: Vec<Hunk>,
+}
+
+impl Hunks {
+    /// Diffs `text` against `head` from now on
+    pub fn set_head(&mut self, head: Option<String>, text: &Rope) {
+        self.head = head;
+        self.update(text);
+    }
+
+    pub fn has_head(&self) -> bool {
+        self.head.is_some()
+    }
+
+    pub fn update(&mut self, text: &Rope) {
+        self.hunks.clear();
+        let head = match &self.head {
+            Some(head) if text.len_bytes() <= MAX_DIFFED_BYTES => head,
+            _ => return,
+        };
+        let old: Vec<&str> = head.split_inclusive('\n').collect();
+        // Ropes end with an empty line after the last `\n`
+        let new: Vec<RopeSlice> = text.lines().filter(|line| line.len_chars() > 0).collect();
+        // An edit changes a few lines, the ones around are not diffed nor copied
+        // out of the rope
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| b == *a).count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| b == *a)
+            .count();
+        let old = &old[prefix..old.len() - suffix];
+        let changed: Vec<String> = new[prefix..new.len() - suffix]
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        let new: Vec<&str> = changed.iter().map(String::as_str).collect();
+        let edits = diff::shortest_edits(old, &new, MAX_CHANGES).unwrap_or_else(|| {
+            let deleted = old.iter().map(|line| Edit::Delete(line));
+            deleted
+                .chain(new.iter().map(|line| Edit::Insert(line)))
+                .collect()
+        });
+
+        let mut line = prefix;
+        for edit in edits {
+            match edit {
+                Edit::Keep(_) => {
+                    line += 1;
+                    continue;
+                }
+                Edit::Delete(_) | Edit::Insert(_) => {}
+            }
+            let hunk = match self.hunks.last_mut() {
+                Some(hunk) if hunk.start + hunk.len == line => hunk,
+                _ => {
+                    self.hunks.push(Hunk {
+                        start: line,
+                        len: 0,
+                        removed: Vec::new(),
+                    });
+                    self.hunks.last_mut().unwrap()
+                }
+            };
+            match edit {
+                Edit::Delete(removed) => hunk.removed.push(removed.to_string()),
+                _ => {
+                    hunk.len += 1;
+                    line += 1;
+                }
+            }
+        }
+    }
+
+    pub fn hunks(&self) -> &[Hunk] {
+        &self.hunks
+    }
+
+    /// The change shown on `line`
+    pub fn change_at(&self, line: usize) -> Option<Change> {
+        self.at(line).map(Hunk::change)
+    }
+
+    /// The hunk shown on `line`
+    pub fn at(&self, line: usize) -> Option<&Hunk> {
+        self.hunks.iter().find(|hunk| hunk.shown_on(line))
+    }
+
+    /// The first hunk starting after `line`
+    pub fn next(&self, line: usize) -> Option<&Hunk> {
+        self.hunks.iter().find(|hunk| hunk.start > line)
+    }
+
+    /// The last hunk starting before `line`
+    pub fn previous(&self, line: usize) -> Option<&Hunk> {
+        self.hunks.iter().rev().find(|hunk| hunk.start < line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::harness::TestDir;
+
+    use super::*;
+
+    fn hunks(head: &str, text: &str) -> Vec<(usize, usize, Change)> {
+        let mut hunks = Hunks::default();
+        hunks.set_head(Some(head.to_string()), &Rope::from_str(text));
+        hunks
+            .hunks()
+            .iter()
+            .map(|hunk| (hunk.start, hunk.len, hunk.change()))
+            .collect()
+    }
+
+    #[test]
+    fn finds_changed_lines() {
+        assert_eq!(hunks("a\nb\n", "a\nb\n"), vec![]);
+        assert_eq!(
+            hunks("a\nb\nc\nd\n", "a\nB\nc\nd\ne\n"),
+            vec![(1, 1, Change::Modified), (4, 1, Change::Added)]
+        );
+        assert_eq!(hunks("a\nb\nc\n", "c\n"), vec![(0, 0, Change::Removed)]);
+        assert_eq!(
+            hunks("a\nb\nc\n", "a\nx\ny\nc\n"),
+            vec![(1, 2, Change::Modified)]
+        );
+        // The last line loses or gains its newline
+        assert_eq!(hunks("a\nb\n", "a\nb"), vec![(1, 1, Change::Modified)]);
+        assert_eq!(hunks("a\nb", "a\nb\nc"), vec![(1, 2, Change::Modified)]);
+        let mut hunks = Hunks::default();
+        hunks.set_head(Some("a\nb\n".to_string()), &Rope::from_str("a\n"));
+        assert_eq!(hunks.at(1).unwrap().removed, vec!["b\n".to_string()]);
+        assert_eq!(hunks.next(0).map(|hunk| hunk.start), Some(1));
+        assert_eq!(hunks.previous(1), None);
+    }
+
+    #[test]
+    fn reads_files_at_head() {
+        let dir = TestDir::new("git");
+        fs::create_dir(dir.0.join("src")).unwrap();
+        let blob = dir.write_object(1, "blob", b"fn main() {}\n");
+        let mut src = b"100644 main.rs\0".to_vec();
+        src.extend_from_slice(&blob);
+        let src = dir.write_object(2, "tree", &src);
+        let mut root = b"40000 src\0".to_vec();
+        root.extend_from_slice(&src);
+        let root = dir.write_object(3, "tree", &root);
+        let commit = format!("tree {}\n\nfirst\n", to_hex(&root));
+        let commit = dir.write_object(4, "commit", commit.as_bytes());
+        let git_dir = dir.0.join(".git");
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(
+            git_dir.join("packed-refs"),
+            format!(
+                "# pack-refs with: peeled\n{} refs/heads/main\n",
+                to_hex(&commit)
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(
+            head_content(&dir.0.join("src/main.rs")).as_deref(),
+            Some(&b"fn main() {}\n"[..])
+        );
+        assert_eq!(head_content(&dir.0.join("src/lib.rs")), None);
+        assert_eq!(head_content(&dir.0.join("src")), None);
+    }
+
+    #[test]
+    fn applies_deltas() {
+        let base = b"hello world";
+        // Sizes 11 and 16, a copy of 6 bytes at 0, then 10 inserted bytes
+        let delta = b"\x0b\x10\x90\x06\x0aeveryone!\n";
+        assert_eq!(apply_delta(base, delta).unwrap(), b"hello everyone!\n");
+        assert!(apply_delta(b"hello", delta).is_err());
+    }
+}
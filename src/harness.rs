@@ -0,0 +1,601 @@
+//! Drives the editor of the binary with scripted events, drawing on a screen in
+//! memory instead of the terminal
+
+use std::{
+    collections::VecDeque,
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use flate2::{write::ZlibEncoder, Compression};
+use ropey::Rope;
+
+use crate::{
+    backend::{EventSource, TestBackend},
+    builder::EditorBuilder,
+    editor::TextEditor,
+    encoding::FileFormat,
+    widget::widget::{ProcessEvent, WidgetType},
+};
+
+pub struct Harness {
+    pub editor: TextEditor,
+    pub screen: TestBackend,
+}
+
+impl Harness {
+    /// An editor of `width` x `height` cells showing `text`, the file of the
+    /// buffer being `save_path`
+    pub fn new(text: &str, save_path: &Path, width: usize, height: usize) -> Self {
+        let screen = TestBackend::new(width, height);
+        let editor = EditorBuilder::new()
+            .text(
+                Rope::from_str(text),
+                FileFormat::default(),
+                save_path.to_path_buf(),
+            )
+            // The command lines typed by tests stay out of the user's history
+            .history_dir(None)
+            .size(width, height)
+            .backend(screen.clone())
+            .build()
+            .unwrap();
+        Self { editor, screen }
+    }
+
+    /// Plays `event` then ticks, like the loop of [`EditorBuilder::run`]
+    pub fn event(&mut self, event: Event) {
+        self.editor.event(&event);
+        self.editor.tick();
+    }
+
+    pub fn key(&mut self, code: KeyCode) {
+        self.event(key_event(code, KeyModifiers::NONE));
+    }
+
+    pub fn ctrl(&mut self, c: char) {
+        self.event(key_event(KeyCode::Char(c), KeyModifiers::CONTROL));
+    }
+
+    /// Types `text` key by key, a newline being Enter
+    pub fn type_text(&mut self, text: &str) {
+        for c in text.chars() {
+            match c {
+                '\n' => self.key(KeyCode::Enter),
+                c => self.key(KeyCode::Char(c)),
+            }
+        }
+    }
+
+    /// Types `line` in the command line opened with ctrl+e and runs it
+    pub fn command(&mut self, line: &str) {
+        self.ctrl('e');
+        self.type_text(line);
+        self.key(KeyCode::Enter);
+    }
+
+    fn panel(&self) -> &dyn ProcessEvent {
+        self.editor
+            .get_widget(WidgetType::Panel)
+            .expect("the panel is always there")
+            .as_ref()
+    }
+
+    /// The text of the panel
+    pub fn text(&self) -> String {
+        self.panel().get_buffer().to_string()
+    }
+
+    /// The char index of the cursor in the panel
+    pub fn text_position(&self) -> usize {
+        self.panel().get_text_position()
+    }
+
+    /// Ticks until the jobs, like filters, ended and sent everything
+    pub fn wait_for_jobs(&mut self) {
+        let started = Instant::now();
+        while self.editor.jobs.is_running() {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "a job never ended"
+            );
+            thread::sleep(Duration::from_millis(5));
+            self.editor.tick();
+        }
+    }
+
+    /// Where the cursor is drawn on the screen
+    pub fn cursor(&self) -> (u16, u16) {
+        self.screen.cursor()
+    }
+
+    /// The first `count` rows of the screen
+    pub fn rows(&self, count: usize) -> Vec<String> {
+        self.screen.lines().into_iter().take(count).collect()
+    }
+}
+
+/// Events played in turn by [`EditorBuilder::run`], running out of them before
+/// the editor quits is a failure
+pub struct Script(pub VecDeque<Event>);
+
+impl EventSource for Script {
+    fn next_event(&mut self, _timeout: Duration) -> Option<Event> {
+        Some(
+            self.0
+                .pop_front()
+                .expect("the script ended before the editor quit"),
+        )
+    }
+}
+
+/// An 80 x 10 editor showing `text` as `file.txt` of a [`TestDir`] named `name`
+pub(crate) fn harness(name: &str, text: &str) -> (Harness, TestDir, PathBuf) {
+    let dir = TestDir::new(name);
+    let path = dir.0.join("file.txt");
+    (Harness::new(text, &path, 80, 10), dir, path)
+}
+
+pub fn key_event(code: KeyCode, modifiers: KeyModifiers) -> Event {
+    Event::Key(KeyEvent::new(code, modifiers))
+}
+
+/// A directory of its own for a test, removed with its files once dropped
+pub struct TestDir(pub PathBuf);
+
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        let dir = env::temp_dir().join(format!("harness-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    /// Writes a loose object of the repository of the directory, with the id
+    /// `[n; 20]` since the objects only need to point to each other
+    pub fn write_object(&self, n: u8, kind: &str, content: &[u8]) -> [u8; 20] {
+        let mut object = format!("{} {}\0", kind, content.len()).into_bytes();
+        object.extend_from_slice(content);
+        let hex = format!("{:02x}", n);
+        let dir = self.0.join(".git/objects").join(&hex);
+        fs::create_dir_all(&dir).unwrap();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&object).unwrap();
+        fs::write(dir.join(hex.repeat(19)), encoder.finish().unwrap()).unwrap();
+        [n; 20]
+    }
+
+    /// Makes the directory a repository whose HEAD has the file `name` with
+    /// `content`
+    pub fn commit(&self, name: &str, content: &str) {
+        let blob = self.write_object(1, "blob", content.as_bytes());
+        let mut tree = format!("100644 {}\0", name).into_bytes();
+        tree.extend_from_slice(&blob);
+        self.write_object(2, "tree", &tree);
+        let commit = format!("tree {}\n\nfirst\n", "02".repeat(20));
+        self.write_object(3, "commit", commit.as_bytes());
+        fs::create_dir_all(self.0.join(".git/refs/heads")).unwrap();
+        fs::write(self.0.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(self.0.join(".git/refs/heads/main"), "03".repeat(20)).unwrap();
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::style::Color;
+
+    use super::*;
+
+    #[test]
+    fn draws_the_standard_widgets() {
+        let (harness, _dir, path) = harness("draw", "first\nsecond\n");
+        let rows = harness.screen.lines();
+        assert_eq!(rows.len(), 10);
+        assert!(rows[0].ends_with("first"), "{:?}", rows[0]);
+        assert!(rows[1].ends_with("second"), "{:?}", rows[1]);
+        assert!(rows[0].trim_start().starts_with('1'), "{:?}", rows[0]);
+        // The status bar shows the file
+        assert!(rows[8].contains(&*path.to_string_lossy()), "{:?}", rows[8]);
+        // The cursor starts on the first char of the panel, after the line numbers
+        assert_eq!(harness.cursor(), (8, 0));
+        assert!(harness.screen.is_cursor_visible());
+    }
+
+    #[test]
+    fn edits_the_buffer() {
+        let (mut harness, _dir, _) = harness("edit", "world\n");
+        harness.type_text("hello ");
+        assert_eq!(harness.text(), "hello world\n");
+        assert_eq!(harness.text_position(), 6);
+        assert_eq!(harness.cursor(), (14, 0));
+        for _ in 0..5 {
+            harness.key(KeyCode::Right);
+        }
+        harness.type_text("\n!");
+        harness.key(KeyCode::Left);
+        harness.key(KeyCode::Backspace);
+        assert_eq!(harness.text(), "hello world!\n");
+        assert_eq!(harness.cursor(), (8 + 11, 0));
+        assert!(harness.editor.written);
+        assert_eq!(harness.rows(2), vec!["       1hello world!", "       1"]);
+        // Modified
+        assert!(harness.screen.lines()[8].contains("file.txt*"));
+    }
+
+    #[test]
+    fn undoes_and_redoes() {
+        let (mut harness, _dir, _) = harness("undo", "abc\n");
+        for _ in 0..3 {
+            harness.key(KeyCode::Right);
+        }
+        harness.type_text("def");
+        assert_eq!(harness.text(), "abcdef\n");
+        harness.ctrl('z');
+        assert_eq!(harness.text(), "abc\n");
+        assert_eq!(harness.cursor(), (11, 0));
+        harness.ctrl('y');
+        assert_eq!(harness.text(), "abcdef\n");
+        assert_eq!(harness.cursor(), (14, 0));
+    }
+
+    #[test]
+    fn finds_matches() {
+        let (mut harness, _dir, _) = harness("find", "one\ntwo foo\nthree foo\n");
+        // Typing finds the first match, Enter goes to the next one
+        harness.command(":find foo");
+        assert_eq!(harness.editor.search.positions.len(), 2);
+        assert_eq!(harness.text_position(), 18);
+        // The command line stays open for the next Enter
+        assert_eq!(harness.cursor(), (9, 9));
+        harness.key(KeyCode::Enter);
+        assert_eq!(harness.text_position(), 8);
+        harness.key(KeyCode::Esc);
+        assert_eq!(harness.cursor(), (8 + 4, 1));
+        // The search does not touch the text
+        assert_eq!(harness.text(), "one\ntwo foo\nthree foo\n");
+    }
+
+    #[test]
+    fn saves_the_buffer() {
+        let (mut harness, _dir, path) = harness("save", "draft\n");
+        harness.type_text("final ");
+        harness.command(":w");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "final draft\n");
+        assert!(!harness.editor.written);
+        // Told in a toast in the top right corner
+        assert!(harness.rows(1)[0].ends_with(&format!("Saved {}", path.display())));
+        let style = harness.screen.style_at(79, 0);
+        assert_eq!(style.background_color, Some(Color::Green));
+        // A copy goes to another file, asking before replacing it
+        let copy = path.with_file_name("copy.txt");
+        fs::write(&copy, "old\n").unwrap();
+        harness.command(&format!(":w {}", copy.display()));
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "old\n");
+        assert!(harness.screen.lines()[9].starts_with("Overwrite"));
+        harness.type_text("y");
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "final draft\n");
+        // The focus went back to the panel
+        harness.type_text("!");
+        assert_eq!(harness.text(), "final !draft\n");
+    }
+
+    #[test]
+    fn refuses_edits_and_saves_when_read_only() {
+        let (mut harness, _dir, path) = harness("readonly", "kept\n");
+        fs::write(&path, "kept\n").unwrap();
+        harness.command(":set readonly");
+        harness.type_text("x");
+        assert!(harness.rows(1)[0].ends_with("Read-only buffer"));
+        harness.key(KeyCode::Enter);
+        harness.key(KeyCode::Backspace);
+        harness.key(KeyCode::Delete);
+        assert_eq!(harness.text(), "kept\n");
+        assert!(!harness.editor.written);
+
+        harness.command(":w");
+        assert!(harness.rows(1)[0].contains("The buffer is read-only"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "kept\n");
+        // The command line keeps the failed command
+        harness.key(KeyCode::Backspace);
+        harness.key(KeyCode::Backspace);
+        let copy = path.with_file_name("copy.txt");
+        harness.command(&format!(":w {}", copy.display()));
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "kept\n");
+
+        harness.command(":set noreadonly");
+        harness.type_text("x");
+        assert_eq!(harness.text(), "xkept\n");
+    }
+
+    #[test]
+    fn keeps_the_changes_saved_to_a_copy() {
+        let (mut harness, _dir, path) = harness("copy", "draft\n");
+        harness.type_text("final ");
+        let copy = path.with_file_name("copy.txt");
+        harness.command(&format!(":w {}", copy.display()));
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "final draft\n");
+        // The file of the buffer is still the original, not saved yet
+        assert!(harness.editor.written);
+        assert_eq!(harness.editor.save_path, path);
+        assert!(harness.screen.lines()[8].starts_with(&path.display().to_string()));
+        harness.command(":q");
+        assert!(harness.editor.running);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn saves_text_without_a_file_to_the_file_it_goes_to() {
+        let dir = TestDir::new("stdin");
+        let mut harness = Harness::new("piped\n", Path::new(""), 80, 10);
+        harness.type_text("x");
+        let path = dir.0.join("out.txt");
+        harness.command(&format!(":wq {}", path.display()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "xpiped\n");
+        assert_eq!(harness.editor.save_path, path);
+        assert!(!harness.editor.running);
+    }
+
+    #[test]
+    fn forgets_the_changes_of_a_reloaded_file() {
+        let (mut harness, _dir, path) = harness("reload", "draft\n");
+        harness.type_text("final ");
+        harness.command(":w");
+        harness.type_text("more ");
+        fs::write(&path, "rewritten\n").unwrap();
+        harness.command(":reload!");
+        assert_eq!(harness.text(), "rewritten\n");
+        // Nothing left to undo or redo in the new text
+        harness.ctrl('z');
+        harness.ctrl('y');
+        assert_eq!(harness.text(), "rewritten\n");
+        harness.type_text("x");
+        harness.ctrl('z');
+        assert_eq!(harness.text(), "rewritten\n");
+    }
+
+    #[test]
+    fn detects_the_filetype() {
+        let (mut harness, dir, _) = harness("filetype", "#!/usr/bin/env python3\n");
+        let syntax = |harness: &Harness| harness.panel().get_syntax().unwrap().name.clone();
+        assert_eq!(syntax(&harness), "Python");
+        // Opening another file detects its filetype again
+        let makefile = dir.0.join("Makefile");
+        fs::write(&makefile, "all:\n").unwrap();
+        harness.command(&format!(":open {}", makefile.display()));
+        assert_eq!(syntax(&harness), "Makefile");
+        // Until another file is opened
+        harness.command(":setfiletype sh");
+        assert_eq!(syntax(&harness), "Bourne Again Shell (bash)");
+        harness.command(":w");
+        assert_eq!(syntax(&harness), "Bourne Again Shell (bash)");
+        harness.command(":setf");
+        assert!(harness.rows(1)[0].ends_with("filetype=Bourne Again Shell (bash)"));
+        let script = dir.0.join("script");
+        fs::write(&script, "#!/usr/bin/ruby -w\n").unwrap();
+        harness.command(&format!(":open {}", script.display()));
+        assert_eq!(syntax(&harness), "Ruby");
+        // The command line keeps the failed command for fixing it
+        harness.command(":setf nope");
+        assert!(harness.rows(1)[0].ends_with("Unknown filetype: nope"));
+        assert_eq!(syntax(&harness), "Ruby");
+    }
+
+    #[test]
+    fn folds_blocks() {
+        let dir = TestDir::new("fold");
+        let text = "fn main() {\n    let a = 1;\n    if a > 0 {\n        print(a);\n    }\n}\n\
+                    fn other() {\n    find_me();\n}\n";
+        let mut harness = Harness::new(text, &dir.0.join("main.rs"), 40, 12);
+        // Where folds start, in the line numbers
+        assert_eq!(harness.rows(3)[0], "▾      1fn main() {");
+        assert_eq!(harness.rows(3)[2], "▾      2    if a > 0 {");
+        harness.command(":foldall");
+        assert_eq!(
+            harness.rows(4),
+            vec![
+                "▸      1fn main() { ⋯ 5 lines",
+                "▸      1fn other() { ⋯ 2 lines",
+                "       2",
+                "",
+            ]
+        );
+        // Down goes over the closed fold
+        harness.key(KeyCode::Down);
+        assert_eq!(harness.cursor(), (8, 1));
+        // A match in a closed fold opens it
+        harness.command(":find find_me");
+        harness.key(KeyCode::Esc);
+        assert_eq!(harness.cursor(), (8 + 4, 2));
+        assert_eq!(harness.rows(3)[1], "▾      1fn other() {");
+        // Closing the fold puts the cursor on its first line
+        harness.ctrl('k');
+        assert_eq!(harness.cursor(), (8, 1));
+        assert_eq!(harness.rows(2)[1], "▸      7fn other() { ⋯ 2 lines");
+        harness.ctrl('k');
+        assert_eq!(harness.rows(3)[1], "▾      7fn other() {");
+        assert_eq!(harness.text(), text);
+    }
+
+    #[test]
+    fn marks_changes_since_head() {
+        let dir = TestDir::new("gutter");
+        dir.commit("notes.txt", "one\ntwo\nthree\nfour\n");
+        let text = "one\n2\nthree\nfour\nfive\n";
+        let mut harness = Harness::new(text, &dir.0.join("notes.txt"), 60, 10);
+        assert_eq!(
+            harness.rows(5),
+            vec![
+                "       1one",
+                " ~     12",
+                "       2three",
+                "       3four",
+                " +     4five",
+            ]
+        );
+        harness.command(":gitnext");
+        assert_eq!(harness.cursor(), (8, 1));
+        harness.command(":gitnext");
+        assert_eq!(harness.cursor(), (8, 4));
+        harness.command(":gitprev");
+        assert_eq!(harness.cursor(), (8, 1));
+        harness.command(":gitrevert");
+        assert_eq!(harness.text(), "one\ntwo\nthree\nfour\nfive\n");
+        assert_eq!(harness.rows(2)[1], "       2two");
+        // The markers follow the edits
+        harness.type_text("2");
+        assert_eq!(harness.rows(2)[1], " ~     22two");
+        harness.key(KeyCode::Home);
+        harness.key(KeyCode::Enter);
+        assert_eq!(harness.rows(2), vec![" +     1", "       2one"]);
+        harness.ctrl('z');
+        harness.ctrl('z');
+        assert_eq!(harness.text(), "one\ntwo\nthree\nfour\nfive\n");
+        assert_eq!(harness.rows(2)[1], "       2two");
+        harness.command(":gitrevert");
+        assert!(harness.rows(1)[0].ends_with("No change at the cursor"));
+        for _ in 0..":gitrevert".len() {
+            harness.key(KeyCode::Backspace);
+        }
+
+        // Reloading diffs against the new HEAD
+        let committed = "one\ntwo\nthree\nfour\nfive\n";
+        dir.commit("notes.txt", committed);
+        fs::write(dir.0.join("notes.txt"), committed).unwrap();
+        harness.command(":reload!");
+        assert_eq!(harness.rows(5)[4], "       3five");
+    }
+
+    #[test]
+    fn embeds_a_configured_editor() {
+        let dir = TestDir::new("embed");
+        let path = dir.0.join("COMMIT_EDITMSG");
+        let screen = TestBackend::new(60, 8);
+        let events = ['f', 'i', 'x']
+            .into_iter()
+            .map(|c| key_event(KeyCode::Char(c), KeyModifiers::NONE))
+            .chain([key_event(KeyCode::Char('t'), KeyModifiers::CONTROL)]);
+        let editor = EditorBuilder::new()
+            .file(&path)
+            .line_numbers(false)
+            .status_bar(false)
+            .bind("ctrl+t", ":wq")
+            .history_dir(None)
+            .size(60, 8)
+            .backend(screen.clone())
+            .events(Script(events.collect()))
+            .run()
+            .unwrap();
+        assert!(!editor.running);
+        assert!(!editor.written);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fix");
+        // Neither line numbers nor status bar
+        let rows = screen.lines();
+        assert!(rows[0].starts_with("fix "), "{:?}", rows[0]);
+        assert!(rows[2..].iter().all(|row| row.is_empty()), "{:?}", rows);
+    }
+
+    #[test]
+    fn rejects_screens_too_small() {
+        let build = |width, height, bars| {
+            EditorBuilder::new()
+                .text(
+                    Rope::from_str("text"),
+                    FileFormat::default(),
+                    "file.txt".into(),
+                )
+                .line_numbers(bars)
+                .status_bar(bars)
+                .history_dir(None)
+                .size(width, height)
+                .backend(TestBackend::new(width, height))
+                .build()
+        };
+        let err = build(0, 0, true).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "file.txt: a screen of 0x0 is too small, 9x3 at least"
+        );
+        assert!(build(8, 3, true).is_err());
+        assert!(build(9, 2, true).is_err());
+        assert!(build(9, 3, true).is_ok());
+        assert!(build(0, 2, false).is_err());
+        assert!(build(1, 1, false).is_err());
+        assert!(build(1, 2, false).is_ok());
+    }
+
+    #[test]
+    fn filters_the_palette() {
+        let (mut harness, _dir, _) = harness("palette", "one\ntwo\n");
+        harness.ctrl('p');
+        harness.type_text("bufstart");
+        let rows = harness.rows(5);
+        assert!(rows[2].contains("> bufstart"), "{:?}", rows);
+        assert!(rows[3].contains("buffer-start"), "{:?}", rows);
+        assert!(!rows[4].contains("buffer"), "{:?}", rows);
+
+        harness.type_text("zz");
+        assert!(harness.rows(4)[3].contains("No matching command"));
+        for _ in 0.."bufstartzz".len() {
+            harness.key(KeyCode::Backspace);
+        }
+        harness.type_text("bufend");
+        harness.key(KeyCode::Enter);
+        // The palette closed and ran the command
+        assert!(!harness.rows(3)[2].contains('>'));
+        assert_eq!(harness.cursor(), (8, 2));
+    }
+
+    #[test]
+    fn recovers_the_buffer_after_a_panic() {
+        let dir = TestDir::new("panic");
+        let path = dir.0.join("file.txt");
+        fs::write(&path, "saved\n").unwrap();
+        // The file must be older than the swap file
+        thread::sleep(Duration::from_millis(20));
+
+        let crashed = std::panic::catch_unwind(|| {
+            let mut harness = Harness::new("saved\n", &path, 80, 10);
+            harness.type_text("unsaved ");
+            panic!("crash with unsaved changes");
+        });
+        assert!(crashed.is_err());
+
+        let mut harness = Harness::new("saved\n", &path, 80, 10);
+        let screen = harness.screen.lines().join("\n");
+        assert!(screen.contains("is newer than"), "{}", screen);
+        assert!(screen.contains("Recover it?"), "{}", screen);
+        // The command line waits with the answer
+        assert_eq!(harness.screen.lines()[9].trim_end(), ":recover");
+        harness.key(KeyCode::Enter);
+        assert_eq!(harness.text(), "unsaved saved\n");
+        assert!(harness.editor.written);
+    }
+
+    #[test]
+    fn completes_while_an_output_popup_is_shown() {
+        let (mut harness, _dir, _) = harness("complete", "text\n");
+        harness.command(":messages");
+        harness.ctrl('e');
+        harness.type_text(":setfile");
+        harness.key(KeyCode::Tab);
+        assert_eq!(harness.screen.lines()[9].trim_end(), ":setfiletype");
+        // From an empty command line it goes to the popup
+        for _ in 0..":setfiletype".len() {
+            harness.key(KeyCode::Backspace);
+        }
+        let on_command_line = harness.cursor();
+        harness.key(KeyCode::Tab);
+        assert_ne!(harness.cursor(), on_command_line);
+        assert_eq!(harness.screen.lines()[9].trim_end(), "");
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    path::Path,
+    sync::{Arc, OnceLock},
+};
+
+use ropey::Rope;
+use syntect::{
+    highlighting::{Theme, ThemeSet},
+    parsing::{SyntaxReference, SyntaxSet},
+    LoadingError,
+};
+
+use crate::filetype;
+
+/// The theme of widgets without one, or with an unknown one
+static DEFAULT_THEME: &str = "base16-ocean.dark";
+
+/// The syntaxes and themes the widgets are highlighted with
+///
+/// Loading them takes a while, [`Highlighter::shared`] loads the defaults once
+/// for every editor of the process.
+#[derive(Clone)]
+pub struct Highlighter {
+    /// the syntaxes bundled with syntect, never linked again
+    syntaxes: SyntaxSet,
+    /// the syntaxes added from folders, linked among themselves only since
+    /// linking every syntax takes a while, they win over the bundled ones
+    added: SyntaxSet,
+    /// never changed, shared by the copies
+    themes: Arc<ThemeSet>,
+}
+
+impl Highlighter {
+    /// The syntaxes and themes bundled with syntect
+    pub fn load_defaults() -> Self {
+        Self {
+            syntaxes: SyntaxSet::load_defaults_nonewlines(),
+            added: SyntaxSet::new(),
+            themes: Arc::new(ThemeSet::load_defaults()),
+        }
+    }
+
+    pub fn shared() -> Arc<Self> {
+        static SHARED: OnceLock<Arc<Highlighter>> = OnceLock::new();
+        SHARED
+            .get_or_init(|| Arc::new(Self::load_defaults()))
+            .clone()
+    }
+
+    /// The sets of syntaxes, the first ones winning
+    fn sets(&self) -> [&SyntaxSet; 2] {
+        [&self.added, &self.syntaxes]
+    }
+
+    /// The set `syntax` was linked in, which highlights with it
+    pub fn set_of(&self, syntax: &SyntaxReference) -> &SyntaxSet {
+        let added = self.added.syntaxes().iter().any(|s| s.name == syntax.name);
+        if added {
+            &self.added
+        } else {
+            &self.syntaxes
+        }
+    }
+
+    pub fn find_syntax_by_name(&self, name: &str) -> Option<&SyntaxReference> {
+        self.sets()
+            .into_iter()
+            .find_map(|set| set.find_syntax_by_name(name))
+    }
+
+    pub fn find_syntax_by_extension(&self, extension: &str) -> Option<&SyntaxReference> {
+        self.sets()
+            .into_iter()
+            .find_map(|set| set.find_syntax_by_extension(extension))
+    }
+
+    pub fn plain_text(&self) -> &SyntaxReference {
+        self.syntaxes.find_syntax_plain_text()
+    }
+
+    /// The syntax of the buffer of `path` showing `text`, by its modeline, its
+    /// file name or its first line, an empty path being text without a file
+    pub fn syntax_for(&self, path: &Path, text: &Rope) -> &SyntaxReference {
+        filetype::detect(&self.sets(), path, text)
+    }
+
+    /// The syntax of a filetype like `rust`, `sh` or `Makefile`
+    pub fn syntax_for_filetype(&self, filetype: &str) -> Option<&SyntaxReference> {
+        filetype::find(&self.sets(), filetype)
+    }
+
+    /// A filetype for each syntax: its name when it is one word, else its first
+    /// extension
+    pub fn filetype_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .sets()
+            .into_iter()
+            .flat_map(|set| set.syntaxes())
+            .filter(|syntax| !syntax.hidden)
+            .filter_map(|syntax| {
+                if syntax.name.contains(char::is_whitespace) {
+                    syntax.file_extensions.first().cloned()
+                } else {
+                    Some(syntax.name.to_lowercase())
+                }
+            })
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    pub fn has_theme(&self, name: &str) -> bool {
+        self.themes.themes.contains_key(name)
+    }
+
+    /// The theme called `name`, or the default one
+    pub fn theme(&self, name: Option<&str>) -> &Theme {
+        name.and_then(|name| self.themes.themes.get(name))
+            .unwrap_or(&self.themes.themes[DEFAULT_THEME])
+    }
+
+    /// The names of the themes, sorted
+    pub fn theme_names(&self) -> Vec<String> {
+        // The themes are kept in a BTreeMap
+        self.themes.themes.keys().cloned().collect()
+    }
+
+    /// Adds the `.sublime-syntax` files found in `dir` to the syntaxes
+    ///
+    /// They can include the syntaxes added before them, not the bundled ones.
+    pub fn add_syntaxes(&mut self, dir: &Path) -> Result<(), LoadingError> {
+        let mut builder = self.added.clone().into_builder();
+        builder.add_from_folder(dir, false)?;
+        self.added = builder.build();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use syntect::easy::HighlightLines;
+
+    use super::*;
+    use crate::harness::TestDir;
+
+    #[test]
+    fn adds_syntaxes() {
+        let mut highlighter = Highlighter::load_defaults();
+        assert_eq!(
+            highlighter.theme(Some("nope")).name,
+            highlighter.theme(None).name
+        );
+
+        let test_dir = TestDir::new("highlight");
+        let dir = &test_dir.0;
+        fs::write(
+            dir.join("todo.sublime-syntax"),
+            "%YAML 1.2\n---\nname: Todo\nfile_extensions: [todo]\nscope: text.todo\n\
+             contexts:\n  main:\n    - match: '^x .*'\n      scope: comment.done\n",
+        )
+        .unwrap();
+        let (path, text) = (Path::new("list.todo"), Rope::new());
+        assert_eq!(highlighter.syntax_for(path, &text).name, "Plain Text");
+        assert!(highlighter.syntax_for_filetype("todo").is_none());
+        highlighter.add_syntaxes(dir).unwrap();
+        assert_eq!(highlighter.syntax_for(path, &text).name, "Todo");
+        assert_eq!(
+            highlighter.syntax_for_filetype("todo").unwrap().name,
+            "Todo"
+        );
+        assert!(highlighter.filetype_names().contains(&"todo".to_string()));
+        // The added syntax highlights with its own set, the bundled ones too
+        let todo = highlighter.syntax_for(path, &text).clone();
+        let mut lines = HighlightLines::new(&todo, highlighter.theme(None));
+        let set = highlighter.set_of(&todo);
+        assert!(lines.highlight_line("x done", set).is_ok());
+        let rust = highlighter.syntax_for(Path::new("main.rs"), &text).clone();
+        let mut lines = HighlightLines::new(&rust, highlighter.theme(None));
+        let set = highlighter.set_of(&rust);
+        assert!(lines.highlight_line("fn main() {}", set).is_ok());
+    }
+}
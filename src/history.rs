@@ -0,0 +1,247 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+static HISTORY_SIZE: usize = 1000;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum HistoryKind {
+    /// `:` commands
+    Command,
+    /// `:find` patterns
+    Search,
+    /// shell commands
+    Shell,
+}
+
+impl HistoryKind {
+    fn file_name(&self) -> &'static str {
+        match self {
+            HistoryKind::Command => "command_history",
+            HistoryKind::Search => "search_history",
+            HistoryKind::Shell => "shell_history",
+        }
+    }
+}
+
+pub struct History {
+    pub entries: Vec<String>,
+    pub size: usize,
+    pub path: Option<PathBuf>,
+
+    /// entry shown while navigating with Up/Down
+    position: Option<usize>,
+    /// what was typed before navigating, also used as a prefix filter
+    draft: String,
+}
+
+impl History {
+    pub fn new(size: usize, path: Option<PathBuf>) -> Self {
+        Self {
+            entries: Vec::new(),
+            size,
+            path,
+            position: None,
+            draft: String::new(),
+        }
+    }
+
+    /// Loads the history from `path`, a missing file gives an empty history
+    pub fn load(path: &Path, size: usize) -> Self {
+        let mut history = Self::new(size, Some(path.to_path_buf()));
+        if let Ok(content) = fs::read_to_string(path) {
+            for line in content.lines() {
+                history.add(line);
+            }
+        }
+        history
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut content = self.entries.join("\n");
+        content.push('\n');
+        fs::write(path, content)
+    }
+
+    fn add(&mut self, entry: &str) {
+        if entry.trim().is_empty() {
+            return;
+        }
+        self.entries.retain(|e| e != entry);
+        self.entries.push(entry.to_string());
+        if self.entries.len() > self.size {
+            let overflow = self.entries.len() - self.size;
+            self.entries.drain(..overflow);
+        }
+    }
+
+    /// Adds an entry, moving it to the end if it is already known, and saves
+    pub fn push(&mut self, entry: &str) {
+        self.add(entry);
+        self.reset_navigation();
+        if let Err(e) = self.save() {
+            log::warn!("Failed to save history: {:?}", e);
+        }
+    }
+
+    pub fn reset_navigation(&mut self) {
+        self.position = None;
+        self.draft.clear();
+    }
+
+    pub fn is_navigating(&self) -> bool {
+        self.position.is_some()
+    }
+
+    /// Older entry starting with what was typed before navigating
+    pub fn prev(&mut self, current: &str) -> Option<String> {
+        if self.position.is_none() {
+            self.draft = current.to_string();
+        }
+        let end = self.position.unwrap_or(self.entries.len());
+        for i in (0..end).rev() {
+            if self.entries[i].starts_with(&self.draft) {
+                self.position = Some(i);
+                return Some(self.entries[i].clone());
+            }
+        }
+        None
+    }
+
+    /// Newer entry, or the draft once the end of the history is reached
+    pub fn next(&mut self) -> Option<String> {
+        let start = self.position? + 1;
+        for i in start..self.entries.len() {
+            if self.entries[i].starts_with(&self.draft) {
+                self.position = Some(i);
+                return Some(self.entries[i].clone());
+            }
+        }
+        let draft = self.draft.clone();
+        self.reset_navigation();
+        Some(draft)
+    }
+
+    /// Index of the most recent entry before `before` containing `query`
+    pub fn search(&self, query: &str, before: Option<usize>) -> Option<usize> {
+        let end = before.unwrap_or(self.entries.len());
+        (0..end).rev().find(|&i| self.entries[i].contains(query))
+    }
+}
+
+/// Directory where the histories are persisted between sessions, in the state
+/// directory
+pub fn history_dir() -> Option<PathBuf> {
+    if let Ok(state) = env::var("XDG_STATE_HOME") {
+        if !state.is_empty() {
+            return Some(PathBuf::from(state).join("terminus"));
+        }
+    }
+    env::var("HOME")
+        .ok()
+        .map(|home| PathBuf::from(home).join(".local/state/terminus"))
+}
+
+/// One history per kind of command line input
+pub struct Histories {
+    pub command: History,
+    pub search: History,
+    pub shell: History,
+}
+
+/// Loads the history persisted in `dir` as `file_name`, no directory giving a
+/// history of the session only
+pub fn load_history(dir: Option<&Path>, file_name: &str) -> History {
+    match dir {
+        Some(dir) => History::load(&dir.join(file_name), HISTORY_SIZE),
+        None => History::new(HISTORY_SIZE, None),
+    }
+}
+
+impl Histories {
+    pub fn load(dir: Option<&Path>) -> Self {
+        Self {
+            command: load_history(dir, HistoryKind::Command.file_name()),
+            search: load_history(dir, HistoryKind::Search.file_name()),
+            shell: load_history(dir, HistoryKind::Shell.file_name()),
+        }
+    }
+
+    pub fn get_mut(&mut self, kind: HistoryKind) -> &mut History {
+        match kind {
+            HistoryKind::Command => &mut self.command,
+            HistoryKind::Search => &mut self.search,
+            HistoryKind::Shell => &mut self.shell,
+        }
+    }
+
+    pub fn reset_navigation(&mut self) {
+        self.command.reset_navigation();
+        self.search.reset_navigation();
+        self.shell.reset_navigation();
+    }
+}
+
+impl Default for Histories {
+    fn default() -> Self {
+        Self {
+            command: History::new(HISTORY_SIZE, None),
+            search: History::new(HISTORY_SIZE, None),
+            shell: History::new(HISTORY_SIZE, None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::TestDir;
+
+    #[test]
+    fn dedupes_and_caps_entries() {
+        let mut history = History::new(3, None);
+        for entry in ["w", "q", "", "  ", "w", "e", "x"] {
+            history.push(entry);
+        }
+        // The repeated `w` moved to the end, then `q` fell off
+        assert_eq!(history.entries, ["w", "e", "x"]);
+
+        let test_dir = TestDir::new("history");
+        let path = test_dir.0.join("state/command_history");
+        let mut history = History::load(&path, 3);
+        history.push("w");
+        history.push("q");
+        history.push("w");
+        assert_eq!(History::load(&path, 3).entries, ["q", "w"]);
+        assert_eq!(History::load(&path, 1).entries, ["w"]);
+    }
+
+    #[test]
+    fn navigates_by_prefix() {
+        let mut history = History::new(10, None);
+        for entry in ["set ff=dos", "w", "set fenc=latin-1", "q"] {
+            history.push(entry);
+        }
+        assert_eq!(history.next(), None);
+        assert_eq!(history.prev("set").as_deref(), Some("set fenc=latin-1"));
+        assert!(history.is_navigating());
+        assert_eq!(history.prev("ignored").as_deref(), Some("set ff=dos"));
+        assert_eq!(history.prev("ignored"), None);
+        assert_eq!(history.next().as_deref(), Some("set fenc=latin-1"));
+        // Past the newest entry the draft comes back
+        assert_eq!(history.next().as_deref(), Some("set"));
+        assert!(!history.is_navigating());
+
+        assert_eq!(history.prev("").as_deref(), Some("q"));
+        history.reset_navigation();
+        assert_eq!(history.prev("x"), None);
+    }
+}
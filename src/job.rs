@@ -0,0 +1,278 @@
+use std::{
+    os::unix::process::CommandExt,
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader},
+    process::Command,
+    runtime::Runtime,
+    sync::{mpsc, oneshot},
+};
+
+static SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+/// How long the output is still read once the command exited, a process it
+/// left in the background can keep the pipes open
+const OUTPUT_DRAIN: Duration = Duration::from_millis(200);
+
+/// What a running shell command reports back to the editor
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobEvent {
+    Stdout(String),
+    Stderr(String),
+    /// exit code, `None` when killed by a signal
+    Exited(Option<i32>),
+    /// the command could not be started
+    Failed(String),
+}
+
+/// A shell command running on the runtime
+pub struct Job {
+    pub id: usize,
+    pub command: String,
+    pub running: bool,
+    events: mpsc::UnboundedReceiver<JobEvent>,
+    kill: Option<oneshot::Sender<()>>,
+}
+
+impl Job {
+    /// Asks the child to stop, it reports `Exited(None)` once killed
+    pub fn kill(&mut self) {
+        if let Some(kill) = self.kill.take() {
+            let _ = kill.send(());
+        }
+    }
+}
+
+/// Sends every line of `reader` as an event, invalid UTF-8 is replaced
+async fn forward_lines<R: AsyncRead + Unpin>(
+    reader: R,
+    sender: mpsc::UnboundedSender<JobEvent>,
+    event: fn(String) -> JobEvent,
+) {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                let text = String::from_utf8_lossy(&line).to_string();
+                if sender.send(event(text)).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+async fn run(
+    command: String,
+    input: Option<String>,
+    sender: mpsc::UnboundedSender<JobEvent>,
+    mut kill: oneshot::Receiver<()>,
+) {
+    let mut std_command = std::process::Command::new("sh");
+    std_command
+        .arg("-c")
+        .arg(&command)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // Its own group, killing it also kills what it started
+        .process_group(0);
+    let child = Command::from(std_command).kill_on_drop(true).spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            let _ = sender.send(JobEvent::Failed(e.to_string()));
+            return;
+        }
+    };
+    // Written while the output is read, a big input would fill the pipes otherwise
+    if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+        tokio::spawn(async move {
+            let _ = stdin.write_all(input.as_bytes()).await;
+        });
+    }
+    let stdout = child
+        .stdout
+        .take()
+        .map(|stdout| tokio::spawn(forward_lines(stdout, sender.clone(), JobEvent::Stdout)));
+    let stderr = child
+        .stderr
+        .take()
+        .map(|stderr| tokio::spawn(forward_lines(stderr, sender.clone(), JobEvent::Stderr)));
+    let status = tokio::select! {
+        status = child.wait() => status,
+        // A dropped sender is no kill, `wait` is then the only branch left
+        Ok(()) = &mut kill => {
+            match child.id() {
+                Some(pid) => unsafe {
+                    libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+                },
+                None => {
+                    let _ = child.start_kill();
+                }
+            }
+            child.wait().await
+        }
+    };
+    // All the output is sent before the exit status
+    let deadline = tokio::time::Instant::now() + OUTPUT_DRAIN;
+    for mut reader in [stdout, stderr].into_iter().flatten() {
+        if tokio::time::timeout_at(deadline, &mut reader)
+            .await
+            .is_err()
+        {
+            reader.abort();
+        }
+    }
+    let _ = match status {
+        Ok(status) => sender.send(JobEvent::Exited(status.code())),
+        Err(e) => sender.send(JobEvent::Failed(e.to_string())),
+    };
+}
+
+/// An event of the job `id`, running `command`
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobUpdate {
+    pub id: usize,
+    pub command: String,
+    pub event: JobEvent,
+}
+
+/// Runs shell commands without blocking the UI, `poll` collects what they sent
+pub struct JobManager {
+    runtime: Option<Runtime>,
+    pub jobs: Vec<Job>,
+    next_id: usize,
+    started: Instant,
+}
+
+impl JobManager {
+    pub fn new() -> Self {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build();
+        if let Err(e) = &runtime {
+            log::error!("Failed to start the job runtime: {:?}", e);
+        }
+        Self {
+            runtime: runtime.ok(),
+            jobs: Vec::new(),
+            next_id: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Starts `command` with `sh -c`, fed with `input`, returns the id of the job
+    pub fn spawn(&mut self, command: &str, input: Option<String>) -> Result<usize, String> {
+        let runtime = match &self.runtime {
+            Some(runtime) => runtime,
+            None => return Err("no runtime to run jobs on".to_string()),
+        };
+        let (sender, events) = mpsc::unbounded_channel();
+        let (kill, kill_receiver) = oneshot::channel();
+        runtime.spawn(run(command.to_string(), input, sender, kill_receiver));
+        let id = self.next_id;
+        self.next_id += 1;
+        self.jobs.push(Job {
+            id,
+            command: command.to_string(),
+            running: true,
+            events,
+            kill: Some(kill),
+        });
+        Ok(id)
+    }
+
+    /// Events sent by the jobs since the last call, finished jobs are dropped
+    pub fn poll(&mut self) -> Vec<JobUpdate> {
+        let mut updates = Vec::new();
+        for job in &mut self.jobs {
+            while let Ok(event) = job.events.try_recv() {
+                if let JobEvent::Exited(_) | JobEvent::Failed(_) = event {
+                    job.running = false;
+                }
+                updates.push(JobUpdate {
+                    id: job.id,
+                    command: job.command.clone(),
+                    event,
+                });
+            }
+        }
+        self.jobs.retain(|job| job.running);
+        updates
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.jobs.is_empty()
+    }
+
+    /// Kills every running job, returns how many were asked to stop
+    pub fn kill_all(&mut self) -> usize {
+        for job in &mut self.jobs {
+            job.kill();
+        }
+        self.jobs.len()
+    }
+
+    /// Frame of the spinner shown while jobs run, it turns every 100ms
+    pub fn spinner(&self) -> char {
+        let frame = self.started.elapsed().as_millis() / 100;
+        SPINNER[frame as usize % SPINNER.len()]
+    }
+}
+
+impl Default for JobManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The events of the job until it ends, within `timeout`
+    fn events_until_end(jobs: &mut JobManager, timeout: Duration) -> Vec<JobEvent> {
+        let started = Instant::now();
+        let mut events = Vec::new();
+        while jobs.is_running() && started.elapsed() < timeout {
+            events.extend(jobs.poll().into_iter().map(|update| update.event));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        events
+    }
+
+    #[test]
+    fn ends_jobs() {
+        let mut jobs = JobManager::new();
+        jobs.spawn("echo out; echo err >&2; exit 3", None).unwrap();
+        let events = events_until_end(&mut jobs, Duration::from_secs(5));
+        assert!(events.contains(&JobEvent::Stdout("out\n".to_string())));
+        assert!(events.contains(&JobEvent::Stderr("err\n".to_string())));
+        assert_eq!(events.last(), Some(&JobEvent::Exited(Some(3))));
+
+        // A process left in its own session keeps stdout open
+        let started = Instant::now();
+        jobs.spawn("echo out; setsid sleep 10 &", None).unwrap();
+        let events = events_until_end(&mut jobs, Duration::from_secs(5));
+        assert_eq!(events.last(), Some(&JobEvent::Exited(Some(0))));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let started = Instant::now();
+        jobs.spawn("sleep 10", None).unwrap();
+        jobs.kill_all();
+        let events = events_until_end(&mut jobs, Duration::from_secs(5));
+        assert_eq!(events, vec![JobEvent::Exited(None)]);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,190 @@
+use std::{borrow::Cow, fs::File, path::Path};
+
+use memmap2::Mmap;
+use regex::{Regex, RegexBuilder};
+use ropey::{Rope, RopeBuilder};
+
+use crate::{
+    encoding::{self, Encoding, FileFormat, LineEnding},
+    file_io::FileError,
+};
+
+/// Files bigger than this are opened in large-file mode: mapped, not highlighted,
+/// searched a batch of lines at a time and never copied whole
+pub const LARGE_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How much of the file is decoded at once
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// How much text is searched at once, made of whole lines
+const BATCH_SIZE: usize = 1024 * 1024;
+
+pub fn is_large(path: &Path) -> bool {
+    path.metadata()
+        .map(|metadata| metadata.len() > LARGE_FILE_SIZE)
+        .unwrap_or(false)
+}
+
+/// Maps the file and builds its rope chunk by chunk
+pub fn load(path: &Path) -> Result<(Rope, FileFormat), FileError> {
+    if path.is_dir() {
+        return Err(FileError::IsADirectory(path.to_path_buf()));
+    }
+    let file = File::open(path).map_err(|e| FileError::new(path, e))?;
+    // The map is only read while building the rope, if the file is truncated
+    // meanwhile the read faults, like any other program mapping files
+    let map = unsafe { Mmap::map(&file) }.map_err(|e| FileError::new(path, e))?;
+    Ok(rope_from_bytes(&map))
+}
+
+/// Decodes `bytes` into a rope without a `String` of the whole text, lines end
+/// with `\n` in the rope
+///
+/// UTF-16 is rare enough in big files to be decoded at once.
+pub fn rope_from_bytes(bytes: &[u8]) -> (Rope, FileFormat) {
+    let (bytes, encoding) = if let Some(rest) = bytes.strip_prefix(&[0xef, 0xbb, 0xbf]) {
+        (rest, Encoding::Utf8Bom)
+    } else if bytes.starts_with(&[0xff, 0xfe]) || bytes.starts_with(&[0xfe, 0xff]) {
+        let (text, format) = encoding::decode(bytes);
+        return (Rope::from_str(&text), format);
+    } else if std::str::from_utf8(bytes).is_ok() {
+        (bytes, Encoding::Utf8)
+    } else {
+        (bytes, Encoding::Latin1)
+    };
+    // The beginning tells how lines end, like it would for the whole file
+    let head = &bytes[..bytes.len().min(CHUNK_SIZE)];
+    let lf = head.iter().filter(|b| **b == b'\n').count();
+    let crlf = head.windows(2).filter(|w| w == b"\r\n").count();
+    let line_ending = if crlf > lf - crlf {
+        LineEnding::Crlf
+    } else {
+        LineEnding::Lf
+    };
+
+    let mut builder = RopeBuilder::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let mut end = rest.len().min(CHUNK_SIZE);
+        if end < rest.len() {
+            // Never split a char, nor a CRLF
+            while end > 1 && encoding != Encoding::Latin1 && rest[end] & 0xc0 == 0x80 {
+                end -= 1;
+            }
+            if rest[end - 1] == b'\r' && rest[end] == b'\n' {
+                end -= 1;
+            }
+        }
+        let chunk = match encoding {
+            Encoding::Latin1 => Cow::Owned(rest[..end].iter().map(|b| *b as char).collect()),
+            _ => String::from_utf8_lossy(&rest[..end]),
+        };
+        if chunk.contains("\r\n") {
+            builder.append(&chunk.replace("\r\n", "\n"));
+        } else {
+            builder.append(&chunk);
+        }
+        rest = &rest[end..];
+    }
+    (
+        builder.finish(),
+        FileFormat {
+            encoding,
+            line_ending,
+            bom: false,
+        },
+    )
+}
+
+/// Finds the first match of `regex` at or after the char `from`, wrapping around
+/// the end of the rope, returns its char position and length
+///
+/// The text is searched a batch of whole lines at a time, so it is never copied
+/// whole, `^` and `$` match at every line.
+pub fn find_next(rope: &Rope, regex: &Regex, from: usize) -> Option<(usize, usize)> {
+    let regex = RegexBuilder::new(regex.as_str())
+        .multi_line(true)
+        .build()
+        .ok()?;
+    let from = from.min(rope.len_chars());
+    // Wrapped around, anything before `from` comes after the end
+    find_from(rope, &regex, from).or_else(|| find_from(rope, &regex, 0))
+}
+
+fn find_from(rope: &Rope, regex: &Regex, from: usize) -> Option<(usize, usize)> {
+    let first_line = rope.char_to_line(from);
+    let mut batch_start = rope.line_to_char(first_line);
+    // Matches before `from` on its line do not count
+    let mut skip = rope.slice(batch_start..from).len_bytes();
+    let mut batch = String::new();
+    let mut lines = rope.lines_at(first_line).peekable();
+    while lines.peek().is_some() {
+        batch.clear();
+        for line in lines.by_ref() {
+            for chunk in line.chunks() {
+                batch.push_str(chunk);
+            }
+            if batch.len() >= BATCH_SIZE {
+                break;
+            }
+        }
+        let found = regex
+            .find_iter(&batch)
+            .find(|m| !m.is_empty() && m.start() >= skip);
+        if let Some(m) = found {
+            let position = batch_start + batch[..m.start()].chars().count();
+            return Some((position, m.as_str().chars().count()));
+        }
+        batch_start += batch.chars().count();
+        skip = 0;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_ropes_by_chunks() {
+        // Long enough for several chunks, with chars and CRLF on the chunk borders
+        let line = "h\u{e9}llo w\u{f6}rld\r\n";
+        let text = line.repeat(CHUNK_SIZE / line.len() * 3);
+        let (rope, format) = rope_from_bytes(text.as_bytes());
+        assert_eq!(format.line_ending, LineEnding::Crlf);
+        assert_eq!(format.encoding, Encoding::Utf8);
+        assert_eq!(rope, text.replace("\r\n", "\n").as_str());
+
+        let (rope, format) = rope_from_bytes(b"caf\xe9\n");
+        assert_eq!(format.encoding, Encoding::Latin1);
+        assert_eq!(rope, "caf\u{e9}\n");
+    }
+
+    #[test]
+    fn finds_matches_by_batches() {
+        let rope = Rope::from_str("one two\nthree two\nfour\n");
+        let regex = Regex::new("two").unwrap();
+        assert_eq!(find_next(&rope, &regex, 0), Some((4, 3)));
+        assert_eq!(find_next(&rope, &regex, 5), Some((14, 3)));
+        // Wraps around the end
+        assert_eq!(find_next(&rope, &regex, 15), Some((4, 3)));
+        assert_eq!(find_next(&rope, &Regex::new("five").unwrap(), 3), None);
+        assert_eq!(
+            find_next(&rope, &Regex::new("^t").unwrap(), 0),
+            Some((8, 1))
+        );
+        let rope = Rope::from_str("\u{e9}t\u{e9}\n");
+        assert_eq!(
+            find_next(&rope, &Regex::new("t\u{e9}").unwrap(), 0),
+            Some((1, 2))
+        );
+        // Past the first batch
+        let text = format!("{}\u{e9} needle\n", "\u{e9}\n".repeat(BATCH_SIZE));
+        let needle = text.chars().count() - "needle\n".len();
+        let rope = Rope::from_str(&text);
+        assert_eq!(
+            find_next(&rope, &Regex::new("needle").unwrap(), 10),
+            Some((needle, 6))
+        );
+    }
+}
@@ -0,0 +1,38 @@
+//! Terminus, a text editor for the terminal
+//!
+//! The `editor` binary is a thin layer over this crate: [`EditorBuilder`] sets up
+//! the same editor for other programs, drawing on the terminal or on any
+//! [`backend::Backend`].
+
+mod action;
+pub mod backend;
+pub mod builder;
+pub mod cli;
+pub mod command;
+mod completion;
+pub mod diff;
+pub mod editor;
+pub mod encoding;
+pub mod file_io;
+mod filetype;
+mod filter;
+pub mod fold;
+mod fuzzy;
+pub mod git;
+#[cfg(test)]
+mod harness;
+pub mod highlight;
+mod history;
+mod job;
+pub mod large_file;
+pub mod logger;
+pub mod message;
+mod pty;
+mod swap;
+pub mod terminal;
+mod vt;
+mod watch;
+pub mod widget;
+
+pub use builder::EditorBuilder;
+pub use editor::TextEditor;
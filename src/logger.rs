@@ -0,0 +1,245 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// The log file is rotated once it grows past this size
+static MAX_LOG_SIZE: u64 = 1024 * 1024;
+
+/// How many rotated files are kept, `terminus.log.1` being the newest
+static ROTATED_LOGS: usize = 3;
+
+/// Lines kept in memory for `:messages`
+static RECENT_LINES: usize = 500;
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Which records are kept: a default level, and levels for some modules
+///
+/// Written like `info,widget::panel=trace,job=off`, modules are named without
+/// the crate and cover their submodules.
+#[derive(PartialEq, Debug)]
+pub struct Filter {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut filter = Self {
+            default: LevelFilter::Info,
+            modules: Vec::new(),
+        };
+        for part in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+        {
+            let parse_level = |level: &str| {
+                level
+                    .parse::<LevelFilter>()
+                    .map_err(|_| format!("Invalid log level: {}", level))
+            };
+            match part.split_once('=') {
+                Some((module, level)) => {
+                    let module = module.trim().trim_start_matches("editor::");
+                    filter
+                        .modules
+                        .push((module.to_string(), parse_level(level.trim())?));
+                }
+                None => filter.default = parse_level(part)?,
+            }
+        }
+        // The most precise module wins
+        filter
+            .modules
+            .sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+        Ok(filter)
+    }
+
+    /// The level for records of `target`, a module path like `editor::widget::panel`
+    pub fn level(&self, target: &str) -> LevelFilter {
+        let target = target.strip_prefix("editor::").unwrap_or(target);
+        self.modules
+            .iter()
+            .find(|(module, _)| {
+                target == module
+                    || target
+                        .strip_prefix(module.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .chain([self.default])
+            .max()
+            .unwrap_or(LevelFilter::Off)
+    }
+}
+
+/// Appends to the log file, rotating it when it gets too big
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+}
+
+impl LogFile {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            len,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.len > MAX_LOG_SIZE {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.len += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// `terminus.log` becomes `terminus.log.1`, which becomes `terminus.log.2`...
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |idx: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", idx));
+            PathBuf::from(name)
+        };
+        for idx in (1..ROTATED_LOGS).rev() {
+            let _ = fs::rename(rotated(idx), rotated(idx + 1));
+        }
+        fs::rename(&self.path, rotated(1))?;
+        *self = Self::open(&self.path)?;
+        Ok(())
+    }
+}
+
+struct Logger {
+    filter: Filter,
+    file: Option<Mutex<LogFile>>,
+    recent: Mutex<VecDeque<String>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "{} {:5} {}: {}",
+            time_of_day(SystemTime::now()),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+            // Nowhere left to report it, the line stays in :messages
+            let _ = file.write_line(&line);
+        }
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        if recent.len() == RECENT_LINES {
+            recent.pop_front();
+        }
+        recent.push_back(line);
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap_or_else(|e| e.into_inner()).file.flush();
+        }
+    }
+}
+
+/// `HH:MM:SS` in UTC, enough to follow a session
+fn time_of_day(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+        % 86400;
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Sends the records of the `log` macros to `file` when there is one, and keeps
+/// the last ones for `:messages`, nothing goes to the screen
+pub fn init(file: Option<&Path>, filter: Filter) -> io::Result<()> {
+    let file = file.map(LogFile::open).transpose()?.map(Mutex::new);
+    let max_level = filter.max_level();
+    let logger = LOGGER.get_or_init(|| Logger {
+        filter,
+        file,
+        recent: Mutex::new(VecDeque::new()),
+    });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(max_level);
+    }
+    Ok(())
+}
+
+/// The last lines logged, oldest first
+pub fn recent() -> Vec<String> {
+    LOGGER
+        .get()
+        .map(|logger| {
+            let recent = logger.recent.lock().unwrap_or_else(|e| e.into_inner());
+            recent.iter().cloned().collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::TestDir;
+
+    #[test]
+    fn filters_by_module() {
+        let filter =
+            Filter::parse("warn, widget=debug, editor::widget::panel=trace,job=off").unwrap();
+        assert_eq!(filter.level("editor::editor"), LevelFilter::Warn);
+        assert_eq!(filter.level("editor::widget::popup"), LevelFilter::Debug);
+        assert_eq!(filter.level("editor::widget::panel"), LevelFilter::Trace);
+        assert_eq!(filter.level("editor::job"), LevelFilter::Off);
+        // Not a submodule of `job`
+        assert_eq!(filter.level("editor::jobs"), LevelFilter::Warn);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+        assert_eq!(Filter::parse("").unwrap().level("x"), LevelFilter::Info);
+        assert!(Filter::parse("widget=loud").is_err());
+    }
+
+    #[test]
+    fn rotates_the_log_file() {
+        let test_dir = TestDir::new("logger");
+        let dir = &test_dir.0;
+        let path = dir.join("terminus.log");
+        let mut file = LogFile::open(&path).unwrap();
+        let line = "x".repeat(1023);
+        for _ in 0..(MAX_LOG_SIZE as usize / 1024 + 1) * 2 {
+            file.write_line(&line).unwrap();
+        }
+        assert!(dir.join("terminus.log.1").exists());
+        assert!(fs::metadata(&path).unwrap().len() <= MAX_LOG_SIZE + 1024);
+    }
+}
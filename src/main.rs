@@ -1,165 +1,110 @@
-// 'aaaa' -> 'aaaa'
-mod action;
-mod editor;
-mod terminal;
-mod widget;
+use std::{env, fs, path::PathBuf, process};
 
-use std::{env, io::stdout, path::PathBuf};
-
-use crossterm::{
-    event::{poll, read},
-    style::{Color, Print, PrintStyledContent},
-    terminal::size,
+use editor::{
+    cli::{self, Cli},
+    diff,
+    encoding::FileFormat,
+    file_io::{self, FileError},
+    logger, terminal, EditorBuilder,
 };
-use editor::TextEditor;
-use terminal::cleanup_terminal;
-use widget::widget::BorderStyle;
-
-use crate::widget::{
-    command_line::CommandLine,
-    line_number::LineNumber,
-    panel::Panel,
-    status_bar::StatusBar,
-    widget::{ProcessEvent, PS, TS},
-}; // test
+use ropey::Rope;
 
-use syntect::parsing::SyntaxSet;
-use syntect::{highlighting::ThemeSet, parsing::SyntaxReference};
+/// Prints `message` and exits, for when the editor cannot start
+fn exit_with(message: &str, code: i32) -> ! {
+    eprintln!("{}", message);
+    process::exit(code);
+}
 
-pub fn main_loop(file_content: String, save_path: PathBuf, new_load: bool) {
-    unsafe {
-        let ps = SyntaxSet::load_defaults_nonewlines();
-        PS = Some(ps);
-    };
-    unsafe {
-        let ts = ThemeSet::load_defaults();
-        TS = Some(ts);
-    };
-    let aaaa = "aaaa";
-    let (width, height) = size().unwrap();
-    println!("width: {}, height: {}", width, height);
-    terminal::setup_terminal(true);
-    let mut editor = TextEditor::new(&save_path, width as usize, height as usize);
-    editor.written = new_load;
-    let line_number_width = 8;
-    eprintln!("line_number_width: {}", line_number_width);
-    eprintln!("width: {}, height: {}", width, height);
-    let mut main = Panel::new(
-        file_content.clone(),
-        line_number_width,
-        0,
-        width as usize - line_number_width,
-        height as usize - 2,
-        Color::White,
-        Color::Reset,
-        true,
-        true,
-        BorderStyle::None,
-    );
-    let syntax: &SyntaxReference;
-    unsafe {
-        if save_path.extension().is_some() {
-            syntax = PS
-                .as_ref()
-                .unwrap()
-                .find_syntax_by_extension(save_path.extension().unwrap().to_str().unwrap())
-                .unwrap_or(PS.as_ref().unwrap().find_syntax_plain_text());
-        } else {
-            syntax = PS.as_ref().unwrap().find_syntax_plain_text();
+fn main() {
+    let mut options = match cli::parse(env::args().skip(1)) {
+        Ok(Cli::Edit(options)) => options,
+        Ok(Cli::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Ok(Cli::Version) => {
+            println!("terminus {}", env!("CARGO_PKG_VERSION"));
+            return;
         }
+        Err(e) => exit_with(&format!("terminus: {}\n\n{}", e, cli::USAGE), 2),
     };
-    main.set_syntax(Some(syntax));
-    // base16-ocean.dark,base16-eighties.dark,base16-mocha.dark,base16-ocean.light
-    // InspiredGitHub from here
-    // Solarized (dark) and Solarized (light)
-    main.set_theme(Some("base16-eighties.dark".to_string()));
-    main.set_z_idx(1);
-
-    let status_bar: Box<StatusBar> = StatusBar::new(
-        save_path.to_str().unwrap().to_string(),
-        0,
-        height as usize - 2,
-        width as usize,
-        1 as usize,
-        Color::Black,
-        Color::White,
-        false,
-        false,
-        BorderStyle::None,
-    );
-    //ici
-
-    let line_number = LineNumber::new(
-        String::new(),
-        0,
-        0,
-        line_number_width as usize,
-        height as usize - 2,
-        Color::DarkGrey,
-        Color::Black,
-        false,
-        false,
-        BorderStyle::None,
-    );
-
-    let command_line = CommandLine::new(
-        0,
-        String::new(),
-        0,
-        height as usize - 1,
-        width as usize,
-        1,
-        Color::White,
-        Color::Black,
-        false,
-        false,
-        BorderStyle::None,
-    );
-    let pos = main.update_cursor_position_and_view();
-    editor.add_widget(status_bar);
-    editor.add_widget(line_number);
-    editor.add_widget(command_line);
-    editor.focused_widget_id = editor.add_widget(main);
-    // editor.add_widget(tmp);
-
-    editor.event(&crossterm::event::Event::FocusGained);
-    editor.render(pos, true);
+    let (log_file, log_level) = cli::log_settings(&options);
+    let filter = match logger::Filter::parse(&log_level) {
+        Ok(filter) => filter,
+        Err(e) => exit_with(&format!("terminus: {}\n\n{}", e, cli::USAGE), 2),
+    };
+    if let Err(e) = logger::init(log_file.as_deref(), filter) {
+        exit_with(&format!("terminus: cannot open the log file: {}", e), 1);
+    }
+    log::info!("terminus {} started", env!("CARGO_PKG_VERSION"));
 
-    while editor.running {
-        if (poll(std::time::Duration::from_millis(100))).unwrap() {
-            editor.event(&read().unwrap());
+    let mut commands = Vec::new();
+    if let Some(config) = cli::config_path(&options) {
+        match fs::read_to_string(&config) {
+            Ok(text) => commands = cli::config_commands(&text),
+            // Only a config asked for must exist
+            Err(_) if options.config.is_none() => {}
+            Err(e) => exit_with(&format!("terminus: {}: {}", config.display(), e), 1),
         }
     }
-    cleanup_terminal("Done");
-}
 
-fn main() {
-    let mut args = env::args();
-    let _ = args.next().unwrap();
-    let filepath = args.next();
-    let pathbuf: PathBuf;
-    let mut file_content = String::new();
-    let new_load;
-    if let Some(filepath) = &filepath {
-        (file_content, new_load) = match std::fs::read_to_string(&filepath) {
-            Ok(content) => {
-                pathbuf = PathBuf::from(filepath);
-                (content, false)
-            }
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                pathbuf = PathBuf::from(filepath);
-                (String::new(), true)
-            }
-            Err(e) => {
-                println!("Failed to open file: {:?}", e);
-                return;
-            }
+    let (file_content, file_format, pathbuf, name, new_load);
+    let first = options.files.first().cloned();
+    if options.diff {
+        // The differences are a read-only buffer, both files can be switched to
+        let (old, new) = (&options.files[0].path, &options.files[1].path);
+        let read = |path: &PathBuf| match file_io::read_file(path) {
+            Ok((text, _)) => text,
+            Err(e) => exit_with(&format!("terminus: {}", e), 1),
         };
+        let diff = diff::unified(
+            &old.to_string_lossy(),
+            &new.to_string_lossy(),
+            &read(old),
+            &read(new),
+        );
+        (file_content, file_format) = (Rope::from_str(&diff), FileFormat::default());
+        (pathbuf, name, new_load) = (PathBuf::new(), "[diff]".to_string(), false);
+        options.readonly = true;
+    } else if first.as_ref().is_some_and(|file| file.is_stdin()) {
+        // Piped text has no file, it is only viewed, or saved with `:w <path>`
+        (file_content, file_format) = match file_io::read_stdin() {
+            Ok(read) => read,
+            Err(e) => exit_with(&format!("terminus: {}", e), 1),
+        };
+        if let Err(e) = terminal::reopen_tty() {
+            exit_with(&format!("terminus: cannot open the terminal: {}", e), 1);
+        }
+        (pathbuf, name, new_load) = (PathBuf::new(), "[stdin]".to_string(), false);
+        options.readonly = true;
     } else {
-        pathbuf = PathBuf::from("untitled");
-        new_load = true;
+        pathbuf = first
+            .map(|file| file.path)
+            .unwrap_or(PathBuf::from("untitled"));
+        name = pathbuf.to_string_lossy().to_string();
+        (file_content, file_format, new_load) = match file_io::read_rope(&pathbuf) {
+            Ok((content, format)) => (content, format, false),
+            Err(FileError::NotFound(_)) => (Rope::new(), FileFormat::default(), true),
+            Err(e) => exit_with(&format!("terminus: {}", e), 1),
+        };
     }
     // ICI
 
-    main_loop(file_content, pathbuf, new_load);
+    let builder = EditorBuilder::new()
+        .text(file_content, file_format, pathbuf)
+        .new_file(new_load)
+        .name(name)
+        .readonly(options.readonly)
+        .buffers(options.files);
+    let builder = commands
+        .iter()
+        .fold(builder, |builder, command| builder.run_command(command));
+    let builder = match options.theme {
+        Some(theme) => builder.theme(theme),
+        None => builder,
+    };
+    if let Err(e) = builder.run() {
+        exit_with(&format!("terminus: {}", e), 1);
+    }
+    println!("Done");
 }
@@ -0,0 +1,134 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crossterm::style::Color;
+
+/// Messages waiting to be shown beyond this are dropped, oldest first
+static MAX_QUEUED: usize = 16;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Level {
+    /// How long a message stays once shown, errors stay until a key is pressed
+    fn timeout(self) -> Option<Duration> {
+        match self {
+            Level::Info => Some(Duration::from_secs(3)),
+            Level::Warning => Some(Duration::from_secs(6)),
+            Level::Error => None,
+        }
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            Level::Info => Color::Green,
+            Level::Warning => Color::Yellow,
+            Level::Error => Color::Red,
+        }
+    }
+
+    fn log_level(self) -> log::Level {
+        match self {
+            Level::Info => log::Level::Info,
+            Level::Warning => log::Level::Warn,
+            Level::Error => log::Level::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub level: Level,
+    pub text: String,
+    /// when it was first drawn, its timeout starts then
+    shown_at: Option<Instant>,
+}
+
+/// What the editor has to tell the user, shown one at a time as a toast
+///
+/// Every message is also logged, `:messages` shows them again.
+#[derive(Default)]
+pub struct Messages {
+    queue: VecDeque<Message>,
+}
+
+impl Messages {
+    pub fn push(&mut self, level: Level, text: impl Into<String>) {
+        let text = text.into();
+        log::log!(target: "editor::message", level.log_level(), "{}", text);
+        if self.queue.len() == MAX_QUEUED {
+            self.queue.pop_front();
+        }
+        self.queue.push_back(Message {
+            level,
+            text,
+            shown_at: None,
+        });
+    }
+
+    /// The message to draw now, its timeout starts with the first call
+    pub fn current(&mut self) -> Option<&Message> {
+        let message = self.queue.front_mut()?;
+        message.shown_at.get_or_insert_with(Instant::now);
+        Some(message)
+    }
+
+    /// A key was pressed: the message seen by the user goes away
+    pub fn dismiss(&mut self) {
+        if self
+            .queue
+            .front()
+            .is_some_and(|message| message.shown_at.is_some())
+        {
+            self.queue.pop_front();
+        }
+    }
+
+    /// Drops the current message once its time is up, returns whether it did
+    pub fn expire(&mut self) -> bool {
+        let expired = self.queue.front().is_some_and(|message| {
+            match (message.shown_at, message.level.timeout()) {
+                (Some(shown_at), Some(timeout)) => shown_at.elapsed() >= timeout,
+                _ => false,
+            }
+        });
+        if expired {
+            self.queue.pop_front();
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shows_messages_in_turn() {
+        let mut messages = Messages::default();
+        messages.push(Level::Info, "saved");
+        messages.push(Level::Error, "failed");
+        // Not seen yet, a key does not dismiss it
+        messages.dismiss();
+        assert_eq!(messages.current().unwrap().text, "saved");
+        messages.queue[0].shown_at = Some(Instant::now() - Duration::from_secs(5));
+        assert!(messages.expire());
+        assert_eq!(messages.current().unwrap().level, Level::Error);
+        // Errors wait for a key
+        messages.queue[0].shown_at = Some(Instant::now() - Duration::from_secs(60));
+        assert!(!messages.expire());
+        messages.dismiss();
+        assert!(messages.current().is_none());
+
+        for idx in 0..MAX_QUEUED + 1 {
+            messages.push(Level::Warning, idx.to_string());
+        }
+        assert_eq!(messages.current().unwrap().text, "1");
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::process::CommandExt,
+    },
+    process::{Child, Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+/// A program running on a pseudo terminal
+pub struct Pty {
+    master: File,
+    child: Child,
+}
+
+fn winsize(width: usize, height: usize) -> libc::winsize {
+    libc::winsize {
+        ws_row: height as u16,
+        ws_col: width as u16,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+/// Closes `fd` in the programs executed from now on
+fn set_cloexec(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags == -1 || unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl Pty {
+    /// Starts `program` on a new terminal of `width` by `height`
+    ///
+    /// What it writes arrives on the receiver, which is closed once it exits.
+    pub fn spawn(
+        program: &str,
+        width: usize,
+        height: usize,
+    ) -> io::Result<(Self, Receiver<Vec<u8>>)> {
+        let mut master = 0;
+        let mut slave = 0;
+        let size = winsize(width, height);
+        let res = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                &size,
+            )
+        };
+        if res != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (master, slave) = unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+        // Only the child gets the terminal, through copies of the slave on its
+        // standard streams, the jobs started later get neither end
+        set_cloexec(master.as_raw_fd())?;
+        set_cloexec(slave.as_raw_fd())?;
+
+        let mut command = Command::new(program);
+        command
+            .env("TERM", "xterm-256color")
+            .stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave));
+        unsafe {
+            // A session of its own, with the pty as controlling terminal for job control
+            command.pre_exec(|| {
+                if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn()?;
+        // Dropping the command closes our copies of the slave, reads fail once the child exits
+        drop(command);
+
+        let mut reader = master.try_clone()?;
+        let (sender, output) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(len) => {
+                        if sender.send(buf[..len].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        Ok((Self { master, child }, output))
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.master.write_all(bytes)
+    }
+
+    /// Tells the program its terminal changed size
+    pub fn resize(&self, width: usize, height: usize) -> io::Result<()> {
+        let size = winsize(width, height);
+        let res = unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) };
+        if res == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
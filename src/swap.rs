@@ -0,0 +1,245 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Sender},
+        Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use ropey::Rope;
+
+use crate::file_io::{self, FileError};
+
+/// How often the swap files of dirty buffers are rewritten
+static SWAP_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The text of every dirty buffer, dumped by the panic hook
+static DIRTY: Mutex<Vec<(PathBuf, Rope)>> = Mutex::new(Vec::new());
+
+/// Where the unsaved text of `path` is kept: `.name.swp` next to it
+pub fn swap_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.swp", name))
+}
+
+enum Request {
+    Write(PathBuf, Rope),
+    Remove(PathBuf),
+}
+
+/// Writes the swap files of the dirty buffers on a thread of its own
+pub struct SwapFiles {
+    requests: Option<Sender<Request>>,
+    writer: Option<JoinHandle<()>>,
+    last_write: Instant,
+    /// buffers whose swap file is ours, with the text it holds
+    written: Vec<(PathBuf, Rope)>,
+    /// buffers this instance keeps in [`DIRTY`]
+    tracked: Vec<PathBuf>,
+}
+
+impl SwapFiles {
+    pub fn new() -> Self {
+        let (requests, receiver) = mpsc::channel();
+        let writer = thread::spawn(move || {
+            for request in receiver {
+                match request {
+                    // A failed write only costs the backup, the buffer is untouched
+                    Request::Write(path, text) => {
+                        let _ =
+                            file_io::write_atomic(&swap_path(&path), text.to_string().as_bytes());
+                    }
+                    Request::Remove(path) => {
+                        let _ = fs::remove_file(swap_path(&path));
+                    }
+                }
+            }
+        });
+        Self {
+            requests: Some(requests),
+            writer: Some(writer),
+            last_write: Instant::now(),
+            written: Vec::new(),
+            tracked: Vec::new(),
+        }
+    }
+
+    fn send(&self, request: Request) {
+        if let Some(requests) = &self.requests {
+            let _ = requests.send(request);
+        }
+    }
+
+    /// Keeps the text of a dirty buffer for the panic hook, and writes its swap
+    /// file when it changed since the last one, at most every [`SWAP_INTERVAL`]
+    pub fn update(&mut self, path: &Path, text: &Rope) {
+        track(path, text);
+        if !self.tracked.iter().any(|tracked| tracked == path) {
+            self.tracked.push(path.to_path_buf());
+        }
+        if self.last_write.elapsed() < SWAP_INTERVAL {
+            return;
+        }
+        self.last_write = Instant::now();
+        match self.written.iter_mut().find(|(written, _)| written == path) {
+            Some((_, written)) if written == text => return,
+            Some((_, written)) => *written = text.clone(),
+            None => self.written.push((path.to_path_buf(), text.clone())),
+        }
+        self.send(Request::Write(path.to_path_buf(), text.clone()));
+    }
+
+    /// The buffer was saved or discarded, its swap file goes away if it is ours
+    pub fn remove(&mut self, path: &Path) {
+        untrack(path);
+        self.tracked.retain(|tracked| tracked != path);
+        if let Some(idx) = self.written.iter().position(|(written, _)| written == path) {
+            self.written.remove(idx);
+            self.send(Request::Remove(path.to_path_buf()));
+        }
+    }
+
+    /// Takes the swap file of a buffer over, it is removed once the buffer is saved
+    pub fn adopt(&mut self, path: &Path, text: &Rope) {
+        if !self.written.iter().any(|(written, _)| written == path) {
+            self.written.push((path.to_path_buf(), text.clone()));
+        }
+    }
+}
+
+impl Default for SwapFiles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for SwapFiles {
+    fn drop(&mut self) {
+        // After a panic the swap files are what is left of the buffers, even
+        // when it is caught and there was no panic hook to write them
+        if thread::panicking() {
+            dump(|path| self.tracked.iter().any(|tracked| tracked == path));
+            return;
+        }
+        for path in std::mem::take(&mut self.tracked) {
+            untrack(&path);
+        }
+        for (path, _) in std::mem::take(&mut self.written) {
+            self.send(Request::Remove(path));
+        }
+        self.requests = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn track(path: &Path, text: &Rope) {
+    let mut dirty = DIRTY.lock().unwrap_or_else(|e| e.into_inner());
+    match dirty.iter_mut().find(|(dirty, _)| dirty == path) {
+        Some((_, dirty)) => *dirty = text.clone(),
+        None => dirty.push((path.to_path_buf(), text.clone())),
+    }
+}
+
+fn untrack(path: &Path) {
+    let mut dirty = DIRTY.lock().unwrap_or_else(|e| e.into_inner());
+    dirty.retain(|(dirty, _)| dirty != path);
+}
+
+/// Writes the swap file of every dirty buffer right away, returns where they went
+///
+/// Called by the panic hook, so it never blocks and never panics.
+pub fn dump_dirty_buffers() -> Vec<PathBuf> {
+    dump(|_| true)
+}
+
+fn dump(wanted: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
+    let dirty = match DIRTY.try_lock() {
+        Ok(dirty) => dirty,
+        Err(std::sync::TryLockError::Poisoned(e)) => e.into_inner(),
+        Err(std::sync::TryLockError::WouldBlock) => return Vec::new(),
+    };
+    dirty
+        .iter()
+        .filter(|(path, _)| wanted(path))
+        .filter_map(|(path, text)| {
+            let swap = swap_path(path);
+            fs::write(&swap, text.to_string()).ok().map(|()| swap)
+        })
+        .collect()
+}
+
+/// The swap file of `path`, if it is newer than the file and worth recovering
+pub fn recoverable(path: &Path) -> Option<PathBuf> {
+    let swap = swap_path(path);
+    let swap_modified = fs::metadata(&swap).and_then(|m| m.modified()).ok()?;
+    match fs::metadata(path).and_then(|m| m.modified()) {
+        Ok(modified) if modified >= swap_modified => None,
+        _ => Some(swap),
+    }
+}
+
+/// Reads the text kept in the swap file of `path`
+pub fn recover(path: &Path) -> Result<String, FileError> {
+    let bytes = file_io::read_bytes(&swap_path(path))?;
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::TestDir;
+
+    #[test]
+    fn recovers_dumped_buffers() {
+        let test_dir = TestDir::new("swap-dump");
+        let dir = &test_dir.0;
+        let path = dir.join("file.txt");
+        fs::write(&path, "saved\n").unwrap();
+        // The file must be older than the swap file
+        thread::sleep(Duration::from_millis(20));
+
+        let mut swap = SwapFiles::new();
+        swap.update(&path, &Rope::from_str("saved\nunsaved\n"));
+        // What the panic hook does
+        let dumped = dump_dirty_buffers();
+        assert!(dumped.contains(&dir.join(".file.txt.swp")));
+
+        assert_eq!(recoverable(&path), Some(dir.join(".file.txt.swp")));
+        assert_eq!(recover(&path).unwrap(), "saved\nunsaved\n");
+    }
+
+    #[test]
+    fn removes_swap_files_once_saved() {
+        let test_dir = TestDir::new("swap-saved");
+        let dir = &test_dir.0;
+        let path = dir.join("file.txt");
+        let mut swap = SwapFiles::new();
+        // The first update waits for the interval
+        swap.last_write -= SWAP_INTERVAL;
+        swap.update(&path, &Rope::from_str("new file\n"));
+        drop(swap);
+        assert!(recoverable(&path).is_none());
+
+        let mut swap = SwapFiles::new();
+        swap.last_write -= SWAP_INTERVAL;
+        swap.update(&path, &Rope::from_str("new file\n"));
+        swap.remove(&path);
+        drop(swap);
+        assert!(!swap_path(&path).exists());
+
+        // A swap file left by someone else is kept
+        fs::write(swap_path(&path), "theirs").unwrap();
+        let mut swap = SwapFiles::new();
+        swap.remove(&path);
+        drop(swap);
+        assert_eq!(recover(&path).unwrap(), "theirs");
+    }
+}
@@ -4,13 +4,37 @@ use crossterm::{
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use std::io::stdout;
+use std::{
+    fs::OpenOptions,
+    io::{self, stdout},
+    os::fd::AsRawFd,
+};
+
+use crate::swap;
+
+/// Reads keys from the terminal again once stdin was read to its end, so text
+/// can be piped to the editor
+pub fn reopen_tty() -> io::Result<()> {
+    let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    if unsafe { libc::dup2(tty.as_raw_fd(), libc::STDIN_FILENO) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
 
 pub fn setup_terminal(disable_mouse_interaction: bool) {
     // set panic hook
     std::panic::set_hook(Box::new(|info| {
         // clean up the terminal
-        cleanup_terminal("Panic!");
+        cleanup_terminal();
+        println!("Panic!");
+
+        // keep what was not saved
+        for swap in swap::dump_dirty_buffers() {
+            println!("Unsaved changes written to {}", swap.display());
+        }
+
+        log::error!("{}", info);
 
         // pring panic info, if any
         if let Some(msg) = info.payload().downcast_ref::<&str>() {
@@ -50,7 +74,7 @@ pub fn setup_terminal(disable_mouse_interaction: bool) {
 }
 
 /// clean up the terminal
-pub fn cleanup_terminal(message: &str) {
+pub fn cleanup_terminal() {
     execute!(
         stdout(),
         // go back to the normal screen
@@ -71,7 +95,4 @@ pub fn cleanup_terminal(message: &str) {
 
     // leave raw mode
     disable_raw_mode().unwrap();
-
-    // print the final message
-    println!("{}", message);
 }
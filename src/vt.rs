@@ -0,0 +1,668 @@
+use crossterm::style::Color;
+use ropey::Rope;
+
+/// Lines kept once they scrolled off the top of the screen
+static SCROLLBACK_LIMIT: usize = 10_000;
+
+/// A character on the screen with its colors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub ch: char,
+    pub fg: Color,
+    pub bg: Color,
+    pub bold: bool,
+    pub underline: bool,
+    pub inverse: bool,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            ch: ' ',
+            fg: Color::Reset,
+            bg: Color::Reset,
+            bold: false,
+            underline: false,
+            inverse: false,
+        }
+    }
+}
+
+impl Cell {
+    /// Same colors and attributes, the char is ignored
+    pub fn same_style(&self, other: &Cell) -> bool {
+        self.fg == other.fg
+            && self.bg == other.bg
+            && self.bold == other.bold
+            && self.underline == other.underline
+            && self.inverse == other.inverse
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Ground,
+    Escape,
+    /// after `ESC [`, params are collected until the final byte
+    Csi,
+    /// after `ESC ]`, ignored until BEL or `ESC \`
+    Osc,
+    OscEscape,
+    /// after `ESC (` and friends, the next byte picks a charset we ignore
+    Charset,
+}
+
+/// A VT100/ANSI screen, fed with what a program writes to its terminal
+///
+/// Lines leaving the top of the screen go to `scrollback`, unless the alternate
+/// screen of full screen programs is shown.
+pub struct Screen {
+    pub width: usize,
+    pub height: usize,
+    rows: Vec<Vec<Cell>>,
+    pub cursor_x: usize,
+    pub cursor_y: usize,
+    pub cursor_visible: bool,
+    pub scrollback: Rope,
+    /// what the program asked for, e.g. the cursor position, to write back to it
+    pub responses: Vec<u8>,
+    /// the style of the next chars
+    pen: Cell,
+    state: State,
+    params: String,
+    utf8: Vec<u8>,
+    /// the last column was written, the next char goes on the next line
+    wrap_pending: bool,
+    scroll_top: usize,
+    scroll_bottom: usize,
+    saved_cursor: (usize, usize),
+    /// the main screen while the alternate one is shown
+    main_rows: Option<Vec<Vec<Cell>>>,
+}
+
+impl Screen {
+    pub fn new(width: usize, height: usize) -> Self {
+        let width = width.max(1);
+        let height = height.max(1);
+        Self {
+            width,
+            height,
+            rows: vec![vec![Cell::default(); width]; height],
+            cursor_x: 0,
+            cursor_y: 0,
+            cursor_visible: true,
+            scrollback: Rope::new(),
+            responses: Vec::new(),
+            pen: Cell::default(),
+            state: State::Ground,
+            params: String::new(),
+            utf8: Vec::new(),
+            wrap_pending: false,
+            scroll_top: 0,
+            scroll_bottom: height - 1,
+            saved_cursor: (0, 0),
+            main_rows: None,
+        }
+    }
+
+    pub fn cell(&self, x: usize, y: usize) -> Cell {
+        self.rows[y][x]
+    }
+
+    /// Lines in the scrollback
+    pub fn scrollback_lines(&self) -> usize {
+        self.scrollback.len_lines() - 1
+    }
+
+    pub fn resize(&mut self, width: usize, height: usize) {
+        let width = width.max(1);
+        let height = height.max(1);
+        for row in &mut self.rows {
+            row.resize(width, Cell::default());
+        }
+        // Shrinking keeps the rows around the cursor
+        while self.rows.len() > height {
+            if self.cursor_y > 0 {
+                let row = self.rows.remove(0);
+                self.push_scrollback(&row);
+                self.cursor_y -= 1;
+            } else {
+                self.rows.pop();
+            }
+        }
+        self.rows.resize(height, vec![Cell::default(); width]);
+        self.width = width;
+        self.height = height;
+        self.scroll_top = 0;
+        self.scroll_bottom = height - 1;
+        self.cursor_x = self.cursor_x.min(width - 1);
+        self.cursor_y = self.cursor_y.min(height - 1);
+        self.wrap_pending = false;
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.feed_byte(byte);
+        }
+    }
+
+    fn feed_byte(&mut self, byte: u8) {
+        match self.state {
+            State::Ground => self.ground(byte),
+            State::Escape => self.escape(byte),
+            State::Csi => match byte {
+                0x1b => self.state = State::Escape,
+                0x20..=0x3f => self.params.push(byte as char),
+                0x40..=0x7e => {
+                    self.state = State::Ground;
+                    let params = std::mem::take(&mut self.params);
+                    self.csi(&params, byte as char);
+                }
+                _ => self.control(byte),
+            },
+            State::Osc => match byte {
+                0x07 => self.state = State::Ground,
+                0x1b => self.state = State::OscEscape,
+                _ => {}
+            },
+            State::OscEscape => {
+                self.state = if byte == b'\\' {
+                    State::Ground
+                } else {
+                    State::Osc
+                }
+            }
+            State::Charset => self.state = State::Ground,
+        }
+    }
+
+    fn ground(&mut self, byte: u8) {
+        if byte < 0x20 || byte == 0x7f {
+            self.utf8.clear();
+            self.control(byte);
+            return;
+        }
+        self.utf8.push(byte);
+        match std::str::from_utf8(&self.utf8) {
+            Ok(text) => {
+                let ch = text.chars().next().unwrap_or(' ');
+                self.utf8.clear();
+                self.print(ch);
+            }
+            Err(e) if e.error_len().is_some() || self.utf8.len() >= 4 => {
+                self.utf8.clear();
+                self.print(char::REPLACEMENT_CHARACTER);
+            }
+            // Waiting for the rest of the char
+            Err(_) => {}
+        }
+    }
+
+    fn control(&mut self, byte: u8) {
+        match byte {
+            0x1b => self.state = State::Escape,
+            b'\r' => {
+                self.cursor_x = 0;
+                self.wrap_pending = false;
+            }
+            b'\n' | 0x0b | 0x0c => self.linefeed(),
+            0x08 => {
+                self.cursor_x = self.cursor_x.saturating_sub(1);
+                self.wrap_pending = false;
+            }
+            b'\t' => {
+                self.cursor_x = ((self.cursor_x / 8 + 1) * 8).min(self.width - 1);
+            }
+            _ => {}
+        }
+    }
+
+    fn escape(&mut self, byte: u8) {
+        self.state = State::Ground;
+        match byte {
+            b'[' => {
+                self.params.clear();
+                self.state = State::Csi;
+            }
+            b']' => self.state = State::Osc,
+            b'(' | b')' | b'*' | b'+' => self.state = State::Charset,
+            b'7' => self.saved_cursor = (self.cursor_x, self.cursor_y),
+            b'8' => self.restore_cursor(),
+            b'D' => self.linefeed(),
+            b'E' => {
+                self.cursor_x = 0;
+                self.linefeed();
+            }
+            b'M' => {
+                if self.cursor_y == self.scroll_top {
+                    self.scroll_down(1);
+                } else {
+                    self.cursor_y = self.cursor_y.saturating_sub(1);
+                }
+            }
+            b'c' => {
+                let scrollback = std::mem::take(&mut self.scrollback);
+                *self = Screen::new(self.width, self.height);
+                self.scrollback = scrollback;
+            }
+            _ => {}
+        }
+    }
+
+    fn print(&mut self, ch: char) {
+        if self.wrap_pending {
+            self.cursor_x = 0;
+            self.linefeed();
+        }
+        self.rows[self.cursor_y][self.cursor_x] = Cell { ch, ..self.pen };
+        if self.cursor_x + 1 == self.width {
+            self.wrap_pending = true;
+        } else {
+            self.cursor_x += 1;
+        }
+    }
+
+    fn linefeed(&mut self) {
+        self.wrap_pending = false;
+        if self.cursor_y == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.cursor_y + 1 < self.height {
+            self.cursor_y += 1;
+        }
+    }
+
+    fn blank(&self) -> Cell {
+        Cell {
+            bg: self.pen.bg,
+            ..Cell::default()
+        }
+    }
+
+    fn push_scrollback(&mut self, row: &[Cell]) {
+        let text: String = row.iter().map(|cell| cell.ch).collect();
+        let len = self.scrollback.len_chars();
+        self.scrollback.insert(len, text.trim_end());
+        let len = self.scrollback.len_chars();
+        self.scrollback.insert_char(len, '\n');
+        if self.scrollback_lines() > SCROLLBACK_LIMIT {
+            let end = self.scrollback.line_to_char(1);
+            self.scrollback.remove(..end);
+        }
+    }
+
+    /// Scrolls the region up by `count` rows, scrolling more than its height
+    /// blanks it all the same
+    fn scroll_up(&mut self, count: usize) {
+        let count = count.min(self.scroll_bottom - self.scroll_top + 1);
+        for _ in 0..count {
+            let row = self.rows.remove(self.scroll_top);
+            if self.scroll_top == 0 && self.main_rows.is_none() {
+                self.push_scrollback(&row);
+            }
+            self.rows
+                .insert(self.scroll_bottom, vec![self.blank(); self.width]);
+        }
+    }
+
+    fn scroll_down(&mut self, count: usize) {
+        let count = count.min(self.scroll_bottom - self.scroll_top + 1);
+        for _ in 0..count {
+            self.rows.remove(self.scroll_bottom);
+            self.rows
+                .insert(self.scroll_top, vec![self.blank(); self.width]);
+        }
+    }
+
+    fn restore_cursor(&mut self) {
+        self.cursor_x = self.saved_cursor.0.min(self.width - 1);
+        self.cursor_y = self.saved_cursor.1.min(self.height - 1);
+        self.wrap_pending = false;
+    }
+
+    fn move_to(&mut self, x: usize, y: usize) {
+        self.cursor_x = x.min(self.width - 1);
+        self.cursor_y = y.min(self.height - 1);
+        self.wrap_pending = false;
+    }
+
+    fn erase(&mut self, y: usize, from: usize, to: usize) {
+        let blank = self.blank();
+        for cell in &mut self.rows[y][from..to.min(self.width)] {
+            *cell = blank;
+        }
+    }
+
+    fn csi(&mut self, params: &str, action: char) {
+        let private = params.starts_with('?');
+        let numbers: Vec<usize> = params
+            .trim_start_matches(['?', '>', '='])
+            .split(';')
+            .map(|n| n.parse().unwrap_or(0))
+            .collect();
+        let arg = |idx: usize, default: usize| match numbers.get(idx) {
+            Some(0) | None => default,
+            Some(n) => *n,
+        };
+        let (x, y) = (self.cursor_x, self.cursor_y);
+        match action {
+            'A' => self.move_to(x, y.saturating_sub(arg(0, 1)).max(self.scroll_top.min(y))),
+            'B' => self.move_to(x, (y + arg(0, 1)).min(self.scroll_bottom.max(y))),
+            'C' => self.move_to(x + arg(0, 1), y),
+            'D' => self.move_to(x.saturating_sub(arg(0, 1)), y),
+            'E' => self.move_to(0, y + arg(0, 1)),
+            'F' => self.move_to(0, y.saturating_sub(arg(0, 1))),
+            'G' | '`' => self.move_to(arg(0, 1) - 1, y),
+            'd' => self.move_to(x, arg(0, 1) - 1),
+            'H' | 'f' => self.move_to(arg(1, 1) - 1, arg(0, 1) - 1),
+            'J' => match numbers[0] {
+                0 => {
+                    self.erase(y, x, self.width);
+                    for row in y + 1..self.height {
+                        self.erase(row, 0, self.width);
+                    }
+                }
+                1 => {
+                    self.erase(y, 0, x + 1);
+                    for row in 0..y {
+                        self.erase(row, 0, self.width);
+                    }
+                }
+                2 => {
+                    for row in 0..self.height {
+                        self.erase(row, 0, self.width);
+                    }
+                }
+                3 => self.scrollback = Rope::new(),
+                _ => {}
+            },
+            'K' => match numbers[0] {
+                0 => self.erase(y, x, self.width),
+                1 => self.erase(y, 0, x + 1),
+                2 => self.erase(y, 0, self.width),
+                _ => {}
+            },
+            'L' | 'M' if (self.scroll_top..=self.scroll_bottom).contains(&y) => {
+                // Lines are inserted or deleted inside the region below the cursor
+                let top = self.scroll_top;
+                self.scroll_top = y;
+                if action == 'L' {
+                    self.scroll_down(arg(0, 1).min(self.scroll_bottom - y + 1));
+                } else {
+                    let main_rows = self.main_rows.take();
+                    // Deleted lines are not scrollback, even at the top of the screen
+                    self.main_rows = Some(Vec::new());
+                    self.scroll_up(arg(0, 1).min(self.scroll_bottom - y + 1));
+                    self.main_rows = main_rows;
+                }
+                self.scroll_top = top;
+                self.cursor_x = 0;
+            }
+            'P' => {
+                let count = arg(0, 1).min(self.width - x);
+                let blank = self.blank();
+                let row = &mut self.rows[y];
+                row.drain(x..x + count);
+                row.resize(self.width, blank);
+            }
+            '@' => {
+                let count = arg(0, 1).min(self.width - x);
+                let blank = self.blank();
+                let row = &mut self.rows[y];
+                for _ in 0..count {
+                    row.insert(x, blank);
+                }
+                row.truncate(self.width);
+            }
+            'X' => self.erase(y, x, x + arg(0, 1)),
+            'S' => self.scroll_up(arg(0, 1)),
+            'T' => self.scroll_down(arg(0, 1)),
+            'r' => {
+                let top = arg(0, 1) - 1;
+                let bottom = arg(1, self.height).min(self.height) - 1;
+                if top < bottom {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.move_to(0, 0);
+                }
+            }
+            's' => self.saved_cursor = (x, y),
+            'u' => self.restore_cursor(),
+            'm' => self.sgr(&numbers),
+            'n' if numbers[0] == 6 => {
+                let report = format!("\x1b[{};{}R", y + 1, x + 1);
+                self.responses.extend_from_slice(report.as_bytes());
+            }
+            'c' if !private => self.responses.extend_from_slice(b"\x1b[?1;2c"),
+            'h' | 'l' if private => {
+                let set = action == 'h';
+                for mode in &numbers {
+                    match mode {
+                        25 => self.cursor_visible = set,
+                        47 | 1047 | 1049 => self.alternate_screen(set),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn alternate_screen(&mut self, set: bool) {
+        let blank_rows = vec![vec![Cell::default(); self.width]; self.height];
+        if set && self.main_rows.is_none() {
+            self.saved_cursor = (self.cursor_x, self.cursor_y);
+            self.main_rows = Some(std::mem::replace(&mut self.rows, blank_rows));
+        } else if !set {
+            if let Some(rows) = self.main_rows.take() {
+                self.rows = rows;
+                self.restore_cursor();
+            }
+        }
+    }
+
+    /// Select Graphic Rendition, `ESC [ ... m`
+    fn sgr(&mut self, numbers: &[usize]) {
+        let mut idx = 0;
+        while idx < numbers.len() {
+            match numbers[idx] {
+                0 => self.pen = Cell::default(),
+                1 => self.pen.bold = true,
+                4 => self.pen.underline = true,
+                7 => self.pen.inverse = true,
+                22 => self.pen.bold = false,
+                24 => self.pen.underline = false,
+                27 => self.pen.inverse = false,
+                n @ 30..=37 => self.pen.fg = ansi_color(n - 30),
+                n @ 40..=47 => self.pen.bg = ansi_color(n - 40),
+                n @ 90..=97 => self.pen.fg = ansi_color(n - 90 + 8),
+                n @ 100..=107 => self.pen.bg = ansi_color(n - 100 + 8),
+                39 => self.pen.fg = Color::Reset,
+                49 => self.pen.bg = Color::Reset,
+                n @ (38 | 48) => {
+                    let color = match numbers.get(idx + 1) {
+                        Some(5) => {
+                            idx += 2;
+                            numbers.get(idx).map(|&n| Color::AnsiValue(n as u8))
+                        }
+                        Some(2) => {
+                            idx += 4;
+                            match numbers.get(idx - 2..=idx) {
+                                Some(&[r, g, b]) => Some(Color::Rgb {
+                                    r: r as u8,
+                                    g: g as u8,
+                                    b: b as u8,
+                                }),
+                                _ => None,
+                            }
+                        }
+                        _ => None,
+                    };
+                    if let Some(color) = color {
+                        if n == 38 {
+                            self.pen.fg = color;
+                        } else {
+                            self.pen.bg = color;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            idx += 1;
+        }
+    }
+}
+
+/// The 16 colors of `ESC [ 3x m` and `ESC [ 9x m`
+fn ansi_color(n: usize) -> Color {
+    match n {
+        0 => Color::Black,
+        1 => Color::DarkRed,
+        2 => Color::DarkGreen,
+        3 => Color::DarkYellow,
+        4 => Color::DarkBlue,
+        5 => Color::DarkMagenta,
+        6 => Color::DarkCyan,
+        7 => Color::Grey,
+        8 => Color::DarkGrey,
+        9 => Color::Red,
+        10 => Color::Green,
+        11 => Color::Yellow,
+        12 => Color::Blue,
+        13 => Color::Magenta,
+        14 => Color::Cyan,
+        _ => Color::White,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Screen {
+        /// Text of a screen row, without the trailing blanks
+        pub fn row_text(&self, y: usize) -> String {
+            let text: String = self.rows[y].iter().map(|cell| cell.ch).collect();
+            text.trim_end().to_string()
+        }
+
+        /// The scrollback followed by the screen, without the blank rows at the bottom
+        pub fn text(&self) -> String {
+            let mut rows: Vec<String> = (0..self.height).map(|y| self.row_text(y)).collect();
+            while rows.last().is_some_and(|row| row.is_empty()) {
+                rows.pop();
+            }
+            let mut text = self.scrollback.to_string();
+            for row in rows {
+                text.push_str(&row);
+                text.push('\n');
+            }
+            text
+        }
+    }
+
+    fn replay(width: usize, height: usize, recording: &[u8]) -> Screen {
+        let mut screen = Screen::new(width, height);
+        screen.feed(recording);
+        screen
+    }
+
+    #[test]
+    fn prints_and_wraps() {
+        let screen = replay(5, 3, b"hello world\r\nok");
+        assert_eq!(screen.row_text(0), " worl");
+        assert_eq!(screen.row_text(1), "d");
+        assert_eq!(screen.row_text(2), "ok");
+        assert_eq!(screen.scrollback.to_string(), "hello\n");
+        assert_eq!(screen.text(), "hello\n worl\nd\nok\n");
+        assert_eq!((screen.cursor_x, screen.cursor_y), (2, 2));
+    }
+
+    #[test]
+    fn keeps_scrollback() {
+        let screen = replay(10, 2, b"1\r\n2\r\n3\r\n4");
+        assert_eq!(screen.scrollback.to_string(), "1\n2\n");
+        assert_eq!(screen.scrollback_lines(), 2);
+        assert_eq!(screen.text(), "1\n2\n3\n4\n");
+    }
+
+    #[test]
+    fn colors_cells() {
+        // `ls --color` output
+        let screen = replay(
+            20,
+            2,
+            b"\x1b[0m\x1b[01;34msrc\x1b[0m  \x1b[38;5;208mCargo.toml\x1b[0m\r\n",
+        );
+        assert_eq!(screen.row_text(0), "src  Cargo.toml");
+        let dir = screen.cell(0, 0);
+        assert_eq!(dir.fg, Color::DarkBlue);
+        assert!(dir.bold);
+        assert_eq!(screen.cell(3, 0), Cell::default());
+        assert_eq!(screen.cell(5, 0).fg, Color::AnsiValue(208));
+        assert!(!screen.cell(5, 0).bold);
+
+        let screen = replay(10, 1, b"\x1b[48;2;1;2;3;97mx\x1b[39;7my");
+        assert_eq!(screen.cell(0, 0).bg, Color::Rgb { r: 1, g: 2, b: 3 });
+        assert_eq!(screen.cell(0, 0).fg, Color::White);
+        assert_eq!(screen.cell(1, 0).fg, Color::Reset);
+        assert!(screen.cell(1, 0).inverse);
+    }
+
+    #[test]
+    fn replays_cargo_test_progress() {
+        // cargo redraws its progress bar in place, then prints over it
+        let recording = b"\x1b[1m\x1b[32m   Compiling\x1b[0m editor v0.1.0\r\n\
+            \x1b[1m\x1b[36m    Building\x1b[0m [=====>   ] 5/9\r\
+            \x1b[K\x1b[1m\x1b[32m    Finished\x1b[0m test target\r\n\
+            test result: \x1b[32mok\x1b[0m. 13 passed\r\n";
+        let screen = replay(40, 5, recording);
+        assert_eq!(screen.row_text(0), "   Compiling editor v0.1.0");
+        assert_eq!(screen.row_text(1), "    Finished test target");
+        assert_eq!(screen.row_text(2), "test result: ok. 13 passed");
+        assert_eq!(screen.cell(4, 1).fg, Color::DarkGreen);
+        assert_eq!(screen.cell(13, 2).fg, Color::DarkGreen);
+        assert_eq!(screen.cell(15, 2).fg, Color::Reset);
+        assert_eq!((screen.cursor_x, screen.cursor_y), (0, 3));
+    }
+
+    #[test]
+    fn moves_and_erases() {
+        let screen = replay(10, 3, b"abcdef\x1b[1;3Hx\x1b[2;2Hy\x1b[1;5H\x1b[K");
+        assert_eq!(screen.row_text(0), "abxd");
+        assert_eq!(screen.row_text(1), " y");
+        let screen = replay(10, 3, b"abc\r\ndef\x1b[2J\x1b[Hz");
+        assert_eq!(screen.text(), "z\n");
+        let screen = replay(10, 1, b"abcdef\x1b[3D\x1b[2P\x1b[1@-");
+        assert_eq!(screen.row_text(0), "abc-f");
+    }
+
+    #[test]
+    fn scroll_region_and_alternate_screen() {
+        let screen = replay(5, 3, b"a\r\nb\r\nc\x1b[2;3r\x1b[3;1H\nd");
+        assert_eq!(screen.text(), "a\nc\nd\n");
+        assert_eq!(screen.scrollback_lines(), 0);
+
+        // `less` draws on the alternate screen, the shell prompt comes back after
+        let screen = replay(5, 2, b"$ ls\r\n\x1b[?1049h\x1b[Hpage\x1b[?1049l$ ");
+        assert_eq!(screen.row_text(0), "$ ls");
+        assert_eq!(screen.row_text(1), "$");
+        assert_eq!((screen.cursor_x, screen.cursor_y), (2, 1));
+
+        // Scrolling by more than the region blanks it at once
+        let screen = replay(5, 3, b"a\r\nb\r\nc\x1b[2;3r\x1b[4000000000S");
+        assert_eq!(screen.text(), "a\n");
+        let screen = replay(5, 3, b"a\r\nb\r\nc\x1b[4000000000T");
+        assert_eq!(screen.text(), "");
+    }
+
+    #[test]
+    fn answers_queries_and_skips_titles() {
+        let mut screen = replay(10, 3, b"\x1b]0;title\x07ab\x1b[6n");
+        assert_eq!(screen.row_text(0), "ab");
+        assert_eq!(std::mem::take(&mut screen.responses), b"\x1b[1;3R");
+        screen.feed(b"\x1b]2;other\x1b\\\x1b[?25l\xc3");
+        assert!(!screen.cursor_visible);
+        screen.feed(b"\xa9");
+        assert_eq!(screen.row_text(0), "ab\u{e9}");
+    }
+}
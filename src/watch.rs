@@ -0,0 +1,176 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs::{self, File},
+    hash::Hasher,
+    io::{self, Read},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::large_file::LARGE_FILE_SIZE;
+
+/// How often the open file is checked for changes made by other programs
+static POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a file looked like when it was read or written
+#[derive(PartialEq, Clone, Copy, Debug)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    inode: u64,
+    /// none for large files, hashing them on every change takes too long
+    hash: Option<u64>,
+}
+
+impl FileStamp {
+    /// Hashes the file a block at a time, big files are never read whole
+    fn read(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let mut stamp = Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            inode: metadata.ino(),
+            hash: None,
+        };
+        if stamp.len > LARGE_FILE_SIZE {
+            return Ok(stamp);
+        }
+        let mut file = File::open(path)?;
+        let mut hasher = DefaultHasher::new();
+        let mut buf = vec![0; 64 * 1024];
+        let mut len = 0;
+        loop {
+            match file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => {
+                    hasher.write(&buf[..read]);
+                    len += read as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        stamp.len = len;
+        stamp.hash = Some(hasher.finish());
+        Ok(stamp)
+    }
+
+    /// Whether the metadata alone shows the file is still the same
+    fn looks_unchanged(&self, metadata: &fs::Metadata) -> bool {
+        self.modified == metadata.modified().ok()
+            && self.len == metadata.len()
+            && self.inode == metadata.ino()
+    }
+}
+
+/// How the file on disk differs from what the buffer was loaded from
+#[derive(PartialEq, Debug)]
+pub enum FileChange {
+    /// another program wrote it
+    Modified,
+    Deleted,
+}
+
+/// Polls the file of the buffer to notice when another program changes it
+pub struct FileWatch {
+    path: PathBuf,
+    /// the file as last read or written, none when it does not exist
+    stamp: Option<FileStamp>,
+    /// the change already reported, so it is reported once, `Some(None)` for a deletion
+    reported: Option<Option<FileStamp>>,
+    last_poll: Instant,
+}
+
+impl FileWatch {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            stamp: FileStamp::read(path).ok(),
+            reported: None,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// The buffer now matches the file at `path`, just read or written
+    pub fn reset(&mut self, path: &Path) {
+        self.path = path.to_path_buf();
+        self.stamp = FileStamp::read(path).ok();
+        self.reported = None;
+    }
+
+    /// Checks the file, at most every [`POLL_INTERVAL`], and returns a change not reported yet
+    pub fn poll(&mut self) -> Option<FileChange> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+        let (stamp, change) = match self.current() {
+            Ok(Some(stamp)) => (Some(stamp), FileChange::Modified),
+            Ok(None) => return None,
+            // A new file is not deleted before it is first saved
+            Err(e) if e.kind() == io::ErrorKind::NotFound && self.stamp.is_some() => {
+                (None, FileChange::Deleted)
+            }
+            // Unreadable for now, maybe in the middle of being written
+            Err(_) => return None,
+        };
+        if self.reported == Some(stamp) {
+            return None;
+        }
+        self.reported = Some(stamp);
+        Some(change)
+    }
+
+    /// Whether another program wrote the file since the buffer was read or written,
+    /// a deleted file is not worth keeping
+    pub fn changed_on_disk(&mut self) -> bool {
+        matches!(self.current(), Ok(Some(_)))
+    }
+
+    /// The stamp of the file when it differs from the buffer's
+    fn current(&mut self) -> io::Result<Option<FileStamp>> {
+        let metadata = fs::metadata(&self.path)?;
+        if let Some(stamp) = &self.stamp {
+            if stamp.looks_unchanged(&metadata) {
+                return Ok(None);
+            }
+        }
+        let stamp = FileStamp::read(&self.path)?;
+        // Touched but not changed, like a checkout of the same content
+        if let Some(old) = &mut self.stamp {
+            if old.hash.is_some() && old.hash == stamp.hash && old.len == stamp.len {
+                *old = stamp;
+                return Ok(None);
+            }
+        }
+        Ok(Some(stamp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::TestDir;
+
+    #[test]
+    fn stamps_large_files_without_reading_them() {
+        let test_dir = TestDir::new("watch-large");
+        let path = test_dir.0.join("file.txt");
+        fs::write(&path, "small\n").unwrap();
+        assert!(FileStamp::read(&path).unwrap().hash.is_some());
+
+        let mut watch = FileWatch::new(&path);
+        // Sparse, nothing is written to the disk
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(LARGE_FILE_SIZE + 1)
+            .unwrap();
+        assert!(watch.changed_on_disk());
+        watch.reset(&path);
+        assert_eq!(watch.stamp.unwrap().hash, None);
+        assert!(!watch.changed_on_disk());
+    }
+}
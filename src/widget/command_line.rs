@@ -1,45 +1,55 @@
-use std::{collections::HashMap, fs};
+use std::path::Path;
 
 use crossterm::{event::Event, style::Color};
-use regex::Regex;
 use ropey::Rope;
-use syntect::parsing::SyntaxReference;
 
-use crate::{editor::TextEditor, widget::popup::Popup};
+use crate::{
+    command::{parser, registry::Trigger},
+    completion::{self, Completion},
+    editor::TextEditor,
+    history::{Histories, HistoryKind},
+    widget::popup::Popup,
+};
 
-use super::{
-    status_bar,
-    widget::{
-        BorderStyle, ColorText, ColorTextTag, CursorPosition, CursorPositionByte, ProcessEvent,
-        ShouldExit, WidgetType, PS,
-    },
+use super::widget::{
+    BorderStyle, ColorText, ColorTextTag, CursorPosition, CursorPositionByte, ProcessEvent,
+    ShouldExit, WidgetType,
 };
 
-trait CommandLineCommands {
-    fn quit(
-        command_line: &mut CommandLine,
-        editor: &mut TextEditor,
-        args: Vec<String>,
-        event: Event,
-    ) -> Option<CursorPosition>;
-    fn find(
-        command_line: &mut CommandLine,
-        editor: &mut TextEditor,
-        args: Vec<String>,
-        event: Event,
-    ) -> Option<CursorPosition>;
-    fn open(
-        command_line: &mut CommandLine,
-        editor: &mut TextEditor,
-        args: Vec<String>,
-        event: Event,
-    ) -> Option<CursorPosition>;
-    fn save(
-        command_line: &mut CommandLine,
-        editor: &mut TextEditor,
-        args: Vec<String>,
-        event: Event,
-    ) -> Option<CursorPosition>;
+fn is_enter(event: &Event) -> bool {
+    match event {
+        Event::Key(key_event) => key_event.code == crossterm::event::KeyCode::Enter,
+        _ => false,
+    }
+}
+
+/// Which history a command line belongs to, and the entry stored for it
+fn history_entry(line: &str) -> (HistoryKind, String) {
+    if let Some(pattern) = line.strip_prefix(":find ") {
+        (HistoryKind::Search, pattern.to_string())
+    } else if line.is_empty() || line.starts_with(':') {
+        (HistoryKind::Command, line.to_string())
+    } else {
+        (HistoryKind::Shell, line.to_string())
+    }
+}
+
+fn history_line(kind: HistoryKind, entry: &str) -> String {
+    match kind {
+        HistoryKind::Search => format!(":find {}", entry),
+        _ => entry.to_string(),
+    }
+}
+
+static REVERSE_SEARCH_PROMPT: &str = "(reverse-i-search)`";
+
+struct ReverseSearch {
+    kind: HistoryKind,
+    query: String,
+    /// index of the matching history entry
+    found: Option<usize>,
+    /// the command line before searching, restored on Esc
+    saved_line: String,
 }
 
 pub struct CommandLine {
@@ -70,22 +80,13 @@ pub struct CommandLine {
     pub boder_style: BorderStyle,
     pub text_position: CursorPositionByte,
 
-    old_buffer: Rope,
-
-    commands: HashMap<
-        String,
-        fn(
-            command_line: &mut Self,
-            editor: &mut TextEditor,
-            args: Vec<String>,
-            event: Event,
-        ) -> Option<CursorPosition>,
-    >,
-    positions: Vec<(CursorPositionByte, usize)>,
-    position_idx: usize,
-
     list_popup: Option<(WidgetType, usize)>,
-    bash_popup: Option<(WidgetType, usize)>,
+    completion: Option<Completion>,
+
+    histories: Histories,
+    /// history being navigated with Up/Down
+    history_kind: Option<HistoryKind>,
+    reverse_search: Option<ReverseSearch>,
 
     z_idx: usize,
 }
@@ -105,22 +106,6 @@ impl CommandLine {
         boder_style: BorderStyle,
     ) -> Box<Self> {
         let buffer = Rope::from_str(&text);
-        let commands = {
-            let mut m: HashMap<
-                String,
-                fn(
-                    command_line: &mut Self,
-                    editor: &mut TextEditor,
-                    args: Vec<String>,
-                    event: Event,
-                ) -> Option<CursorPosition>,
-            > = HashMap::new();
-            m.insert(":quit".to_string(), Self::quit);
-            m.insert(":find".to_string(), Self::find);
-            m.insert(":open".to_string(), Self::open);
-            m.insert(":save".to_string(), Self::save);
-            m
-        };
         Box::new(Self {
             typ: WidgetType::CommandLine,
             id,
@@ -134,61 +119,129 @@ impl CommandLine {
             focused,
             targetable,
             boder_style,
-            commands,
             ..Default::default()
         })
     }
 
-    fn parse_command_line(&self) -> Vec<String> {
-        let buffer = self.buffer.to_string();
-        let mut args = Vec::new();
-        let mut current_arg = String::new();
-        let mut in_single_quote = false;
-        let mut in_double_quote = false;
-        let mut in_regex = false;
-        let mut chars = buffer.chars().peekable();
-
-        while let Some(c) = chars.next() {
-            match c {
-                // Handle space outside of quotes
-                ' ' if !in_single_quote && !in_double_quote && !in_regex => {
-                    if !current_arg.is_empty() {
-                        args.push(current_arg.clone());
-                        current_arg.clear();
-                    }
-                }
-                // Handle slash
-                '/' if !in_single_quote && !in_double_quote => {
-                    in_regex = !in_regex;
-                    current_arg.push(c);
-                }
-                // Handle end of regex
-                '\'' if !in_double_quote && !in_regex => {
-                    in_single_quote = !in_single_quote;
-                    current_arg.push(c);
-                }
-                // Handle double quote
-                '"' if !in_single_quote && !in_regex => {
-                    in_double_quote = !in_double_quote;
-                    current_arg.push(c);
-                }
-                // Handle escape sequence
-                '\\' if in_double_quote && chars.peek() == Some(&'"') => {
-                    current_arg.push(chars.next().unwrap());
-                }
-                '\\' if in_single_quote && chars.peek() == Some(&'\'') => {
-                    current_arg.push(chars.next().unwrap());
+    /// Reads the histories kept in `dir`
+    pub fn load_histories(&mut self, dir: Option<&Path>) {
+        self.histories = Histories::load(dir);
+    }
+
+    fn set_line(&mut self, line: &str) {
+        self.buffer = Rope::from_str(line);
+        self.text_position = self.buffer.len_chars();
+        self.scroll_columns = 0;
+    }
+
+    fn record_history(&mut self) {
+        let line = self.buffer.to_string();
+        if line.is_empty() {
+            return;
+        }
+        let (kind, entry) = history_entry(&line);
+        self.histories.get_mut(kind).push(&entry);
+        self.histories.reset_navigation();
+        self.history_kind = None;
+    }
+
+    fn history_prev(&mut self) {
+        let current = self.buffer.to_string();
+        let (current_kind, entry) = history_entry(&current);
+        let kind = self.history_kind.unwrap_or(current_kind);
+        if let Some(entry) = self.histories.get_mut(kind).prev(&entry) {
+            self.history_kind = Some(kind);
+            self.set_line(&history_line(kind, &entry));
+        }
+    }
+
+    fn history_next(&mut self) {
+        if let Some(kind) = self.history_kind {
+            let history = self.histories.get_mut(kind);
+            if let Some(entry) = history.next() {
+                if !history.is_navigating() {
+                    self.history_kind = None;
                 }
-                // Any other character
-                _ => current_arg.push(c),
+                self.set_line(&history_line(kind, &entry));
             }
         }
+    }
+
+    fn start_reverse_search(&mut self) {
+        let saved_line = self.buffer.to_string();
+        self.reverse_search = Some(ReverseSearch {
+            kind: history_entry(&saved_line).0,
+            query: String::new(),
+            found: None,
+            saved_line,
+        });
+        self.update_reverse_search_line();
+    }
+
+    fn update_reverse_search_line(&mut self) {
+        if let Some(search) = &self.reverse_search {
+            let history = match search.kind {
+                HistoryKind::Command => &self.histories.command,
+                HistoryKind::Search => &self.histories.search,
+                HistoryKind::Shell => &self.histories.shell,
+            };
+            let found = match search.found {
+                Some(idx) => history_line(search.kind, &history.entries[idx]),
+                None => String::new(),
+            };
+            let line = format!("{}{}': {}", REVERSE_SEARCH_PROMPT, search.query, found);
+            let cursor = REVERSE_SEARCH_PROMPT.chars().count() + search.query.chars().count();
+            self.buffer = Rope::from_str(&line);
+            self.text_position = cursor;
+        }
+    }
 
-        if !current_arg.is_empty() {
-            args.push(current_arg);
+    /// Ends the reverse search, keeping the match or restoring the old line
+    fn stop_reverse_search(&mut self, accept: bool) {
+        if let Some(search) = self.reverse_search.take() {
+            let history = self.histories.get_mut(search.kind);
+            let line = match search.found {
+                Some(idx) if accept => history_line(search.kind, &history.entries[idx]),
+                _ => search.saved_line,
+            };
+            self.set_line(&line);
         }
+    }
 
-        args
+    fn reverse_search_event(&mut self, key_event: &crossterm::event::KeyEvent) {
+        let search = match self.reverse_search.as_mut() {
+            Some(search) => search,
+            None => return,
+        };
+        let history = self.histories.get_mut(search.kind);
+        match (key_event.modifiers, key_event.code) {
+            (crossterm::event::KeyModifiers::CONTROL, crossterm::event::KeyCode::Char('r')) => {
+                if let Some(idx) = history.search(&search.query, search.found) {
+                    search.found = Some(idx);
+                }
+            }
+            (crossterm::event::KeyModifiers::CONTROL, crossterm::event::KeyCode::Char('g'))
+            | (_, crossterm::event::KeyCode::Esc) => {
+                self.stop_reverse_search(false);
+                return;
+            }
+            (
+                crossterm::event::KeyModifiers::NONE | crossterm::event::KeyModifiers::SHIFT,
+                crossterm::event::KeyCode::Char(c),
+            ) => {
+                search.query.push(c);
+                search.found = history.search(&search.query, search.found.map(|idx| idx + 1));
+            }
+            (_, crossterm::event::KeyCode::Backspace) => {
+                search.query.pop();
+                search.found = history.search(&search.query, None);
+            }
+            _ => {
+                self.stop_reverse_search(true);
+                return;
+            }
+        }
+        self.update_reverse_search_line();
     }
 
     fn execute_command(
@@ -196,447 +249,166 @@ impl CommandLine {
         editor: &mut TextEditor,
         event: &Event,
     ) -> Option<CursorPosition> {
-        if self.buffer.chars().next() == Some(':') {
-            let args: Vec<String> = self.parse_command_line();
-            eprintln!("args: {:?}", args);
-            if args.len() > 0 {
-                if let Some(command) = self.commands.get(&args[0]) {
-                    let res = command(self, editor, args, event.clone());
-                    self.old_buffer = self.buffer.clone();
-                    return res;
+        let line = self.buffer.to_string();
+        if line.starts_with(':') {
+            let trigger = if is_enter(event) {
+                Trigger::Submit
+            } else {
+                Trigger::Typing
+            };
+            match editor.execute_command_line(&line, trigger) {
+                Ok(res) => return res,
+                // The line is parsed on every key, only complain once it is submitted
+                Err(e) => {
+                    if trigger == Trigger::Submit {
+                        editor.error(e.to_string());
+                    }
                 }
             }
         } else {
-            match event {
-                Event::Key(key_event) => {
-                    if key_event.code != crossterm::event::KeyCode::Enter {
-                        return None;
-                    }
-                }
-
-                _ => return None,
+            if !is_enter(event) {
+                return None;
             }
-            // This is a bash command, execute it, dont need to parse
-            let output = std::process::Command::new("sh")
-                .arg("-c")
-                .arg(self.buffer.to_string())
-                .output()
-                .expect("failed to execute process");
-            let output = String::from_utf8_lossy(&output.stdout);
-            eprintln!("output: {:?}", output);
-            return Some(self.create_output_bash_popup(editor, output.to_string()));
+            // This is a bash command, it runs in the background, dont need to parse
+            return editor.run_shell(&line);
         }
 
         None
     }
 
-    fn next_position(&mut self, editor: &mut TextEditor) -> (CursorPositionByte, usize) {
-        if self.positions.len() > 0 {
-            self.position_idx += 1;
-            if self.position_idx >= self.positions.len() {
-                self.position_idx = 0;
+    /// Candidates for the word ending at the cursor
+    fn completion_candidates(&self, editor: &TextEditor) -> Completion {
+        let before_cursor: String = self.buffer.chars().take(self.text_position).collect();
+        let start = match before_cursor.rfind(' ') {
+            Some(idx) => before_cursor[..idx].chars().count() + 1,
+            None => 0,
+        };
+        let word: String = before_cursor.chars().skip(start).collect();
+        let candidates = if start == 0 {
+            if let Some(prefix) = word.strip_prefix(':') {
+                completion::complete_commands(editor, prefix)
+                    .iter()
+                    .map(|name| format!(":{}", name))
+                    .collect()
+            } else {
+                Vec::new()
             }
-            if let Some(panel) = editor.get_widget_mut(WidgetType::Panel) {
-                panel.set_text_position(self.positions[self.position_idx].0);
-                panel.update_cursor_position_and_view();
-                return self.positions[self.position_idx];
+        } else if before_cursor.starts_with(':') {
+            let first_word = before_cursor.split(' ').next().unwrap_or("");
+            let name = parser::parse(first_word)
+                .map(|parsed| parsed.name)
+                .unwrap_or_default();
+            match editor
+                .commands
+                .get(&name)
+                .and_then(|command| command.completer)
+            {
+                Some(completer) => completer(editor, &word),
+                None => Vec::new(),
             }
-        }
-        (0, 0)
+        } else {
+            // Arguments of a shell command are most likely paths
+            completion::complete_paths(editor, &word)
+        };
+        Completion::new(start, candidates)
     }
 
-    fn prev_position(&mut self, editor: &mut TextEditor) {
-        if self.positions.len() > 0 {
-            if self.position_idx == 0 {
-                self.position_idx = self.positions.len() - 1;
+    fn replace_completed_word(&mut self, start: usize, text: &str) {
+        self.buffer.remove(start..self.text_position);
+        self.buffer.insert(start, text);
+        self.text_position = start + text.chars().count();
+    }
+
+    fn complete(&mut self, editor: &mut TextEditor, forward: bool) {
+        if let Some(completion) = self.completion.as_mut() {
+            let candidate = if forward {
+                completion.next().to_string()
             } else {
-                self.position_idx -= 1;
+                completion.prev().to_string()
+            };
+            let start = completion.start;
+            self.replace_completed_word(start, &candidate);
+            self.update_completion_popup(editor);
+            return;
+        }
+        let completion = self.completion_candidates(editor);
+        match completion.candidates.len() {
+            0 => {}
+            1 => {
+                let candidate = completion.candidates[0].clone();
+                self.replace_completed_word(completion.start, &candidate);
             }
-            if let Some(panel) = editor.get_widget_mut(WidgetType::Panel) {
-                panel.set_text_position(self.positions[self.position_idx].0);
-                panel.update_cursor_position_and_view();
+            _ => {
+                let prefix = completion::common_prefix(&completion.candidates);
+                if prefix.chars().count() > self.text_position - completion.start {
+                    self.replace_completed_word(completion.start, &prefix);
+                }
+                self.completion = Some(completion);
+                self.update_completion_popup(editor);
             }
         }
     }
 
-    fn create_output_bash_popup(
-        &mut self,
-        editor: &mut TextEditor,
-        text: String,
-    ) -> CursorPosition {
-        // Delete the old popup
-        if let Some((typ, id)) = self.bash_popup {
+    fn reset_completion(&mut self, editor: &mut TextEditor) {
+        self.completion = None;
+        if let Some((typ, id)) = self.list_popup {
             editor.remove_widget_id(id, typ);
-            self.bash_popup = None;
-        }
-        let mut new_line_count = 0;
-        let mut max_len = 0;
-        let mut curr_len = 0;
-        text.chars().for_each(|c| {
-            curr_len += 1;
-            if c == '\n' {
-                if curr_len > max_len {
-                    max_len = curr_len;
-                }
-                new_line_count += 1;
-            }
-        });
-
-        new_line_count += 1;
-        let y = if self.get_cursor_view().1 - new_line_count - 2 < 0 {
-            0
-        } else {
-            self.get_cursor_view().1 - new_line_count - 2
-        };
-        if new_line_count > editor.height as i32 - 2 {
-            new_line_count = editor.height as i32 - 2;
+            self.list_popup = None;
         }
-        max_len = if max_len > self.get_width() {
-            self.get_width()
-        } else {
-            max_len
-        };
-        let mut widget = Popup::new(
-            text,
-            0,
-            y as usize,
-            max_len,
-            new_line_count as usize,
-            Color::Grey,
-            Color::Blue,
-            true,
-            true,
-            BorderStyle::Dashed,
-        );
-        let syntax: &SyntaxReference = unsafe {
-            PS.as_ref()
-                .unwrap()
-                .find_syntax_by_extension("sh")
-                .unwrap_or(PS.as_ref().unwrap().find_syntax_plain_text())
-        };
-        widget.set_syntax(Some(syntax));
-        widget.set_theme(Some("base16-eighties.dark".to_string()));
-        widget.set_z_idx(10);
-        let new_position = widget.get_cursor_view();
-        self.bash_popup = Some((WidgetType::Popup, editor.add_widget(widget)));
-        new_position
     }
 
-    fn create_list_command_popup(&mut self, editor: &mut TextEditor) {
-        // Delete the old popup
+    fn update_completion_popup(&mut self, editor: &mut TextEditor) {
         if let Some((typ, id)) = self.list_popup {
             editor.remove_widget_id(id, typ);
             self.list_popup = None;
         }
-        let mut text = String::new();
-        let mut max_len = 0;
-        for (key, _) in &self.commands {
-            if key.len() > max_len {
-                max_len = key.len();
-            }
-            text.push_str(key);
-            text.push_str("\n");
+        let completion = match &self.completion {
+            Some(completion) => completion,
+            None => return,
+        };
+        let text = completion.candidates.join("\n");
+        let max_len = completion
+            .candidates
+            .iter()
+            .map(|c| c.chars().count())
+            .max()
+            .unwrap_or(0);
+        let cursor_view = self.get_cursor_view();
+        let mut height = completion.candidates.len();
+        if height + 2 > cursor_view.1 as usize {
+            height = (cursor_view.1 as usize).saturating_sub(2).max(1);
         }
+        let x = (self.x + completion.start).saturating_sub(self.scroll_columns);
         let mut widget = Popup::new(
             text,
-            self.get_cursor_view().0 as usize,
-            self.get_cursor_view().1 as usize - self.commands.len() - 2,
+            x,
+            (cursor_view.1 as usize).saturating_sub(height + 2),
             max_len + 1,
-            self.commands.len() + 1,
+            height + 1,
             Color::Grey,
             Color::Blue,
             false,
             false,
             BorderStyle::Dashed,
         );
+        if let Some(selected) = completion.selected {
+            widget.push_color(
+                selected,
+                ColorText::new(
+                    0,
+                    Color::Black,
+                    Color::Grey,
+                    max_len,
+                    10,
+                    ColorTextTag::Selection,
+                ),
+            );
+            let line_start = widget.get_buffer().line_to_char(selected);
+            widget.set_text_position(line_start);
+            widget.update_cursor_position_and_view();
+        }
         widget.set_z_idx(10);
         self.list_popup = Some((WidgetType::Popup, editor.add_widget(widget)));
-        eprintln!("popup: {:?}", self.list_popup)
-    }
-
-    fn update_command_list_popup(&mut self, editor: &mut TextEditor) {
-        if let Some((typ, id)) = self.list_popup {
-            if let Some(widget) = editor.get_widget_id_mut(id, typ) {
-                let current_buffer = self.buffer.to_string();
-                let mut text = String::new();
-                let mut max_len = 0;
-                let mut number_of_commands = 0;
-                for (key, _) in &self.commands {
-                    if key.starts_with(&current_buffer) || current_buffer.starts_with(key) {
-                        if key.len() > max_len {
-                            max_len = key.len();
-                        }
-                        text.push_str(key);
-                        text.push_str("\n");
-                        number_of_commands += 1;
-                    }
-                }
-                if max_len == 0 {
-                    editor.remove_widget_id(id, typ);
-                    self.list_popup = None;
-                    return;
-                }
-                widget.set_x(self.get_cursor_view().0 as usize);
-                widget.set_y(self.get_cursor_view().1 as usize - number_of_commands - 2);
-                widget.set_width(max_len + 1);
-                widget.set_height(number_of_commands + 1);
-                widget.set_buffer(Rope::from_str(&text));
-            }
-        }
-    }
-}
-
-impl CommandLineCommands for CommandLine {
-    fn quit(
-        command_line: &mut CommandLine,
-        editor: &mut TextEditor,
-        _args: Vec<String>,
-        _event: Event,
-    ) -> Option<CursorPosition> {
-        if command_line.old_buffer.cmp(&command_line.buffer) == std::cmp::Ordering::Equal {
-            editor.running = false;
-        }
-        None
-    }
-
-    fn find(
-        command_line: &mut CommandLine,
-        editor: &mut TextEditor,
-        args: Vec<String>,
-        _event: Event,
-    ) -> Option<CursorPosition> {
-        if args.len() < 2 {
-            command_line.positions.clear();
-            command_line.position_idx = 0;
-            if let Some(panel) = editor.get_widget_mut(WidgetType::Panel) {
-                panel.remove_color(&|c: &ColorText| {
-                    c.tag == ColorTextTag::Selection || c.tag == ColorTextTag::Find
-                });
-            }
-            return None;
-        }
-        let search_term = &args[1];
-        if command_line.positions.len() > 0
-            && command_line.old_buffer.cmp(&command_line.buffer) == std::cmp::Ordering::Equal
-        {
-            let (new_position, new_len) = command_line.next_position(editor);
-            if let Some(panel) = editor.get_widget_mut(WidgetType::Panel) {
-                let y = panel.get_buffer().byte_to_line(new_position);
-                let x = panel.get_buffer().byte_to_char(new_position)
-                    - panel.get_buffer().line_to_char(y);
-                panel.remove_color(&|c: &ColorText| c.tag == ColorTextTag::Selection);
-
-                panel.push_color(
-                    y,
-                    ColorText {
-                        x,
-                        fg: Color::Blue,
-                        bg: Color::Reset,
-                        len: new_len,
-                        z_index: 10,
-                        tag: ColorTextTag::Selection,
-                    },
-                );
-            }
-            return Some(command_line.update_cursor_position_and_view());
-        }
-        command_line.positions.clear();
-        command_line.position_idx = 0;
-        if let Some(panel) = editor.get_widget_mut(WidgetType::Panel) {
-            let mut found_pos = panel.get_text_position();
-            let mut found = false;
-            let mut found_len = 0;
-            // searchterm === mod
-            // searchterm === "mod"
-            // searchterm === 'mod'
-            // searchterm === /mod/
-            // let pattern
-
-            panel.remove_color(&|c: &ColorText| c.tag == ColorTextTag::Find);
-            panel.remove_color(&|c: &ColorText| c.tag == ColorTextTag::Selection);
-
-            if search_term.starts_with('/') && search_term.ends_with('/') && search_term.len() > 2 {
-                let pattern = &search_term[1..search_term.len() - 1];
-                let regex = Regex::new(pattern);
-                if let Ok(regex) = regex {
-                    regex
-                        .find_iter(&panel.get_buffer().to_string())
-                        .for_each(|m| {
-                            let pos = m.start();
-                            let y = panel.get_buffer().byte_to_line(pos);
-                            let x = panel.get_buffer().byte_to_char(pos)
-                                - panel.get_buffer().line_to_char(y);
-                            panel.push_color(
-                                y,
-                                ColorText {
-                                    x,
-                                    fg: Color::Red,
-                                    bg: Color::Reset,
-                                    len: m.len(),
-                                    z_index: 5,
-                                    tag: ColorTextTag::Find,
-                                },
-                            );
-                            if !found && pos >= found_pos {
-                                found = true;
-                                found_pos = pos;
-                                found_len = m.len();
-                                command_line.position_idx = command_line.positions.len();
-                            }
-                            command_line.positions.push((pos, m.len()));
-                            // found_positions.push(m.start());
-                        });
-                }
-            } else {
-                let search_term = if search_term.starts_with('"')
-                    && search_term.ends_with('"')
-                    && search_term.len() > 2
-                {
-                    &search_term[1..search_term.len() - 1]
-                } else if search_term.starts_with('\'')
-                    && search_term.ends_with('\'')
-                    && search_term.len() > 2
-                {
-                    &search_term[1..search_term.len() - 1]
-                } else {
-                    search_term
-                };
-                panel
-                    .get_buffer()
-                    .to_string()
-                    .match_indices(search_term)
-                    .for_each(|(pos, _)| {
-                        let y = panel.get_buffer().byte_to_line(pos);
-                        let x = panel.get_buffer().byte_to_char(pos)
-                            - panel.get_buffer().line_to_char(y);
-                        panel.push_color(
-                            y,
-                            ColorText {
-                                x,
-                                fg: Color::Red,
-                                bg: Color::Reset,
-                                len: search_term.len(),
-                                z_index: 5,
-                                tag: ColorTextTag::Find,
-                            },
-                        );
-                        if !found && pos >= found_pos {
-                            found = true;
-                            found_pos = pos;
-                            found_len = search_term.len();
-                            command_line.position_idx = command_line.positions.len();
-                        }
-                        command_line.positions.push((pos, search_term.len()));
-                    });
-            }
-            if !found && command_line.positions.len() > 0 {
-                found_pos = command_line.positions[0].0;
-                found_len = command_line.positions[0].1;
-                command_line.position_idx = 0;
-            }
-
-            if found {
-                let y = panel.get_buffer().byte_to_line(found_pos);
-                let x =
-                    panel.get_buffer().byte_to_char(found_pos) - panel.get_buffer().line_to_char(y);
-                panel.push_color(
-                    y,
-                    ColorText {
-                        x,
-                        fg: Color::Blue,
-                        bg: Color::Reset,
-                        len: found_len,
-                        z_index: 10,
-                        tag: ColorTextTag::Selection,
-                    },
-                );
-                panel.set_text_position(found_pos);
-                panel.update_cursor_position_and_view();
-            }
-        }
-        None
-    }
-
-    fn open(
-        command_line: &mut CommandLine,
-        editor: &mut TextEditor,
-        args: Vec<String>,
-        event: Event,
-    ) -> Option<CursorPosition> {
-        if args.len() < 2 {
-            return None;
-        }
-        // if event != Enter
-        match event {
-            Event::Key(key_event) => {
-                if key_event.code != crossterm::event::KeyCode::Enter {
-                    return None;
-                }
-            }
-
-            _ => return None,
-        }
-        let path = &args[1];
-        // Open the file
-        if let Some(panel) = editor.get_widget(WidgetType::Panel) {
-            editor.focused_widget_id = panel.get_id();
-        }
-        if let Some(panel) = editor.get_widget_mut(WidgetType::Panel) {
-            let buffer = fs::read_to_string(path).unwrap();
-            eprintln!("buffer: {:?}", buffer);
-            panel.set_buffer(Rope::from_str(&buffer));
-            panel.set_text_position(0);
-            let res = panel.update_cursor_position_and_view();
-            panel.remove_color(&|c: &ColorText| {
-                c.tag == ColorTextTag::Selection || c.tag == ColorTextTag::Find
-            });
-            editor.written = false;
-            command_line.buffer = Rope::from_str("");
-            command_line.text_position = 0;
-            command_line.focused = false;
-            command_line.old_buffer = command_line.buffer.clone();
-            return Some(res);
-        }
-        None
-    }
-
-    fn save(
-        command_line: &mut CommandLine,
-        editor: &mut TextEditor,
-        args: Vec<String>,
-        event: Event,
-    ) -> Option<CursorPosition> {
-        if args.len() < 2 {
-            return None;
-        }
-        // if event != Enter
-        match event {
-            Event::Key(key_event) => {
-                if key_event.code != crossterm::event::KeyCode::Enter {
-                    return None;
-                }
-            }
-
-            _ => return None,
-        }
-        let path = &args[1];
-        // Save the file
-        if let Some(panel) = editor.get_widget(WidgetType::Panel) {
-            editor.focused_widget_id = panel.get_id();
-        }
-        if let Some(panel) = editor.get_widget(WidgetType::Panel) {
-            fs::write(path, panel.get_buffer().to_string()).unwrap();
-            editor.render(panel.get_cursor_view(), panel.is_cursor_visible());
-            editor.written = false;
-            command_line.buffer = Rope::from_str("");
-            command_line.text_position = 0;
-            command_line.focused = false;
-            command_line.old_buffer = command_line.buffer.clone();
-        }
-        if let Some(status_bar) = editor.get_widget_mut(WidgetType::StatusBar) {
-            status_bar.set_buffer(Rope::from_str(path));
-        }
-        None
     }
 }
 
@@ -647,7 +419,6 @@ impl Default for CommandLine {
             typ: WidgetType::CommandLine,
             id: 0,
             buffer: Rope::from_str(""),
-            old_buffer: Rope::from_str(""),
             scroll_lines: 0,
             scroll_columns: 0,
             default_fg: Color::White,
@@ -660,11 +431,11 @@ impl Default for CommandLine {
             targetable: false,
             boder_style: BorderStyle::None,
             text_position: 0,
-            commands: HashMap::new(),
-            positions: Vec::new(),
-            position_idx: 0,
             list_popup: None,
-            bash_popup: None,
+            completion: None,
+            histories: Histories::default(),
+            history_kind: None,
+            reverse_search: None,
             z_idx: 0,
             colors: Vec::new(),
         }
@@ -787,46 +558,70 @@ impl ProcessEvent for CommandLine {
     ) -> Option<(CursorPosition, ShouldExit)> {
         if self.focused {
             if let Event::Key(key_event) = event {
+                if self.reverse_search.is_some() {
+                    self.reverse_search_event(key_event);
+                    return Some((self.update_cursor_position_and_view(), false));
+                }
+                match key_event.code {
+                    crossterm::event::KeyCode::Up | crossterm::event::KeyCode::Down => {}
+                    _ => {
+                        self.histories.reset_navigation();
+                        self.history_kind = None;
+                    }
+                }
+                if key_event.code != crossterm::event::KeyCode::Tab
+                    && key_event.code != crossterm::event::KeyCode::BackTab
+                    && key_event.code != crossterm::event::KeyCode::Esc
+                {
+                    self.reset_completion(editor);
+                }
                 if key_event.modifiers == crossterm::event::KeyModifiers::SHIFT {
                     match key_event.code {
                         crossterm::event::KeyCode::Char(c) => {
                             self.buffer.insert_char(self.text_position, c);
                             self.text_position += 1;
                             let position = self.execute_command(editor, event);
-                            self.update_command_list_popup(editor);
                             if let Some(position) = position {
                                 return Some((position, false));
                             }
                             return Some((self.update_cursor_position_and_view(), false));
                         }
+                        crossterm::event::KeyCode::BackTab => {
+                            self.complete(editor, false);
+                            return Some((self.update_cursor_position_and_view(), false));
+                        }
                         _ => {}
                     }
                 }
                 if key_event.modifiers == crossterm::event::KeyModifiers::NONE {
                     match key_event.code {
                         crossterm::event::KeyCode::Tab => {
-                            eprintln!("command line tab");
-                            if let Some((bash_popup_type, bash_popup_id)) = self.bash_popup {
-                                editor.focused_widget_id = bash_popup_id;
+                            // Tab completes what was typed, the popup is only
+                            // focused from an empty command line
+                            let typed = self.buffer.chars().any(|c| c != ':');
+                            if let Some((output_popup_type, output_popup_id)) =
+                                editor.output_popup.filter(|_| !typed)
+                            {
+                                editor.focused_widget_id = output_popup_id;
                                 if let Some(widget) =
-                                    editor.get_widget_id_mut(bash_popup_id, bash_popup_type)
+                                    editor.get_widget_id_mut(output_popup_id, output_popup_type)
                                 {
                                     self.focused = false;
                                     widget.set_focused(true);
                                     return Some((widget.update_cursor_position_and_view(), false));
                                 }
                             }
-                            self.create_list_command_popup(editor)
+                            self.complete(editor, true);
+                            return Some((self.update_cursor_position_and_view(), false));
                         }
                         crossterm::event::KeyCode::Esc => {
-                            if let Some((typ, id)) = self.list_popup {
-                                editor.remove_widget_id(id, typ);
-                                self.list_popup = None;
+                            if self.list_popup.is_some() {
+                                self.reset_completion(editor);
                                 return Some((self.update_cursor_position_and_view(), false));
                             }
-                            if let Some((typ, id)) = self.bash_popup {
+                            if let Some((typ, id)) = editor.output_popup {
                                 editor.remove_widget_id(id, typ);
-                                self.bash_popup = None;
+                                editor.output_popup = None;
                                 return Some((self.update_cursor_position_and_view(), false));
                             }
                             self.focused = false;
@@ -846,7 +641,6 @@ impl ProcessEvent for CommandLine {
                             self.buffer.insert_char(self.text_position, c);
                             self.text_position += 1;
                             let position = self.execute_command(editor, event);
-                            self.update_command_list_popup(editor);
                             if let Some(position) = position {
                                 return Some((position, false));
                             }
@@ -859,15 +653,27 @@ impl ProcessEvent for CommandLine {
                                 self.text_position -= 1;
                             }
                             let position = self.execute_command(editor, event);
-                            self.update_command_list_popup(editor);
                             if let Some(position) = position {
                                 return Some((position, false));
                             }
                             return Some((self.update_cursor_position_and_view(), false));
                         }
+                        crossterm::event::KeyCode::Up => {
+                            self.history_prev();
+                            return Some((self.update_cursor_position_and_view(), false));
+                        }
+                        crossterm::event::KeyCode::Down => {
+                            self.history_next();
+                            return Some((self.update_cursor_position_and_view(), false));
+                        }
                         crossterm::event::KeyCode::Enter => {
+                            self.record_history();
                             let position = self.execute_command(editor, event);
-                            self.update_command_list_popup(editor);
+                            // The command moved the focus, it is done with the line
+                            if editor.focused_widget_id != self.id {
+                                self.set_line("");
+                                self.focused = false;
+                            }
                             if let Some(position) = position {
                                 return Some((position, false));
                             }
@@ -881,11 +687,14 @@ impl ProcessEvent for CommandLine {
                             // TODO: repace this
                             self.execute_command(editor, event);
                         }
+                        crossterm::event::KeyCode::Char('r') => {
+                            self.start_reverse_search();
+                            return Some((self.update_cursor_position_and_view(), false));
+                        }
                         _ => {}
                     }
                 }
             }
-            self.update_command_list_popup(editor);
         }
         return None;
     }
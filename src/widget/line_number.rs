@@ -1,11 +1,11 @@
 use crossterm::{event::Event, style::Color};
 use ropey::Rope;
 
-use crate::editor::TextEditor;
+use crate::{editor::TextEditor, git::Change};
 
 use super::widget::{
-    BorderStyle, ColorText, CursorPosition, CursorPositionByte, ProcessEvent, ShouldExit,
-    WidgetType,
+    BorderStyle, ColorText, ColorTextTag, CursorPosition, CursorPositionByte, ProcessEvent,
+    ShouldExit, WidgetType,
 };
 
 pub struct LineNumber {
@@ -214,32 +214,57 @@ impl ProcessEvent for LineNumber {
                 let is_relative = true;
                 let mut line_number = String::new();
                 let max_lines = panel.get_buffer().len_lines();
-                let max_v = if max_lines < panel.get_height() {
-                    max_lines
-                } else {
-                    panel.get_height()
-                };
-                for j in panel.get_scroll_lines()..(panel.get_scroll_lines() + max_v) {
-                    // Padded to the right
-                    if is_relative {
-                        let pos = panel.get_cursor_view();
-                        let v: i32 = (j as i32) - (pos.1 + panel.get_scroll_lines() as i32);
-                        let value: String = if v == 0 {
+                let folds = panel.get_folds();
+                let hunks = panel.get_hunks();
+                let mut colors = Vec::new();
+                // The rows of the panel, a closed fold taking one
+                let mut j = panel.row_line(panel.line_row(panel.get_scroll_lines()));
+                let cursor_row = panel.get_cursor_view().1 - panel.get_y() as i32;
+                for row in 0..panel.get_height() {
+                    if j >= max_lines {
+                        break;
+                    }
+                    let value: String = if is_relative {
+                        let v: i32 = row as i32 - cursor_row;
+                        if v == 0 {
                             (j + 1).to_string()
                         } else {
                             (v.abs()).to_string()
-                        };
-                        line_number.push_str(&" ".repeat(self.width - value.len()));
-                        line_number.push_str(&value);
-                        line_number.push('\n');
+                        }
                     } else {
-                        let value: String = (j + 1).to_string();
-                        line_number.push_str(&" ".repeat(self.width - value.len()));
-                        line_number.push_str(&value);
-                        line_number.push('\n');
-                    }
+                        (j + 1).to_string()
+                    };
+                    // Padded to the right, after whether a fold starts there
+                    let marker = match folds.and_then(|folds| folds.starting_at(j)) {
+                        Some(fold) if fold.closed => '▸',
+                        Some(_) => '▾',
+                        None => ' ',
+                    };
+                    line_number.push(marker);
+                    // Then how the line changed since the last commit
+                    let (sign, color) = match hunks.and_then(|hunks| hunks.change_at(j)) {
+                        Some(Change::Added) => ('+', Color::Green),
+                        Some(Change::Modified) => ('~', Color::Yellow),
+                        Some(Change::Removed) => ('-', Color::Red),
+                        None => (' ', self.default_fg),
+                    };
+                    line_number.push(sign);
+                    colors.push(vec![ColorText::new(
+                        marker.len_utf8(),
+                        color,
+                        self.default_bg,
+                        1,
+                        0,
+                        ColorTextTag::None,
+                    )]);
+                    let padding = (self.width - 2).saturating_sub(value.len());
+                    line_number.push_str(&" ".repeat(padding));
+                    line_number.push_str(&value);
+                    line_number.push('\n');
+                    j = folds.map_or(j + 1, |folds| folds.next_line(j));
                 }
                 self.set_buffer(ropey::Rope::from_str(&line_number));
+                self.set_colors(colors);
             }
         }
         None
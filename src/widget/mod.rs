@@ -1,6 +1,9 @@
 pub mod command_line;
 pub mod line_number;
+pub mod palette;
 pub mod panel;
 pub mod popup;
+pub mod prompt;
 pub mod status_bar;
+pub mod terminal_pane;
 pub mod widget;
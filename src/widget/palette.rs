@@ -0,0 +1,393 @@
+use crossterm::{
+    event::{Event, KeyCode, KeyModifiers},
+    style::Color,
+};
+use ropey::Rope;
+
+use crate::{editor::TextEditor, fuzzy};
+
+use super::widget::{
+    BorderStyle, ColorText, ColorTextTag, CursorPosition, CursorPositionByte, ProcessEvent,
+    ShouldExit, WidgetType,
+};
+
+static PROMPT: &str = "> ";
+
+/// A command as listed in the palette
+pub struct PaletteEntry {
+    pub name: String,
+    pub description: String,
+    /// keybindings running the command, comma separated
+    pub keys: String,
+    /// the command can not run without arguments, the command line is opened instead
+    pub needs_args: bool,
+}
+
+impl PaletteEntry {
+    /// Every registered command with its keybindings
+    pub fn collect(editor: &TextEditor) -> Vec<PaletteEntry> {
+        editor
+            .commands
+            .names()
+            .iter()
+            .filter_map(|name| editor.commands.get(name))
+            .map(|command| PaletteEntry {
+                name: command.name.clone(),
+                description: command.description.clone(),
+                keys: editor.keymap.bindings_for(&command.name).join(", "),
+                needs_args: command.args.iter().any(|arg| !arg.optional),
+            })
+            .collect()
+    }
+}
+
+pub struct Palette {
+    pub typ: WidgetType,
+    pub id: usize,
+    /// the text
+    pub buffer: Rope,
+    pub colors: Vec<Vec<ColorText>>,
+
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+
+    /// the color
+    pub default_fg: Color,
+    pub default_bg: Color,
+
+    pub focused: bool,
+    pub targetable: bool,
+
+    /// scolled lines
+    pub scroll_lines: usize,
+
+    /// scrolled columns
+    pub scroll_columns: usize,
+
+    pub boder_style: BorderStyle,
+    pub text_position: CursorPositionByte,
+
+    pub z_index: usize,
+
+    query: String,
+    entries: Vec<PaletteEntry>,
+    /// indices in `entries` matching the query, best first
+    matches: Vec<usize>,
+    /// index in `matches`
+    selected: usize,
+    /// first match shown
+    first_shown: usize,
+
+    /// widget focused, and its cursor, before the palette opened
+    return_focus: usize,
+    return_cursor: CursorPosition,
+}
+
+impl Palette {
+    pub fn new(
+        editor: &TextEditor,
+        return_focus: usize,
+        return_cursor: CursorPosition,
+    ) -> Box<Self> {
+        let entries = PaletteEntry::collect(editor);
+        let width = (editor.width * 2 / 3)
+            .max(20)
+            .min(editor.width.saturating_sub(2));
+        let height = (entries.len() + 2).min(editor.height / 2).max(4);
+        let mut palette = Box::new(Self {
+            x: (editor.width - width) / 2,
+            y: 1,
+            width,
+            height,
+            focused: true,
+            targetable: true,
+            boder_style: BorderStyle::Solid,
+            z_index: 20,
+            entries,
+            return_focus,
+            return_cursor,
+            ..Default::default()
+        });
+        palette.update_matches();
+        palette
+    }
+
+    /// Number of matches that fit under the query line
+    fn visible_rows(&self) -> usize {
+        self.height.saturating_sub(2 * self.get_offset() + 1).max(1)
+    }
+
+    fn update_matches(&mut self) {
+        let names: Vec<&str> = self.entries.iter().map(|e| e.name.as_str()).collect();
+        self.matches = fuzzy::fuzzy_filter(&self.query, &names);
+        self.selected = 0;
+        self.first_shown = 0;
+        self.update_view();
+    }
+
+    fn select(&mut self, forward: bool) {
+        if self.matches.is_empty() {
+            return;
+        }
+        self.selected = if forward {
+            (self.selected + 1) % self.matches.len()
+        } else {
+            (self.selected + self.matches.len() - 1) % self.matches.len()
+        };
+        self.update_view();
+    }
+
+    fn update_view(&mut self) {
+        let rows = self.visible_rows();
+        if self.selected < self.first_shown {
+            self.first_shown = self.selected;
+        }
+        if self.selected >= self.first_shown + rows {
+            self.first_shown = self.selected + 1 - rows;
+        }
+        let width = self.width.saturating_sub(2 * self.get_offset());
+        let name_width = self
+            .entries
+            .iter()
+            .map(|e| e.name.chars().count())
+            .max()
+            .unwrap_or(0);
+
+        let mut text = format!("{}{}", PROMPT, self.query);
+        for &idx in self.matches.iter().skip(self.first_shown).take(rows) {
+            let entry = &self.entries[idx];
+            let left = format!("{:name_width$}  {}", entry.name, entry.description);
+            let left_len = left.chars().count();
+            let keys_len = entry.keys.chars().count();
+            let row = if left_len + keys_len + 2 <= width {
+                format!(
+                    "{}{}{}",
+                    left,
+                    " ".repeat(width - left_len - keys_len),
+                    entry.keys
+                )
+            } else {
+                left
+            };
+            text.push('\n');
+            text.push_str(&row);
+        }
+        if self.matches.is_empty() {
+            text.push_str("\nNo matching command");
+        }
+        self.buffer = Rope::from_str(&text);
+        self.clear_colors();
+        if !self.matches.is_empty() {
+            self.push_color(
+                self.selected - self.first_shown + 1,
+                ColorText::new(
+                    0,
+                    Color::Black,
+                    Color::Grey,
+                    width,
+                    10,
+                    ColorTextTag::Selection,
+                ),
+            );
+        }
+        self.text_position = PROMPT.len() + self.query.chars().count();
+    }
+
+    /// Gives the focus back to the widget focused before the palette opened
+    fn close(&mut self, editor: &mut TextEditor) -> Option<(CursorPosition, ShouldExit)> {
+        editor.focused_widget_id = self.return_focus;
+        if let Some(widget) = editor.focused_widget_mut() {
+            widget.set_focused(true);
+        }
+        Some((self.return_cursor, true))
+    }
+
+    /// Runs the selected command through the same path as the keybindings
+    fn run_selected(&mut self, editor: &mut TextEditor) -> Option<(CursorPosition, ShouldExit)> {
+        if let Some(&idx) = self.matches.get(self.selected) {
+            let entry = &self.entries[idx];
+            if entry.needs_args {
+                editor.queue_command(&format!(":command-line :{} ", entry.name));
+            } else {
+                editor.queue_command(&format!(":{}", entry.name));
+            }
+        }
+        self.close(editor)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        // Return a new Widget with default values here
+        Self {
+            typ: WidgetType::Palette,
+            id: 0,
+            buffer: Rope::from_str(""),
+            scroll_lines: 0,
+            scroll_columns: 0,
+            default_fg: Color::White,
+            default_bg: Color::Black,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            focused: false,
+            targetable: false,
+            boder_style: BorderStyle::None,
+            text_position: 0,
+            z_index: 0,
+            colors: Vec::new(),
+            query: String::new(),
+            entries: Vec::new(),
+            matches: Vec::new(),
+            selected: 0,
+            first_shown: 0,
+            return_focus: 0,
+            return_cursor: (0, 0),
+        }
+    }
+}
+
+impl ProcessEvent for Palette {
+    fn get_border_style(&self) -> BorderStyle {
+        self.boder_style
+    }
+    fn get_buffer(&self) -> &Rope {
+        &self.buffer
+    }
+    fn get_height(&self) -> usize {
+        self.height
+    }
+    fn get_width(&self) -> usize {
+        self.width
+    }
+    fn get_x(&self) -> usize {
+        self.x
+    }
+    fn get_y(&self) -> usize {
+        self.y
+    }
+    fn get_scroll_lines(&self) -> usize {
+        self.scroll_lines
+    }
+    fn get_scroll_columns(&self) -> usize {
+        self.scroll_columns
+    }
+    fn get_default_fg(&self) -> Color {
+        self.default_fg
+    }
+    fn get_default_bg(&self) -> Color {
+        self.default_bg
+    }
+    fn get_text_position(&self) -> CursorPositionByte {
+        self.text_position
+    }
+    fn get_focused(&self) -> bool {
+        self.focused
+    }
+    fn get_targetable(&self) -> bool {
+        self.targetable
+    }
+    fn get_type(&self) -> WidgetType {
+        WidgetType::Palette
+    }
+    fn get_id(&self) -> usize {
+        self.id
+    }
+    fn get_z_idx(&self) -> usize {
+        self.z_index
+    }
+
+    fn get_colors(&self) -> Vec<Vec<ColorText>> {
+        self.colors.clone()
+    }
+    fn get_colors_mut(&mut self) -> &mut Vec<Vec<ColorText>> {
+        &mut self.colors
+    }
+    fn set_colors(&mut self, colors: Vec<Vec<ColorText>>) {
+        self.colors = colors;
+    }
+
+    fn set_border_style(&mut self, border_style: BorderStyle) {
+        self.boder_style = border_style;
+    }
+    fn set_buffer(&mut self, buffer: Rope) {
+        self.buffer = buffer;
+    }
+    fn set_height(&mut self, height: usize) {
+        self.height = height;
+    }
+    fn set_width(&mut self, width: usize) {
+        self.width = width;
+    }
+    fn set_x(&mut self, x: usize) {
+        self.x = x;
+    }
+    fn set_y(&mut self, y: usize) {
+        self.y = y;
+    }
+    fn set_scroll_lines(&mut self, scroll_lines: usize) {
+        self.scroll_lines = scroll_lines;
+    }
+    fn set_scroll_columns(&mut self, scroll_columns: usize) {
+        self.scroll_columns = scroll_columns;
+    }
+    fn set_default_fg(&mut self, default_fg: Color) {
+        self.default_fg = default_fg;
+    }
+    fn set_default_bg(&mut self, default_bg: Color) {
+        self.default_bg = default_bg;
+    }
+    fn set_text_position(&mut self, text_position: CursorPositionByte) {
+        self.text_position = text_position;
+    }
+    fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+    fn set_targetable(&mut self, targetable: bool) {
+        self.targetable = targetable;
+    }
+    fn set_type(&mut self, id: WidgetType) {
+        self.typ = id;
+    }
+    fn set_id(&mut self, id: usize) {
+        self.id = id;
+    }
+    fn set_z_idx(&mut self, z_idx: usize) {
+        self.z_index = z_idx;
+    }
+
+    fn event(
+        &mut self,
+        editor: &mut TextEditor,
+        event: &Event,
+    ) -> Option<(CursorPosition, ShouldExit)> {
+        if !self.focused {
+            return None;
+        }
+        if let Event::Key(key) = event {
+            match (key.code, key.modifiers) {
+                (KeyCode::Esc, _) => return self.close(editor),
+                (KeyCode::Enter, _) => return self.run_selected(editor),
+                (KeyCode::Up, _) | (KeyCode::BackTab, _) => self.select(false),
+                (KeyCode::Char('p'), KeyModifiers::CONTROL) => self.select(false),
+                (KeyCode::Down, _) | (KeyCode::Tab, _) => self.select(true),
+                (KeyCode::Char('n'), KeyModifiers::CONTROL) => self.select(true),
+                (KeyCode::Backspace, _) => {
+                    self.query.pop();
+                    self.update_matches();
+                }
+                (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                    self.query.push(c);
+                    self.update_matches();
+                }
+                _ => {}
+            }
+            // Keys never reach the keybindings while the palette is open
+            return Some((self.update_cursor_position_and_view(), false));
+        }
+        None
+    }
+}
@@ -6,11 +6,14 @@ use syntect::parsing::{SyntaxReference, SyntaxSet};
 use crate::{
     action::{Action, ActionType},
     editor::TextEditor,
+    fold::{FoldMethod, Folds},
+    git::Hunks,
     widget::widget::WidgetType,
 };
 
 use super::widget::{
-    BorderStyle, ColorText, CursorPosition, CursorPositionByte, ProcessEvent, ShouldExit,
+    BorderStyle, ColorText, ColorTextTag, CursorPosition, CursorPositionByte, ProcessEvent,
+    ShouldExit,
 };
 
 pub struct Panel {
@@ -49,6 +52,15 @@ pub struct Panel {
     pub undo_stack: Vec<Action>,
     pub redo_stack: Vec<Action>,
     pub current_action: Action,
+
+    pub selection_anchor: Option<CursorPositionByte>,
+
+    /// the folds of the text, from its indentation or its braces
+    pub folds: Folds,
+    /// the lines changed since the last commit of the file
+    pub hunks: Hunks,
+    /// the text changed since the hunks were diffed, they are on the next tick
+    pub hunks_outdated: bool,
 }
 
 impl Panel {
@@ -82,6 +94,54 @@ impl Panel {
     }
 }
 
+impl Panel {
+    /// Folds the text again after an edit at the char `position`, it is diffed
+    /// on the next tick so that a burst of edits is diffed once
+    fn text_changed(&mut self, position: usize) {
+        let line = self
+            .buffer
+            .char_to_line(position.min(self.buffer.len_chars()));
+        self.folds.update(&self.buffer, Some(line));
+        self.hunks_outdated = true;
+    }
+
+    /// Diffs the text again if it changed since, returns whether it did
+    fn update_hunks(&mut self) -> bool {
+        if !std::mem::take(&mut self.hunks_outdated) {
+            return false;
+        }
+        self.hunks.update(&self.buffer);
+        true
+    }
+
+    /// Colors the selected chars, line by line
+    fn update_selection_colors(&mut self) {
+        self.remove_color(&|c: &ColorText| c.tag == ColorTextTag::TextSelection);
+        let (start, end) = match self.get_selection() {
+            Some(selection) => selection,
+            None => return,
+        };
+        let first_line = self.buffer.char_to_line(start);
+        let last_line = self.buffer.char_to_line(end - 1);
+        for line in first_line..=last_line {
+            let line_start = self.buffer.line_to_char(line);
+            let from = start.max(line_start);
+            let to = end.min(line_start + self.buffer.line(line).len_chars());
+            self.push_color(
+                line,
+                ColorText::new(
+                    from - line_start,
+                    Color::Black,
+                    Color::Grey,
+                    (to - from).max(1),
+                    8,
+                    ColorTextTag::TextSelection,
+                ),
+            );
+        }
+    }
+}
+
 impl Default for Panel {
     fn default() -> Self {
         // Return a new Widget with default values here
@@ -108,6 +168,10 @@ impl Default for Panel {
             undo_stack: vec![],
             redo_stack: vec![],
             current_action: Action::default(),
+            selection_anchor: None,
+            folds: Folds::default(),
+            hunks: Hunks::default(),
+            hunks_outdated: false,
         }
     }
 }
@@ -168,13 +232,56 @@ impl ProcessEvent for Panel {
         self.theme.clone()
     }
 
+    fn get_folds(&self) -> Option<&Folds> {
+        Some(&self.folds)
+    }
+    fn get_folds_mut(&mut self) -> Option<&mut Folds> {
+        Some(&mut self.folds)
+    }
+    fn get_hunks(&self) -> Option<&Hunks> {
+        Some(&self.hunks)
+    }
+    fn get_hunks_mut(&mut self) -> Option<&mut Hunks> {
+        self.update_hunks();
+        Some(&mut self.hunks)
+    }
+    fn get_selection_anchor(&self) -> Option<CursorPositionByte> {
+        self.selection_anchor
+    }
+    fn set_selection_anchor(&mut self, anchor: Option<CursorPositionByte>) {
+        self.selection_anchor = anchor;
+    }
+
+    fn replace_range(&mut self, start: usize, end: usize, text: &str) {
+        if self.current_action.started {
+            self.current_action.done = true;
+            self.undo_stack.push(self.current_action.clone());
+            self.current_action = Action::default();
+        }
+        let old_text = self.buffer.slice(start..end).to_string();
+        self.buffer.remove(start..end);
+        self.buffer.insert(start, text);
+        self.text_changed(start);
+        let mut action = Action::new(ActionType::Replace, start, text.to_string());
+        action.old_text = old_text;
+        action.done = true;
+        self.undo_stack.push(action);
+        self.redo_stack.clear();
+        self.selection_anchor = None;
+        self.text_position = start + text.chars().count();
+    }
+
+    fn tick(&mut self) -> bool {
+        self.update_hunks()
+    }
+
     fn undo(&mut self) -> Option<CursorPosition> {
         if self.current_action.started {
             self.current_action.done = true;
             self.undo_stack.push(self.current_action.clone());
             self.current_action = Action::default();
         }
-        eprintln!("Undo stack {:?}", self.undo_stack);
+        log::trace!("Undo stack {:?}", self.undo_stack);
         if let Some(action) = self.undo_stack.pop() {
             match action.typ {
                 ActionType::Insert => {
@@ -184,6 +291,7 @@ impl ProcessEvent for Panel {
                             ..action.cursor_position_byte - 1 + action.text.len(),
                     );
                     self.text_position = action.cursor_position_byte - 1;
+                    self.text_changed(self.text_position);
                     return Some(self.update_cursor_position_and_view());
                 }
                 ActionType::Delete => {
@@ -193,6 +301,7 @@ impl ProcessEvent for Panel {
                         &action.text.chars().rev().collect::<String>(),
                     );
                     self.text_position = action.cursor_position_byte;
+                    self.text_changed(action.cursor_position_byte - action.text.len());
                     return Some(self.update_cursor_position_and_view());
                 }
                 ActionType::MoveCursor => {
@@ -200,6 +309,16 @@ impl ProcessEvent for Panel {
                     self.text_position = action.cursor_position_byte;
                     return Some(self.update_cursor_position_and_view());
                 }
+                ActionType::Replace => {
+                    self.redo_stack.push(action.clone());
+                    let start = action.cursor_position_byte;
+                    self.buffer
+                        .remove(start..start + action.text.chars().count());
+                    self.buffer.insert(start, &action.old_text);
+                    self.text_position = start;
+                    self.text_changed(start);
+                    return Some(self.update_cursor_position_and_view());
+                }
                 _ => {}
             }
         }
@@ -212,7 +331,7 @@ impl ProcessEvent for Panel {
             self.undo_stack.push(self.current_action.clone());
             self.current_action = Action::default();
         }
-        eprintln!("Redo stack {:?}", self.redo_stack);
+        log::trace!("Redo stack {:?}", self.redo_stack);
         if let Some(action) = self.redo_stack.pop() {
             match action.typ {
                 ActionType::Insert => {
@@ -220,6 +339,7 @@ impl ProcessEvent for Panel {
                     self.buffer
                         .insert(action.cursor_position_byte - 1, &action.text);
                     self.text_position = action.cursor_position_byte - 1 + action.text.len();
+                    self.text_changed(action.cursor_position_byte - 1);
                     return Some(self.update_cursor_position_and_view());
                 }
                 ActionType::Delete => {
@@ -229,6 +349,7 @@ impl ProcessEvent for Panel {
                             ..action.cursor_position_byte - 1 + action.text.len(),
                     );
                     self.text_position = action.cursor_position_byte - 1;
+                    self.text_changed(self.text_position);
                     return Some(self.update_cursor_position_and_view());
                 }
                 ActionType::MoveCursor => {
@@ -236,6 +357,16 @@ impl ProcessEvent for Panel {
                     self.text_position = action.cursor_position_byte;
                     return Some(self.update_cursor_position_and_view());
                 }
+                ActionType::Replace => {
+                    self.undo_stack.push(action.clone());
+                    let start = action.cursor_position_byte;
+                    self.buffer
+                        .remove(start..start + action.old_text.chars().count());
+                    self.buffer.insert(start, &action.text);
+                    self.text_position = start + action.text.chars().count();
+                    self.text_changed(start);
+                    return Some(self.update_cursor_position_and_view());
+                }
                 _ => {}
             }
         }
@@ -257,6 +388,15 @@ impl ProcessEvent for Panel {
     }
     fn set_buffer(&mut self, buffer: Rope) {
         self.buffer = buffer;
+        // The changes were made to another text, their offsets mean nothing in
+        // this one
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.current_action = Action::default();
+        self.selection_anchor = None;
+        self.folds.reset(&self.buffer);
+        self.hunks.update(&self.buffer);
+        self.hunks_outdated = false;
     }
     fn set_height(&mut self, height: usize) {
         self.height = height;
@@ -302,6 +442,10 @@ impl ProcessEvent for Panel {
     }
     fn set_syntax(&mut self, syntax: Option<&SyntaxReference>) {
         self.syntax = syntax.cloned();
+        let method = syntax.map_or(FoldMethod::Indent, |syntax| {
+            FoldMethod::for_syntax(&syntax.name)
+        });
+        self.folds.set_method(method, &self.buffer);
     }
     fn set_theme(&mut self, theme: Option<String>) {
         self.theme = theme;
@@ -312,6 +456,26 @@ impl ProcessEvent for Panel {
         editor: &mut TextEditor,
         event: &Event,
     ) -> Option<(CursorPosition, ShouldExit)> {
+        if let Event::Key(key_event) = event {
+            // Editing drops the selection, moving with shift extends it
+            let edits = matches!(
+                (key_event.code, key_event.modifiers),
+                (
+                    crossterm::event::KeyCode::Char(_),
+                    crossterm::event::KeyModifiers::NONE | crossterm::event::KeyModifiers::SHIFT
+                ) | (
+                    crossterm::event::KeyCode::Enter | crossterm::event::KeyCode::Backspace,
+                    _
+                )
+            );
+            if self.focused && edits {
+                if !editor.check_writable() {
+                    return Some((self.update_cursor_position_and_view(), false));
+                }
+                self.selection_anchor = None;
+            }
+        }
+        self.update_selection_colors();
         if self.focused {
             if let Event::Key(key_event) = event {
                 match key_event.modifiers {
@@ -319,6 +483,7 @@ impl ProcessEvent for Panel {
                         crossterm::event::KeyCode::Char(c) => {
                             self.buffer.insert_char(self.text_position, c);
                             self.text_position += 1;
+                            self.text_changed(self.text_position - 1);
                             editor.written = true;
                             if self.current_action.started {
                                 if self.current_action.typ != ActionType::Insert
@@ -351,6 +516,7 @@ impl ProcessEvent for Panel {
                         crossterm::event::KeyCode::Char(c) => {
                             self.buffer.insert_char(self.text_position, c);
                             self.text_position += 1;
+                            self.text_changed(self.text_position - 1);
                             editor.written = true;
                             if self.current_action.started {
                                 if self.current_action.typ != ActionType::Insert
@@ -370,7 +536,7 @@ impl ProcessEvent for Panel {
                                 }
                                 self.current_action.text.push(c);
                             } else {
-                                eprintln!("Insert");
+                                log::trace!("Insert");
                                 self.current_action = Action::new(
                                     ActionType::Insert,
                                     self.text_position,
@@ -382,6 +548,7 @@ impl ProcessEvent for Panel {
                         crossterm::event::KeyCode::Enter => {
                             self.buffer.insert_char(self.text_position, '\n');
                             self.text_position += 1;
+                            self.text_changed(self.text_position - 1);
                             editor.written = true;
                             if self.current_action.started {
                                 self.current_action.done = true;
@@ -430,76 +597,26 @@ impl ProcessEvent for Panel {
                                 self.buffer
                                     .remove(self.text_position - 1..self.text_position);
                                 self.text_position -= 1;
+                                self.text_changed(self.text_position);
                                 editor.written = true;
                             }
                             return Some((self.update_cursor_position_and_view(), false));
                         }
                         _ => {}
                     },
-                    crossterm::event::KeyModifiers::CONTROL => match key_event.code {
-                        crossterm::event::KeyCode::Char(c) => match c {
-                            'e' => {
-                                if let Some(command_line) =
-                                    editor.get_widget_mut(WidgetType::CommandLine)
-                                {
-                                    command_line.set_focused(true);
-                                    // command_line.set_scroll_columns(0);
-                                    // command_line.set_buffer(Rope::from_str(""));
-                                    // command_line.set_text_position(0);
-                                    let cursor_position =
-                                        command_line.update_cursor_position_and_view();
-                                    editor.focused_widget_id = command_line.get_id();
-                                    return Some((cursor_position, false));
-                                }
-                            }
-                            's' => {
-                                let saved_path = editor.save_path.clone();
-                                if let Some(command_line) =
-                                    editor.get_widget_mut(WidgetType::CommandLine)
-                                {
-                                    command_line.set_focused(true);
-                                    command_line.set_scroll_columns(0);
-                                    let save = format!(":save {}", saved_path.to_str().unwrap());
-                                    command_line.set_buffer(Rope::from_str(save.as_str()));
-                                    command_line.set_text_position(save.len());
-                                    let cursor_position =
-                                        command_line.update_cursor_position_and_view();
-                                    editor.focused_widget_id = command_line.get_id();
-                                    return Some((cursor_position, false));
-                                }
-                            }
-                            'f' => {
-                                if let Some(command_line) =
-                                    editor.get_widget_mut(WidgetType::CommandLine)
-                                {
-                                    command_line.set_focused(true);
-                                    command_line.set_scroll_columns(0);
-                                    let find = ":find ";
-                                    command_line.set_buffer(Rope::from_str(find));
-                                    command_line.set_text_position(find.len());
-                                    let cursor_position =
-                                        command_line.update_cursor_position_and_view();
-                                    editor.focused_widget_id = command_line.get_id();
-                                    return Some((cursor_position, false));
-                                }
-                            }
-                            _ => {}
-                        },
-                        _ => {}
-                    },
                     _ => {}
                 }
             }
             if let Event::Mouse(mouse_event) = event {
                 if mouse_event.kind == crossterm::event::MouseEventKind::ScrollDown {
                     if self.scroll_lines < self.buffer.len_lines() {
-                        self.scroll_lines += 1;
+                        self.scroll_lines = self.folds.next_line(self.scroll_lines);
                         // return Some((self.update_cursor_position_and_view(), false));
                     }
                 }
                 if mouse_event.kind == crossterm::event::MouseEventKind::ScrollUp {
                     if self.scroll_lines > 0 {
-                        self.scroll_lines -= 1;
+                        self.scroll_lines = self.folds.previous_line(self.scroll_lines);
                         // return Some((self.update_cursor_position_and_view(), false));
                     }
                 }
@@ -239,7 +239,7 @@ impl ProcessEvent for Popup {
                         return Some((pos, true));
                     }
                     crossterm::event::KeyCode::Tab => {
-                        eprintln!("popup tab");
+                        log::trace!("popup tab");
                         let panel = editor.get_widget_mut(WidgetType::CommandLine).unwrap();
                         let pos = panel.update_cursor_position_and_view();
                         panel.set_focused(true);
@@ -0,0 +1,560 @@
+use std::path::Path;
+
+use crossterm::{
+    event::{Event, KeyCode, KeyModifiers},
+    style::Color,
+};
+use ropey::Rope;
+
+use crate::{
+    completion::{self, Completer, Completion},
+    editor::TextEditor,
+    history::{self, History},
+};
+
+use super::widget::{
+    BorderStyle, ColorText, CursorPosition, CursorPositionByte, ProcessEvent, ShouldExit,
+    WidgetType,
+};
+
+/// An answer to a prompt: the key choosing it and the command lines it runs
+pub struct Choice {
+    pub key: char,
+    pub commands: Vec<String>,
+}
+
+impl Choice {
+    pub fn new(key: char, commands: &[&str]) -> Self {
+        Self {
+            key,
+            commands: commands.iter().map(|command| command.to_string()).collect(),
+        }
+    }
+}
+
+/// Checks the text of an input before it is accepted, the error is shown and
+/// the prompt stays open
+pub type Validator = fn(editor: &TextEditor, text: &str) -> Result<(), String>;
+
+/// A line typed in a prompt, Enter runs `command` with `{}` replaced by the text,
+/// quoted as a single argument
+pub struct Input {
+    command: String,
+    text: String,
+    /// char index of the cursor in `text`
+    cursor: usize,
+    completer: Option<Completer>,
+    completion: Option<Completion>,
+    validator: Option<Validator>,
+    history: Option<History>,
+}
+
+impl Input {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+            text: String::new(),
+            cursor: 0,
+            completer: None,
+            completion: None,
+            validator: None,
+            history: None,
+        }
+    }
+
+    /// Tab completes the whole text with the candidates of `completer`
+    pub fn with_completer(mut self, completer: Completer) -> Self {
+        self.completer = Some(completer);
+        self
+    }
+
+    pub fn with_validator(mut self, validator: Validator) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+
+    /// Up and Down go through the texts accepted before, kept in the history
+    /// directory `dir` as `file_name`
+    pub fn with_history(mut self, dir: Option<&Path>, file_name: &str) -> Self {
+        self.history = Some(history::load_history(dir, file_name));
+        self
+    }
+
+    fn byte_idx(&self, cursor: usize) -> usize {
+        self.text
+            .char_indices()
+            .nth(cursor)
+            .map(|(idx, _)| idx)
+            .unwrap_or(self.text.len())
+    }
+
+    fn set_text(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.cursor = text.chars().count();
+    }
+
+    /// Tab fills in the common prefix of the candidates, then cycles through them
+    fn complete(&mut self, editor: &TextEditor, forward: bool) {
+        if let Some(completion) = self.completion.as_mut() {
+            let candidate = if forward {
+                completion.next().to_string()
+            } else {
+                completion.prev().to_string()
+            };
+            self.set_text(&candidate);
+            return;
+        }
+        let Some(completer) = self.completer else {
+            return;
+        };
+        let completion = Completion::new(0, completer(editor, &self.text));
+        match completion.candidates.len() {
+            0 => {}
+            1 => self.set_text(&completion.candidates[0]),
+            _ => {
+                let prefix = completion::common_prefix(&completion.candidates);
+                if prefix.chars().count() > self.text.chars().count() {
+                    self.set_text(&prefix);
+                }
+                self.completion = Some(completion);
+            }
+        }
+    }
+
+    /// Edits the text with `code`, returns whether it was an editing key
+    fn edit(&mut self, editor: &TextEditor, code: KeyCode) -> bool {
+        if !matches!(code, KeyCode::Tab | KeyCode::BackTab) {
+            self.completion = None;
+        }
+        if !matches!(code, KeyCode::Up | KeyCode::Down) {
+            if let Some(history) = self.history.as_mut() {
+                history.reset_navigation();
+            }
+        }
+        let len = self.text.chars().count();
+        match code {
+            KeyCode::Char(c) => {
+                let idx = self.byte_idx(self.cursor);
+                self.text.insert(idx, c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                let idx = self.byte_idx(self.cursor);
+                self.text.remove(idx);
+            }
+            KeyCode::Delete if self.cursor < len => {
+                let idx = self.byte_idx(self.cursor);
+                self.text.remove(idx);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(len),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = len,
+            KeyCode::Tab => self.complete(editor, true),
+            KeyCode::BackTab => self.complete(editor, false),
+            KeyCode::Up => {
+                let entry = self
+                    .history
+                    .as_mut()
+                    .and_then(|history| history.prev(&self.text));
+                if let Some(entry) = entry {
+                    self.set_text(&entry);
+                }
+            }
+            KeyCode::Down => {
+                let entry = self.history.as_mut().and_then(History::next);
+                if let Some(entry) = entry {
+                    self.set_text(&entry);
+                }
+            }
+            KeyCode::Backspace | KeyCode::Delete => {}
+            _ => return false,
+        }
+        true
+    }
+
+    /// Checks the text, keeps it in the history and gives the command line to run
+    fn accept(&mut self, editor: &TextEditor) -> Result<String, String> {
+        if let Some(validator) = self.validator {
+            validator(editor, &self.text)?;
+        }
+        if let Some(history) = self.history.as_mut() {
+            if !self.text.is_empty() {
+                history.push(&self.text);
+            }
+        }
+        Ok(self.command.replace("{}", &quote(&self.text)))
+    }
+}
+
+/// `text` as one argument of a command line
+pub fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// How a prompt is answered
+pub enum Answer {
+    /// a key among the choices
+    Choices(Vec<Choice>),
+    /// a line of text
+    Input(Input),
+}
+
+/// Asks a question on the command line row, answered with a key or a line of
+/// text, and runs the command lines of the answer, Esc cancels
+///
+/// Nothing else gets the keys until it is answered, like the palette. Either
+/// way the focus goes back to the widget focused before.
+pub struct Prompt {
+    pub typ: WidgetType,
+    pub id: usize,
+    /// the text
+    pub buffer: Rope,
+    pub colors: Vec<Vec<ColorText>>,
+
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+
+    /// the color
+    pub default_fg: Color,
+    pub default_bg: Color,
+
+    pub focused: bool,
+    pub targetable: bool,
+
+    /// scolled lines
+    pub scroll_lines: usize,
+
+    /// scrolled columns
+    pub scroll_columns: usize,
+
+    pub boder_style: BorderStyle,
+    pub text_position: CursorPositionByte,
+
+    pub z_index: usize,
+
+    label: String,
+    answer: Answer,
+
+    /// widget focused, and its cursor, before the prompt opened
+    return_focus: usize,
+    return_cursor: CursorPosition,
+}
+
+impl Prompt {
+    /// `label` lists the keys of choices, like `Save changes? [y]es/[n]o/[c]ancel`,
+    /// the text of an input is typed after it
+    pub fn new(
+        editor: &TextEditor,
+        label: &str,
+        answer: Answer,
+        return_focus: usize,
+        return_cursor: CursorPosition,
+    ) -> Box<Self> {
+        let mut prompt = Box::new(Self {
+            x: 0,
+            y: editor.height.saturating_sub(1),
+            width: editor.width,
+            height: 1,
+            default_fg: Color::Black,
+            default_bg: Color::Yellow,
+            focused: true,
+            targetable: true,
+            z_index: 20,
+            label: label.to_string(),
+            answer,
+            return_focus,
+            return_cursor,
+            ..Default::default()
+        });
+        prompt.update_line();
+        prompt
+    }
+
+    fn update_line(&mut self) {
+        let (text, cursor) = match &self.answer {
+            Answer::Choices(_) => ("", 0),
+            Answer::Input(input) => (input.text.as_str(), input.cursor),
+        };
+        self.buffer = Rope::from_str(&format!("{} {}", self.label, text));
+        self.text_position = self.label.chars().count() + 1 + cursor;
+    }
+
+    /// Gives the focus back to the widget focused before the prompt opened
+    fn close(&mut self, editor: &mut TextEditor) -> Option<(CursorPosition, ShouldExit)> {
+        editor.focused_widget_id = self.return_focus;
+        // Asked by a `:` command, the command line is done with its line
+        let from_command_line = editor
+            .focused_widget_mut()
+            .is_some_and(|widget| widget.get_type() == WidgetType::CommandLine);
+        if from_command_line {
+            if let Some(panel) = editor.get_widget(WidgetType::Panel) {
+                editor.focused_widget_id = panel.get_id();
+            }
+        }
+        let mut cursor = self.return_cursor;
+        if let Some(widget) = editor.focused_widget_mut() {
+            widget.set_focused(true);
+            if from_command_line {
+                cursor = widget.update_cursor_position_and_view();
+            }
+        }
+        Some((cursor, true))
+    }
+}
+
+impl Default for Prompt {
+    fn default() -> Self {
+        // Return a new Widget with default values here
+        Self {
+            typ: WidgetType::Prompt,
+            id: 0,
+            buffer: Rope::from_str(""),
+            scroll_lines: 0,
+            scroll_columns: 0,
+            default_fg: Color::White,
+            default_bg: Color::Black,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            focused: false,
+            targetable: false,
+            boder_style: BorderStyle::None,
+            text_position: 0,
+            z_index: 0,
+            colors: Vec::new(),
+            label: String::new(),
+            answer: Answer::Choices(Vec::new()),
+            return_focus: 0,
+            return_cursor: (0, 0),
+        }
+    }
+}
+
+impl ProcessEvent for Prompt {
+    fn get_border_style(&self) -> BorderStyle {
+        self.boder_style
+    }
+    fn get_buffer(&self) -> &Rope {
+        &self.buffer
+    }
+    fn get_height(&self) -> usize {
+        self.height
+    }
+    fn get_width(&self) -> usize {
+        self.width
+    }
+    fn get_x(&self) -> usize {
+        self.x
+    }
+    fn get_y(&self) -> usize {
+        self.y
+    }
+    fn get_scroll_lines(&self) -> usize {
+        self.scroll_lines
+    }
+    fn get_scroll_columns(&self) -> usize {
+        self.scroll_columns
+    }
+    fn get_default_fg(&self) -> Color {
+        self.default_fg
+    }
+    fn get_default_bg(&self) -> Color {
+        self.default_bg
+    }
+    fn get_text_position(&self) -> CursorPositionByte {
+        self.text_position
+    }
+    fn get_focused(&self) -> bool {
+        self.focused
+    }
+    fn get_targetable(&self) -> bool {
+        self.targetable
+    }
+    fn get_type(&self) -> WidgetType {
+        WidgetType::Prompt
+    }
+    fn get_id(&self) -> usize {
+        self.id
+    }
+    fn get_z_idx(&self) -> usize {
+        self.z_index
+    }
+
+    fn get_colors(&self) -> Vec<Vec<ColorText>> {
+        self.colors.clone()
+    }
+    fn get_colors_mut(&mut self) -> &mut Vec<Vec<ColorText>> {
+        &mut self.colors
+    }
+    fn set_colors(&mut self, colors: Vec<Vec<ColorText>>) {
+        self.colors = colors;
+    }
+
+    fn set_border_style(&mut self, border_style: BorderStyle) {
+        self.boder_style = border_style;
+    }
+    fn set_buffer(&mut self, buffer: Rope) {
+        self.buffer = buffer;
+    }
+    fn set_height(&mut self, height: usize) {
+        self.height = height;
+    }
+    fn set_width(&mut self, width: usize) {
+        self.width = width;
+    }
+    fn set_x(&mut self, x: usize) {
+        self.x = x;
+    }
+    fn set_y(&mut self, y: usize) {
+        self.y = y;
+    }
+    fn set_scroll_lines(&mut self, scroll_lines: usize) {
+        self.scroll_lines = scroll_lines;
+    }
+    fn set_scroll_columns(&mut self, scroll_columns: usize) {
+        self.scroll_columns = scroll_columns;
+    }
+    fn set_default_fg(&mut self, default_fg: Color) {
+        self.default_fg = default_fg;
+    }
+    fn set_default_bg(&mut self, default_bg: Color) {
+        self.default_bg = default_bg;
+    }
+    fn set_text_position(&mut self, text_position: CursorPositionByte) {
+        self.text_position = text_position;
+    }
+    fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+    fn set_targetable(&mut self, targetable: bool) {
+        self.targetable = targetable;
+    }
+    fn set_type(&mut self, id: WidgetType) {
+        self.typ = id;
+    }
+    fn set_id(&mut self, id: usize) {
+        self.id = id;
+    }
+    fn set_z_idx(&mut self, z_idx: usize) {
+        self.z_index = z_idx;
+    }
+
+    fn event(
+        &mut self,
+        editor: &mut TextEditor,
+        event: &Event,
+    ) -> Option<(CursorPosition, ShouldExit)> {
+        if !self.focused {
+            return None;
+        }
+        if let Event::Key(key) = event {
+            if key.code == KeyCode::Esc {
+                return self.close(editor);
+            }
+            match &mut self.answer {
+                Answer::Choices(choices) => {
+                    if let KeyCode::Char(c) = key.code {
+                        let c = c.to_ascii_lowercase();
+                        if let Some(choice) = choices.iter().find(|choice| choice.key == c) {
+                            for command in &choice.commands {
+                                editor.queue_command(command);
+                            }
+                            return self.close(editor);
+                        }
+                    }
+                }
+                Answer::Input(input) => {
+                    if key.code == KeyCode::Enter {
+                        match input.accept(editor) {
+                            Ok(command) => {
+                                editor.queue_command(&command);
+                                return self.close(editor);
+                            }
+                            Err(e) => editor.error(e),
+                        }
+                    } else if !key.modifiers.contains(KeyModifiers::CONTROL)
+                        && input.edit(editor, key.code)
+                    {
+                        self.update_line();
+                    }
+                }
+            }
+            // Keys never reach the keybindings while the prompt is open
+            return Some((self.update_cursor_position_and_view(), false));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crossterm::event::KeyCode;
+
+    use crate::harness::{Harness, TestDir};
+
+    /// A harness asking whether to save its changes before quitting
+    fn asking(dir: &TestDir) -> Harness {
+        let mut harness = Harness::new("text\n", &dir.0.join("file.txt"), 80, 10);
+        harness.type_text("new ");
+        harness.command(":q");
+        assert!(harness.screen.lines()[9].starts_with("Save changes to"));
+        harness
+    }
+
+    #[test]
+    fn answers_yes_or_no() {
+        let dir = TestDir::new("prompt-yes-no");
+        let mut harness = asking(&dir);
+        // Other keys are not answers and do not edit
+        harness.type_text("x");
+        assert!(harness.editor.running);
+        assert_eq!(harness.text(), "new text\n");
+        harness.type_text("y");
+        assert!(!harness.editor.running);
+        assert_eq!(
+            fs::read_to_string(dir.0.join("file.txt")).unwrap(),
+            "new text\n"
+        );
+
+        let dir = TestDir::new("prompt-no");
+        let mut harness = asking(&dir);
+        // The keys of the choices ignore case
+        harness.type_text("N");
+        assert!(!harness.editor.running);
+        assert!(!dir.0.join("file.txt").exists());
+    }
+
+    #[test]
+    fn cancels() {
+        let dir = TestDir::new("prompt-cancel");
+        let mut harness = asking(&dir);
+        harness.type_text("c");
+        assert!(harness.editor.running);
+        assert!(!harness.screen.lines()[9].starts_with("Save changes"));
+        // The panel has the keys back
+        harness.type_text("!");
+        assert_eq!(harness.text(), "new !text\n");
+
+        harness.command(":q");
+        harness.key(KeyCode::Esc);
+        assert!(harness.editor.running);
+        harness.type_text("?");
+        assert_eq!(harness.text(), "new !?text\n");
+
+        // An input is cancelled the same way
+        let mut harness = Harness::new("text\n", "".as_ref(), 80, 10);
+        harness.command(":w");
+        assert!(harness.screen.lines()[9].starts_with("Save a copy as:"));
+        harness.type_text("copy.txt");
+        harness.key(KeyCode::Esc);
+        harness.type_text("!");
+        assert_eq!(harness.text(), "!text\n");
+    }
+}
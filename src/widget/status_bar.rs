@@ -262,8 +262,7 @@ impl ProcessEvent for StatusBar {
             let current = self.get_buffer().to_string();
             let parts = current.split(' ').collect::<Vec<&str>>();
             let mut file_info = parts[0].to_string();
-            let last_char = file_info.chars().last().unwrap();
-            if last_char != '*' && editor.written {
+            if !file_info.ends_with('*') && editor.written {
                 file_info.push('*');
             }
             if !editor.written {
@@ -291,6 +290,14 @@ impl ProcessEvent for StatusBar {
             }
             status_bar.push_str(&" ".repeat(TOTAL_POS_INFO_WIDTH - pos_info.len()));
 
+            let format = editor.file_format;
+            let large = if editor.large_file { " large" } else { "" };
+            let readonly = if editor.readonly { " [RO]" } else { "" };
+            status_bar.push_str(&format!(
+                "{} {}{}{}  ",
+                format.encoding, format.line_ending, large, readonly
+            ));
+
             // Get git branch
 
             match get_git_branch_name(Path::new(".")) {
@@ -302,6 +309,10 @@ impl ProcessEvent for StatusBar {
                 }
             }
 
+            if let Some(job) = editor.jobs.jobs.last() {
+                status_bar.push_str(&format!("  {} {}", editor.jobs.spinner(), job.command));
+            }
+
             self.set_buffer(ropey::Rope::from_str(&status_bar));
         }
         None
@@ -0,0 +1,486 @@
+use std::{
+    io,
+    sync::mpsc::{Receiver, TryRecvError},
+};
+
+use crossterm::{
+    event::{Event, KeyCode, KeyEvent, KeyModifiers},
+    style::{Attribute, Color, ContentStyle, Stylize},
+};
+use ropey::Rope;
+
+use crate::{backend::Backend, editor::TextEditor, pty::Pty, vt::Screen};
+
+use super::widget::{
+    BorderStyle, ColorText, CursorPosition, CursorPositionByte, ProcessEvent, RenderContext,
+    ShouldExit, WidgetType,
+};
+
+/// Where the terminal pane goes on the screen
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Placement {
+    /// under the panel, across the whole width
+    Bottom,
+    /// on the right half, next to the panel
+    Split,
+}
+
+impl Placement {
+    pub const NAMES: &'static [&'static str] = &["bottom", "split"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bottom" => Some(Placement::Bottom),
+            "split" => Some(Placement::Split),
+            _ => None,
+        }
+    }
+}
+
+/// Bytes a terminal sends for a key
+fn key_bytes(key: &KeyEvent) -> Vec<u8> {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    let mut bytes = match key.code {
+        KeyCode::Char(c) if ctrl && c.is_ascii_alphabetic() => {
+            vec![c.to_ascii_lowercase() as u8 & 0x1f]
+        }
+        KeyCode::Char(' ') if ctrl => vec![0],
+        KeyCode::Char(c) => c.to_string().into_bytes(),
+        KeyCode::Enter => vec![b'\r'],
+        KeyCode::Backspace => vec![0x7f],
+        KeyCode::Tab => vec![b'\t'],
+        KeyCode::BackTab => b"\x1b[Z".to_vec(),
+        KeyCode::Esc => vec![0x1b],
+        KeyCode::Up => b"\x1b[A".to_vec(),
+        KeyCode::Down => b"\x1b[B".to_vec(),
+        KeyCode::Right => b"\x1b[C".to_vec(),
+        KeyCode::Left => b"\x1b[D".to_vec(),
+        KeyCode::Home => b"\x1b[H".to_vec(),
+        KeyCode::End => b"\x1b[F".to_vec(),
+        KeyCode::Insert => b"\x1b[2~".to_vec(),
+        KeyCode::Delete => b"\x1b[3~".to_vec(),
+        KeyCode::PageUp => b"\x1b[5~".to_vec(),
+        KeyCode::PageDown => b"\x1b[6~".to_vec(),
+        KeyCode::F(n @ 1..=4) => vec![0x1b, b'O', b'P' + n - 1],
+        KeyCode::F(n) => {
+            let code = match n {
+                5 => 15,
+                6..=10 => n + 11,
+                _ => n + 12,
+            };
+            format!("\x1b[{}~", code).into_bytes()
+        }
+        _ => vec![],
+    };
+    // Alt sends an escape before the key
+    if key.modifiers.contains(KeyModifiers::ALT) && !bytes.is_empty() {
+        bytes.insert(0, 0x1b);
+    }
+    bytes
+}
+
+/// A shell running on a pty, drawn with the colors it asked for
+///
+/// The first row is a title bar, the screen of the shell is below it. Lines
+/// scrolled off the screen stay in the buffer.
+pub struct TerminalPane {
+    pub typ: WidgetType,
+    pub id: usize,
+    pub colors: Vec<Vec<ColorText>>,
+
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+
+    /// the color
+    pub default_fg: Color,
+    pub default_bg: Color,
+
+    pub focused: bool,
+    pub targetable: bool,
+
+    /// lines scrolled back in the scrollback, 0 shows the live screen
+    pub scroll_lines: usize,
+
+    /// scrolled columns
+    pub scroll_columns: usize,
+
+    pub boder_style: BorderStyle,
+    pub text_position: CursorPositionByte,
+
+    pub z_index: usize,
+
+    /// the program started in the pty
+    pub command: String,
+    pub screen: Screen,
+    pty: Option<Pty>,
+    output: Option<Receiver<Vec<u8>>>,
+    /// the program exited, the pane asks to be closed
+    pub exited: bool,
+}
+
+impl TerminalPane {
+    pub fn new(
+        command: String,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        fg: Color,
+        bg: Color,
+        focused: bool,
+        targetable: bool,
+        boder_style: BorderStyle,
+    ) -> Box<Self> {
+        Box::new(Self {
+            command,
+            screen: Screen::new(width, height.saturating_sub(1)),
+            x,
+            y,
+            width,
+            height,
+            default_fg: fg,
+            default_bg: bg,
+            focused,
+            targetable,
+            boder_style,
+            ..Default::default()
+        })
+    }
+
+    /// Spawns the command on a pty the size of the pane
+    pub fn start(&mut self) -> io::Result<()> {
+        let (pty, output) = Pty::spawn(&self.command, self.screen.width, self.screen.height)?;
+        self.pty = Some(pty);
+        self.output = Some(output);
+        self.exited = false;
+        Ok(())
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        if let Some(pty) = &mut self.pty {
+            if let Err(e) = pty.write(bytes) {
+                log::warn!("Failed to write to the terminal: {:?}", e);
+            }
+        }
+    }
+
+    /// Resizes the screen and the pty to the pane, returns whether it changed
+    fn fit(&mut self) -> bool {
+        let height = self.height.saturating_sub(1).max(1);
+        if self.screen.width == self.width && self.screen.height == height {
+            return false;
+        }
+        self.screen.resize(self.width, height);
+        if let Some(pty) = &self.pty {
+            let _ = pty.resize(self.screen.width, self.screen.height);
+        }
+        true
+    }
+
+    fn style(&self, fg: Color, bg: Color) -> ContentStyle {
+        let fg = if fg == Color::Reset {
+            self.default_fg
+        } else {
+            fg
+        };
+        let bg = if bg == Color::Reset {
+            self.default_bg
+        } else {
+            bg
+        };
+        ContentStyle::new().with(fg).on(bg)
+    }
+
+    fn render_title(&self, out: &mut dyn Backend) {
+        let mut title = format!(" {}", self.command);
+        if self.exited {
+            title.push_str(" [exited]");
+        } else if self.scroll_lines > 0 {
+            title.push_str(&format!(" [-{}]", self.scroll_lines));
+        }
+        let title: String = title.chars().take(self.width).collect();
+        let padding = self.width - title.chars().count();
+        let style = self
+            .style(Color::Reset, Color::Reset)
+            .attribute(Attribute::Reverse);
+        out.move_to(self.x as u16, self.y as u16);
+        out.print(&(title + &" ".repeat(padding)), style);
+    }
+
+    /// Prints a screen row, one styled run per change of style
+    fn render_row(&self, out: &mut dyn Backend, row: usize) {
+        let mut x = 0;
+        while x < self.screen.width {
+            let cell = self.screen.cell(x, row);
+            let mut text = String::new();
+            while x < self.screen.width && self.screen.cell(x, row).same_style(&cell) {
+                text.push(self.screen.cell(x, row).ch);
+                x += 1;
+            }
+            let mut style = self.style(cell.fg, cell.bg);
+            if cell.bold {
+                style = style.attribute(Attribute::Bold);
+            }
+            if cell.underline {
+                style = style.attribute(Attribute::Underlined);
+            }
+            if cell.inverse {
+                style = style.attribute(Attribute::Reverse);
+            }
+            out.print(&text, style);
+        }
+    }
+}
+
+impl Default for TerminalPane {
+    fn default() -> Self {
+        // Return a new Widget with default values here
+        Self {
+            typ: WidgetType::Terminal,
+            id: 0,
+            scroll_lines: 0,
+            scroll_columns: 0,
+            default_fg: Color::White,
+            default_bg: Color::Black,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            focused: false,
+            targetable: false,
+            boder_style: BorderStyle::None,
+            text_position: 0,
+            z_index: 0,
+            colors: Vec::new(),
+            command: String::new(),
+            screen: Screen::new(1, 1),
+            pty: None,
+            output: None,
+            exited: false,
+        }
+    }
+}
+
+impl ProcessEvent for TerminalPane {
+    fn get_border_style(&self) -> BorderStyle {
+        self.boder_style
+    }
+    fn get_buffer(&self) -> &Rope {
+        &self.screen.scrollback
+    }
+    fn get_height(&self) -> usize {
+        self.height
+    }
+    fn get_width(&self) -> usize {
+        self.width
+    }
+    fn get_x(&self) -> usize {
+        self.x
+    }
+    fn get_y(&self) -> usize {
+        self.y
+    }
+    fn get_scroll_lines(&self) -> usize {
+        self.scroll_lines
+    }
+    fn get_scroll_columns(&self) -> usize {
+        self.scroll_columns
+    }
+    fn get_default_fg(&self) -> Color {
+        self.default_fg
+    }
+    fn get_default_bg(&self) -> Color {
+        self.default_bg
+    }
+    fn get_text_position(&self) -> CursorPositionByte {
+        self.text_position
+    }
+    fn get_focused(&self) -> bool {
+        self.focused
+    }
+    fn get_targetable(&self) -> bool {
+        self.targetable
+    }
+    fn get_type(&self) -> WidgetType {
+        WidgetType::Terminal
+    }
+    fn get_id(&self) -> usize {
+        self.id
+    }
+    fn get_z_idx(&self) -> usize {
+        self.z_index
+    }
+
+    fn get_colors(&self) -> Vec<Vec<ColorText>> {
+        self.colors.clone()
+    }
+    fn get_colors_mut(&mut self) -> &mut Vec<Vec<ColorText>> {
+        &mut self.colors
+    }
+    fn set_colors(&mut self, colors: Vec<Vec<ColorText>>) {
+        self.colors = colors;
+    }
+
+    fn set_border_style(&mut self, border_style: BorderStyle) {
+        self.boder_style = border_style;
+    }
+    fn set_buffer(&mut self, buffer: Rope) {
+        self.screen.scrollback = buffer;
+    }
+    fn set_height(&mut self, height: usize) {
+        self.height = height;
+    }
+    fn set_width(&mut self, width: usize) {
+        self.width = width;
+    }
+    fn set_x(&mut self, x: usize) {
+        self.x = x;
+    }
+    fn set_y(&mut self, y: usize) {
+        self.y = y;
+    }
+    fn set_scroll_lines(&mut self, scroll_lines: usize) {
+        self.scroll_lines = scroll_lines.min(self.screen.scrollback_lines());
+    }
+    fn set_scroll_columns(&mut self, scroll_columns: usize) {
+        self.scroll_columns = scroll_columns;
+    }
+    fn set_default_fg(&mut self, default_fg: Color) {
+        self.default_fg = default_fg;
+    }
+    fn set_default_bg(&mut self, default_bg: Color) {
+        self.default_bg = default_bg;
+    }
+    fn set_text_position(&mut self, text_position: CursorPositionByte) {
+        self.text_position = text_position;
+    }
+    fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+    fn set_targetable(&mut self, targetable: bool) {
+        self.targetable = targetable;
+    }
+    fn set_type(&mut self, id: WidgetType) {
+        self.typ = id;
+    }
+    fn set_id(&mut self, id: usize) {
+        self.id = id;
+    }
+    fn set_z_idx(&mut self, z_idx: usize) {
+        self.z_index = z_idx;
+    }
+
+    fn tick(&mut self) -> bool {
+        let mut changed = self.fit();
+        if let Some(output) = &self.output {
+            loop {
+                match output.try_recv() {
+                    Ok(bytes) => {
+                        self.screen.feed(&bytes);
+                        changed = true;
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        self.output = None;
+                        self.pty = None;
+                        self.exited = true;
+                        changed = true;
+                        break;
+                    }
+                }
+            }
+        }
+        if !self.screen.responses.is_empty() {
+            let responses = std::mem::take(&mut self.screen.responses);
+            self.write(&responses);
+        }
+        changed
+    }
+
+    fn is_closed(&self) -> bool {
+        self.exited
+    }
+
+    fn event(
+        &mut self,
+        editor: &mut TextEditor,
+        event: &Event,
+    ) -> Option<(CursorPosition, ShouldExit)> {
+        if !self.focused || self.exited {
+            return None;
+        }
+        match event {
+            Event::Key(key) => {
+                // Keys bound to the terminal commands still reach them, to leave the pane
+                let binding = editor.keymap.get(key);
+                if binding.is_some_and(|command_line| command_line.starts_with(":terminal")) {
+                    return None;
+                }
+                match (key.code, key.modifiers) {
+                    (KeyCode::PageUp, KeyModifiers::SHIFT) => {
+                        self.set_scroll_lines(self.scroll_lines + self.screen.height / 2);
+                    }
+                    (KeyCode::PageDown, KeyModifiers::SHIFT) => {
+                        self.scroll_lines =
+                            self.scroll_lines.saturating_sub(self.screen.height / 2);
+                    }
+                    _ => {
+                        self.scroll_lines = 0;
+                        self.write(&key_bytes(key));
+                    }
+                }
+            }
+            Event::Paste(text) => {
+                self.scroll_lines = 0;
+                self.write(text.as_bytes());
+            }
+            _ => return None,
+        }
+        Some((self.get_cursor_view(), false))
+    }
+
+    fn render(&self, ctx: &mut RenderContext) {
+        let out: &mut dyn Backend = ctx.out;
+        self.render_title(out);
+        let scrollback_lines = self.screen.scrollback_lines();
+        let first = scrollback_lines - self.scroll_lines;
+        for row in 0..self.screen.height {
+            let y = self.y + 1 + row;
+            out.move_to(self.x as u16, y as u16);
+            let line = first + row;
+            if line >= scrollback_lines {
+                self.render_row(out, line - scrollback_lines);
+                continue;
+            }
+            let text: String = self
+                .screen
+                .scrollback
+                .line(line)
+                .chars()
+                .filter(|c| *c != '\n')
+                .take(self.screen.width)
+                .collect();
+            let padding = self.screen.width - text.chars().count();
+            let style = self.style(Color::Reset, Color::Reset);
+            out.print(&(text + &" ".repeat(padding)), style);
+        }
+    }
+
+    fn update_cursor_position_and_view(&mut self) -> CursorPosition {
+        self.fit();
+        self.get_cursor_view()
+    }
+
+    fn get_cursor_view(&self) -> CursorPosition {
+        (
+            (self.x + self.screen.cursor_x) as i32,
+            (self.y + 1 + self.screen.cursor_y) as i32,
+        )
+    }
+
+    fn is_cursor_visible(&self) -> bool {
+        self.screen.cursor_visible && self.scroll_lines == 0 && !self.exited
+    }
+}
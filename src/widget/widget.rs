@@ -1,23 +1,20 @@
-use std::io::stdout;
-
 use crossterm::{
-    cursor::{self},
     event::Event,
-    queue,
-    style::{self, Color, ContentStyle, StyledContent, Stylize},
+    style::{Color, ContentStyle, Stylize},
 };
 
 use ropey::{Rope, RopeSlice};
-use syntect::{
-    easy::HighlightLines,
-    highlighting::{Style, ThemeSet},
-    parsing::{SyntaxReference, SyntaxSet},
-};
+use syntect::{easy::HighlightLines, highlighting::Style, parsing::SyntaxReference};
 
-use super::super::editor::TextEditor;
+use super::super::{
+    backend::Backend, editor::TextEditor, fold::Folds, git::Hunks, highlight::Highlighter,
+};
 
-pub static mut PS: Option<SyntaxSet> = None;
-pub static mut TS: Option<ThemeSet> = None;
+/// What widgets are drawn with
+pub struct RenderContext<'a> {
+    pub out: &'a mut dyn Backend,
+    pub highlighter: &'a Highlighter,
+}
 
 pub type ShouldExit = bool;
 pub type CursorPosition = (i32, i32);
@@ -29,6 +26,8 @@ pub enum ColorTextTag {
     Cursor,
     Selection,
     Find,
+    /// text selected with shift and the arrows
+    TextSelection,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -69,6 +68,9 @@ pub enum WidgetType {
     StatusBar,
     LineNumber,
     CommandLine,
+    Palette,
+    Prompt,
+    Terminal,
     _WidgetCount,
 }
 
@@ -82,6 +84,9 @@ impl std::fmt::Display for WidgetType {
             WidgetType::StatusBar => write!(f, "StatusBar"),
             WidgetType::LineNumber => write!(f, "LineNumber"),
             WidgetType::CommandLine => write!(f, "CommandLine"),
+            WidgetType::Palette => write!(f, "Palette"),
+            WidgetType::Prompt => write!(f, "Prompt"),
+            WidgetType::Terminal => write!(f, "Terminal"),
             WidgetType::_WidgetCount => write!(f, "WidgetCount"),
         }
     }
@@ -174,6 +179,65 @@ pub trait ProcessEvent {
         }
     }
 
+    /// Where the selection started, the cursor is its other end
+    fn get_selection_anchor(&self) -> Option<CursorPositionByte> {
+        None
+    }
+    fn set_selection_anchor(&mut self, _anchor: Option<CursorPositionByte>) {}
+    /// Selected chars, from the start included to the end excluded
+    fn get_selection(&self) -> Option<(usize, usize)> {
+        let anchor = self.get_selection_anchor()?;
+        let cursor = self.get_text_position();
+        if anchor == cursor {
+            return None;
+        }
+        Some((anchor.min(cursor), anchor.max(cursor)))
+    }
+    /// First and last lines touched by the selection
+    fn get_selection_lines(&self) -> Option<(usize, usize)> {
+        let (start, end) = self.get_selection()?;
+        let buffer = self.get_buffer();
+        Some((buffer.char_to_line(start), buffer.char_to_line(end - 1)))
+    }
+
+    /// The folds of the text, widgets without any show every line
+    fn get_folds(&self) -> Option<&Folds> {
+        None
+    }
+    fn get_folds_mut(&mut self) -> Option<&mut Folds> {
+        None
+    }
+    /// The lines of the text changed since the last commit of its file
+    fn get_hunks(&self) -> Option<&Hunks> {
+        None
+    }
+    fn get_hunks_mut(&mut self) -> Option<&mut Hunks> {
+        None
+    }
+    /// The row of `line` counted from the first line, the lines hidden by closed
+    /// folds taking none
+    fn line_row(&self, line: usize) -> usize {
+        self.get_folds().map_or(line, |folds| folds.row(line))
+    }
+    /// The line shown on `row`, the reverse of `line_row`
+    fn row_line(&self, row: usize) -> usize {
+        self.get_folds().map_or(row, |folds| folds.line(row))
+    }
+
+    /// Replaces the chars `start..end` by `text` as one undoable action
+    fn replace_range(&mut self, _start: usize, _end: usize, _text: &str) {}
+
+    /// Work done between events, e.g. reading what a program wrote
+    ///
+    /// Returns whether the widget needs to be rendered again.
+    fn tick(&mut self) -> bool {
+        false
+    }
+    /// The widget asks to be removed, e.g. the program it showed exited
+    fn is_closed(&self) -> bool {
+        false
+    }
+
     fn undo(&mut self) -> Option<CursorPosition> {
         None
     }
@@ -181,97 +245,46 @@ pub trait ProcessEvent {
         None
     }
 
-    fn render_box(&self, chars: [&str; 6]) {
-        let mut stdout = stdout();
-        queue!(
-            stdout,
-            cursor::MoveTo(self.get_x() as u16, self.get_y() as u16)
-        )
-        .unwrap();
-        queue!(stdout, style::Print(chars[0])).unwrap();
-        queue!(
-            stdout,
-            cursor::MoveTo(
-                self.get_x() as u16 + self.get_width() as u16,
-                self.get_y() as u16
-            )
-        )
-        .unwrap();
-        queue!(stdout, style::Print(chars[1])).unwrap();
-        queue!(
-            stdout,
-            cursor::MoveTo(
-                self.get_x() as u16,
-                self.get_y() as u16 + self.get_height() as u16
-            )
-        )
-        .unwrap();
-        queue!(stdout, style::Print(chars[2])).unwrap();
-        queue!(
-            stdout,
-            cursor::MoveTo(
-                self.get_x() as u16 + self.get_width() as u16,
-                self.get_y() as u16 + self.get_height() as u16
-            )
-        )
-        .unwrap();
-        queue!(stdout, style::Print(chars[3])).unwrap();
-        for i in 1..self.get_width() {
-            queue!(
-                stdout,
-                cursor::MoveTo(self.get_x() as u16 + i as u16, self.get_y() as u16)
-            )
-            .unwrap();
-            queue!(stdout, style::Print(chars[4])).unwrap();
-            queue!(
-                stdout,
-                cursor::MoveTo(
-                    self.get_x() as u16 + i as u16,
-                    self.get_y() as u16 + self.get_height() as u16
-                )
-            )
-            .unwrap();
-            queue!(stdout, style::Print(chars[4])).unwrap();
+    fn render_box(&self, out: &mut dyn Backend, chars: [&str; 6]) {
+        let (x, y) = (self.get_x() as u16, self.get_y() as u16);
+        let (width, height) = (self.get_width() as u16, self.get_height() as u16);
+        let style = ContentStyle::default();
+        for (corner_x, corner_y, corner) in [
+            (x, y, chars[0]),
+            (x + width, y, chars[1]),
+            (x, y + height, chars[2]),
+            (x + width, y + height, chars[3]),
+        ] {
+            out.move_to(corner_x, corner_y);
+            out.print(corner, style);
+        }
+        for i in 1..width {
+            out.move_to(x + i, y);
+            out.print(chars[4], style);
+            out.move_to(x + i, y + height);
+            out.print(chars[4], style);
         }
-        for i in 1..self.get_height() {
-            queue!(
-                stdout,
-                cursor::MoveTo(self.get_x() as u16, self.get_y() as u16 + i as u16)
-            )
-            .unwrap();
-            queue!(stdout, style::Print(chars[5])).unwrap();
-            queue!(
-                stdout,
-                cursor::MoveTo(
-                    self.get_x() as u16 + self.get_width() as u16,
-                    self.get_y() as u16 + i as u16
-                )
-            )
-            .unwrap();
-            queue!(stdout, style::Print(chars[5])).unwrap();
+        for i in 1..height {
+            out.move_to(x, y + i);
+            out.print(chars[5], style);
+            out.move_to(x + width, y + i);
+            out.print(chars[5], style);
         }
     }
 
-    fn render(&self) {
-        let mut stdout = stdout();
-        queue!(
-            stdout,
-            cursor::MoveTo(self.get_x() as u16, self.get_y() as u16)
-        )
-        .unwrap();
+    fn render(&self, ctx: &mut RenderContext) {
+        let out: &mut dyn Backend = ctx.out;
+        out.move_to(self.get_x() as u16, self.get_y() as u16);
         let offset = self.get_offset();
         let height = self.get_height() - offset;
         let width = self.get_width() - offset;
         let x = self.get_x() + offset;
         let mut y = self.get_y() + offset;
 
-        let ps = unsafe { PS.as_ref().unwrap() };
-        let ts = unsafe { TS.as_ref().unwrap() };
-        let syntax = self.get_syntax().unwrap_or(&ps.find_syntax_plain_text());
-        let mut h = HighlightLines::new(
-            syntax,
-            &ts.themes[&self.get_theme().unwrap_or("base16-ocean.dark".to_string())],
-        );
+        let syntax = self.get_syntax().unwrap_or(ctx.highlighter.plain_text());
+        let ps = ctx.highlighter.set_of(syntax);
+        let theme = self.get_theme();
+        let mut h = HighlightLines::new(syntax, ctx.highlighter.theme(theme.as_deref()));
 
         let mut colors = self.get_colors();
         for color_line in &mut colors {
@@ -280,9 +293,12 @@ pub trait ProcessEvent {
         }
 
         let buffer = self.get_buffer();
-        let num_lines = buffer.lines().len();
-        let lines = buffer.lines().skip(self.get_scroll_lines()).take(height);
-        for line in lines {
+        let folds = self.get_folds();
+        // Jumping to the first visible line keeps scrolling instant in large files
+        let mut line_idx = self.row_line(self.line_row(self.get_scroll_lines()));
+        let mut line_rendered = 0;
+        while line_rendered < height && line_idx < buffer.len_lines() {
+            let line = buffer.line(line_idx);
             let mut line_to_display: String = line
                 .chars()
                 .skip(self.get_scroll_columns())
@@ -291,11 +307,18 @@ pub trait ProcessEvent {
             if line_to_display.ends_with('\n') {
                 line_to_display.pop();
             }
-            if line_to_display.len() < width {
-                line_to_display.push_str(&" ".repeat(width - line_to_display.len()));
+            // A closed fold shows its first line and how many it hides
+            let fold = folds.and_then(|folds| folds.closed_at(line_idx));
+            if let Some(fold) = fold {
+                line_to_display.push_str(&format!(" ⋯ {} lines", fold.end - fold.start));
+                line_to_display = line_to_display.chars().take(width).collect();
+            }
+            let displayed_len = line_to_display.chars().count();
+            if displayed_len < width {
+                line_to_display.push_str(&" ".repeat(width - displayed_len));
             }
 
-            let color_line = colors.get(y + self.get_scroll_lines() - offset);
+            let color_line = colors.get(self.get_y() + line_idx);
             let mut intermediate_color_line = Vec::<ColorText>::new();
             if color_line.unwrap_or(&Vec::<ColorText>::new()).len() > 0 {
                 let color_line = color_line.unwrap();
@@ -331,8 +354,8 @@ pub trait ProcessEvent {
                 }
             }
             let line = &line_to_display;
-            let ranges: Vec<(Style, &str)> = h.highlight_line(line, &ps).unwrap();
-            queue!(stdout, cursor::MoveTo(x as u16, y as u16)).unwrap();
+            let ranges: Vec<(Style, &str)> = h.highlight_line(line, ps).unwrap();
+            out.move_to(x as u16, y as u16);
             let mut total_read = 0;
             let mut next_color_idx = 0;
             for (style, mut text) in ranges {
@@ -362,8 +385,7 @@ pub trait ProcessEvent {
 
                     // eprintln!("text: {:?}", text);
                     let end_of_first_part = intermediate_color_line[next_color_idx].x - total_read;
-                    let text_to_print = StyledContent::new(def_style, &text[..end_of_first_part]);
-                    queue!(stdout, style::PrintStyledContent(text_to_print)).unwrap();
+                    out.print(&text[..end_of_first_part], def_style);
 
                     let mut end_of_second_part = intermediate_color_line[next_color_idx].x
                         - total_read
@@ -371,9 +393,7 @@ pub trait ProcessEvent {
                     if end_of_second_part > text.len() {
                         end_of_second_part = text.len();
                     }
-                    let text_to_print =
-                        StyledContent::new(style, &text[end_of_first_part..end_of_second_part]);
-                    queue!(stdout, style::PrintStyledContent(text_to_print)).unwrap();
+                    out.print(&text[end_of_first_part..end_of_second_part], style);
 
                     text = &text[end_of_second_part..];
                     // let text_to_print = StyledContent::new(def_style, &text[end_of_second_part..]);
@@ -381,7 +401,7 @@ pub trait ProcessEvent {
 
                     total_read += end_of_second_part;
                     if y == 0 {
-                        eprintln!(
+                        log::trace!(
                         "total_read: {}, next_color_idx: {}, intermediate_color_line.len(): {:?}, end_of_first_part: {}, end_of_second_part: {}, text.len(): {}",
                         total_read, next_color_idx, intermediate_color_line[next_color_idx], end_of_first_part, end_of_second_part, text.len()
                     );
@@ -407,78 +427,80 @@ pub trait ProcessEvent {
                     }),
                     ..ContentStyle::default()
                 };
-                let text_to_print = StyledContent::new(style, &text);
-                queue!(stdout, style::PrintStyledContent(text_to_print)).unwrap();
+                out.print(text, style);
                 total_read += text.len();
             }
             // }
             y += 1;
+            line_rendered += 1;
+            line_idx = fold.map_or(line_idx + 1, |fold| fold.end + 1);
         }
-        let line_rendered = if num_lines - self.get_scroll_lines() > height {
-            height
-        } else {
-            num_lines - self.get_scroll_lines()
-        };
         for i in 0..(height - line_rendered) {
-            queue!(stdout, cursor::MoveTo(x as u16, (y + i) as u16)).unwrap();
-            queue!(
-                stdout,
-                style::PrintStyledContent(
-                    " ".repeat(width)
-                        .with(self.get_default_fg())
-                        .on(self.get_default_bg())
-                )
-            )
-            .unwrap();
+            out.move_to(x as u16, (y + i) as u16);
+            out.print(
+                &" ".repeat(width),
+                ContentStyle::new()
+                    .with(self.get_default_fg())
+                    .on(self.get_default_bg()),
+            );
         }
 
         match self.get_border_style() {
             BorderStyle::None => {}
             BorderStyle::Solid => {
-                self.render_box(["┌", "┐", "└", "┘", "─", "│"]);
+                self.render_box(out, ["┌", "┐", "└", "┘", "─", "│"]);
             }
             BorderStyle::Dashed => {
-                self.render_box(["┌", "┐", "└", "┘", "┄", "┆"]);
+                self.render_box(out, ["┌", "┐", "└", "┘", "┄", "┆"]);
             }
         }
     }
 
     fn update_cursor_position_and_view(&mut self) -> CursorPosition {
         let offset = self.get_offset();
-        let mut y = self.get_buffer().byte_to_line(self.get_text_position());
-        let mut x = self.get_text_position() - self.get_buffer().line_to_byte(y);
+        let line = self.get_buffer().char_to_line(self.get_text_position());
+        let mut x = self.get_text_position() - self.get_buffer().line_to_char(line);
+        // The cursor is never in a closed fold
+        if let Some(folds) = self.get_folds_mut() {
+            folds.reveal(line);
+        }
+        let mut y = self.line_row(line);
+        let scroll_row = self.line_row(self.get_scroll_lines());
 
-        eprintln!(
+        log::trace!(
             "y: {}, x: {}, scroll_lines: {}, scroll_columns: {}",
             y,
             x,
             self.get_scroll_lines(),
             self.get_scroll_columns()
         );
-        if y < self.get_scroll_lines() {
-            self.set_scroll_lines(y);
+        if y < scroll_row {
+            self.set_scroll_lines(line);
         }
-        if y > self.get_scroll_lines() + (self.get_height() - 1) - offset - offset {
-            self.set_scroll_lines(y - (self.get_height() - 1 - offset));
+        // Compared without subtracting, small bordered widgets would underflow
+        if y + offset + offset + 1 > scroll_row + self.get_height() {
+            let scroll_row = (y + 1 + offset).saturating_sub(self.get_height());
+            self.set_scroll_lines(self.row_line(scroll_row));
         }
         if x < self.get_scroll_columns() {
             self.set_scroll_columns(x);
         }
-        if x > self.get_scroll_columns() + self.get_width() - offset - offset {
-            self.set_scroll_columns((x + offset) - self.get_width());
+        if x + offset + offset > self.get_scroll_columns() + self.get_width() {
+            self.set_scroll_columns((x + offset).saturating_sub(self.get_width()));
         }
         x += offset + self.get_x();
         y += offset + self.get_y();
-        y -= self.get_scroll_lines();
+        y -= self.line_row(self.get_scroll_lines());
         x -= self.get_scroll_columns();
         (x as i32, y as i32)
     }
 
     fn get_cursor_view(&self) -> CursorPosition {
         let offset = self.get_offset();
-        let y = self.get_buffer().byte_to_line(self.get_text_position());
-        let x = self.get_text_position() - self.get_buffer().line_to_byte(y);
-        let scroll_lines = self.get_scroll_lines();
+        let line = self.get_buffer().char_to_line(self.get_text_position());
+        let x = self.get_text_position() - self.get_buffer().line_to_char(line);
+        let y = self.line_row(line);
+        let scroll_lines = self.line_row(self.get_scroll_lines());
         let scroll_columns = self.get_scroll_columns();
         // if '\n' we need to go to the next line
 
@@ -501,9 +523,10 @@ pub trait ProcessEvent {
     }
 
     fn is_cursor_visible(&self) -> bool {
-        let y = self.get_buffer().byte_to_line(self.get_text_position());
-        let x = self.get_text_position() - self.get_buffer().line_to_byte(y);
-        let scroll_lines = self.get_scroll_lines();
+        let line = self.get_buffer().char_to_line(self.get_text_position());
+        let x = self.get_text_position() - self.get_buffer().line_to_char(line);
+        let y = self.line_row(line);
+        let scroll_lines = self.line_row(self.get_scroll_lines());
         let scroll_columns = self.get_scroll_columns();
 
         if y < scroll_lines {